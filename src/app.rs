@@ -1,4 +1,3 @@
-use ping;
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
@@ -7,47 +6,125 @@ use std::time::{Duration, Instant};
 
 use eframe::egui::{self, TextureHandle, Vec2};
 
+use crate::auto_select;
+use crate::benchmark::{self, ServerStats, SortColumn};
+use crate::compare::{self, ProviderAnswer};
+use crate::dns::{self, HttpsUrl, Upstream};
 use crate::domain::{
-    AppState, DnsOperation, DnsProvider, DnsState, OperationResult, SavedDnsEntry,
+    AdapterInfo, AdapterTarget, AppSettings, AppState, AutoSelectSettings, BlockResponse,
+    DnsOperation, DnsProvider, DnsSnapshot, DnsState, HealthCheckSettings, IpFamily,
+    OperationResult, RoutingRule, SavedDnsEntry, ServerList, UpstreamStrategy,
+};
+use crate::forwarder::{self, Blocklist, Forwarder, Group, QueryEvent, Routes, Timeouts};
+use crate::health;
+use crate::interception;
+use crate::reachability;
+use crate::storage::{
+    add_saved_dns, delete_saved_dns, load_rules, load_saved_dns, load_settings, save_query_log,
+    save_rules, save_settings,
 };
-use crate::storage::{add_saved_dns, delete_saved_dns, load_saved_dns};
 use crate::system::{
-    clear_dns_with_result, get_active_adapter, get_current_dns, set_dns_with_result,
+    apply_with_rollback, default_backend, dry_run_backend, restore_all, DnsBackend, Plan,
+    SharedBackend,
 };
 use crate::textures::{
     load_background_image, load_custom_dns_background_image, load_ping_background_image,
     load_social_logos,
 };
 use crate::ui::{
-    self, render_action_buttons, render_add_dns_window_content, render_app_state,
-    render_custom_dns_window_content, render_footer, render_ping_window_content,
-    render_provider_selection, render_status_section, ui_colors, ui_constants,
+    self, render_action_buttons, render_adapter_selection, render_add_dns_window_content,
+    render_app_state, render_benchmark_window_content, render_blocklists_window_content,
+    render_compare_window_content, render_custom_dns_window_content, render_dry_run_toggle,
+    render_footer, render_forwarder_toggle, render_keep_changes, render_ping_window_content,
+    render_plan_window_content, render_provider_selection, render_query_log_window_content,
+    render_reachability_window_content, render_rules_window_content, render_status_section,
+    ui_colors, ui_constants, ListKind,
 };
 use crate::windows::{custom_window_frame, simple_window_frame};
 
 #[derive(Default)]
 pub struct MyApp {
+    backend: SharedBackend,
+    settings: AppSettings,
     adapter: Option<String>,
-    dns: Vec<String>,
+    adapters: Vec<AdapterInfo>,
+    adapters_receiver: Option<mpsc::Receiver<Vec<AdapterInfo>>>,
     app_state: AppState,
     selected_provider: DnsProvider,
     dns_state: DnsState,
-    custom_primary: String,
-    custom_secondary: String,
+    custom_servers: Vec<String>,
+    custom_servers_v6: Vec<String>,
     operation_sender: Option<mpsc::Sender<OperationResult>>,
     operation_receiver: Option<mpsc::Receiver<OperationResult>>,
+    undo_snapshots: Vec<DnsSnapshot>,
+    undo_receiver: Option<mpsc::Receiver<Vec<DnsSnapshot>>>,
+    plan: Plan,
+    last_plan: Vec<String>,
+    show_plan_window: bool,
+    awaiting_set: bool,
+    keep_deadline: Option<Instant>,
+    reverting_unconfirmed: bool,
     show_second_window: bool,
     ping_value: f64,
     ping_history: VecDeque<f64>,
     ping_sender: Option<mpsc::Sender<f64>>,
     ping_receiver: Option<mpsc::Receiver<f64>>,
+    show_benchmark_window: bool,
+    benchmark_domains: String,
+    benchmark_results: Vec<ServerStats>,
+    benchmark_receiver: Option<mpsc::Receiver<ServerStats>>,
+    benchmark_total: usize,
+    benchmark_error: Option<String>,
+    benchmark_sort: SortColumn,
+    benchmark_descending: bool,
+    auto_receiver: Option<mpsc::Receiver<Vec<ServerStats>>>,
+    auto_next_run: Option<Instant>,
+    /// The provider auto-select last switched to and when.
+    auto_current: Option<(DnsProvider, Instant)>,
+    /// The provider an automatic switch is waiting to hear back about.
+    auto_pending: Option<DnsProvider>,
+    auto_status: Option<String>,
+    show_compare_window: bool,
+    compare_domain: String,
+    compare_results: Vec<ProviderAnswer>,
+    compare_receiver: Option<mpsc::Receiver<Vec<ProviderAnswer>>>,
+    compare_error: Option<String>,
+    show_reachability_window: bool,
+    reachability_hosts: String,
+    reachability_providers: Vec<String>,
+    reachability_results: Vec<reachability::Cell>,
+    reachability_receiver: Option<mpsc::Receiver<reachability::Cell>>,
+    reachability_total: usize,
+    reachability_error: Option<String>,
+    /// Running while the adapter points at 127.0.0.1.
+    forwarder: Option<Forwarder>,
+    /// The adapter is being pointed at a just started forwarder, which is
+    /// only kept, and remembered as enabled, if that works.
+    forwarder_pending: bool,
+    routing_rules: Vec<RoutingRule>,
+    show_rules_window: bool,
+    new_rule: RoutingRule,
+    rules_error: Option<String>,
+    show_blocklists_window: bool,
+    new_list_path: String,
+    blocklist_receiver: Option<mpsc::Receiver<Result<Blocklist, String>>>,
+    blocklist_summary: Option<String>,
+    blocklists_error: Option<String>,
+    show_query_log_window: bool,
+    query_log: VecDeque<QueryEvent>,
+    query_log_receiver: Option<mpsc::Receiver<QueryEvent>>,
+    query_log_filter: String,
+    query_log_paused: bool,
+    query_log_export: Option<Result<String, String>>,
     show_clear_confirmation: bool,
     show_custom_dns_window: bool,
     show_add_dns_window: bool,
     saved_dns_entries: Vec<SavedDnsEntry>,
     new_dns_name: String,
-    new_dns_primary: String,
-    new_dns_secondary: String,
+    new_dns_servers: Vec<String>,
+    new_dns_servers_v6: Vec<String>,
+    new_dns_doh_url: String,
+    new_dns_tls_name: String,
     add_dns_error: Option<String>,
     background_texture: Option<TextureHandle>,
     ping_background_texture: Option<TextureHandle>,
@@ -55,13 +132,46 @@ pub struct MyApp {
     social_logos: std::collections::HashMap<String, TextureHandle>,
 }
 
+/// Oldest queries are dropped from the log past this many.
+const QUERY_LOG_ROWS: usize = 5_000;
+
 static PING_REQUEST: AtomicBool = AtomicBool::new(false);
+static BENCHMARK_REQUEST: AtomicBool = AtomicBool::new(false);
+static COMPARE_REQUEST: AtomicBool = AtomicBool::new(false);
+static REACHABILITY_REQUEST: AtomicBool = AtomicBool::new(false);
 
 impl MyApp {
-    pub fn new() -> Self {
+    /// `dry_run` comes from the command line and overrides the saved setting
+    /// for this session.
+    pub fn new(dry_run: bool) -> Self {
+        let plan = Plan::default();
+        let dry_run = dry_run || load_settings().dry_run;
+        let backend = if dry_run {
+            dry_run_backend(plan.clone())
+        } else {
+            default_backend()
+        };
+
+        let mut app = Self::with_backend(backend);
+        app.plan = plan;
+        app.settings.dry_run = dry_run;
+        if app.settings.forwarder.enabled {
+            app.restart_forwarder();
+        }
+        app
+    }
+
+    pub fn with_backend(backend: SharedBackend) -> Self {
         let saved_dns_entries = load_saved_dns();
+        let routing_rules = load_rules().unwrap_or_else(|e| {
+            log::warn!("Routing rules not loaded: {}", e);
+            Vec::new()
+        });
+        log::info!("Using {} DNS backend", backend.name());
 
-        let app = Self {
+        let mut app = Self {
+            backend,
+            settings: load_settings(),
             dns_state: DnsState::None,
             ping_value: 0.0,
             ping_history: VecDeque::with_capacity(15),
@@ -72,57 +182,384 @@ impl MyApp {
             custom_dns_background_texture: None,
             social_logos: std::collections::HashMap::new(),
             saved_dns_entries,
+            routing_rules,
             ..Default::default()
         };
-
+        app.refresh_adapters();
         app
     }
 
+    fn refresh_adapters(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        self.adapters_receiver = Some(receiver);
+
+        let backend = self.backend.clone();
+        thread::spawn(move || {
+            let _ = sender.send(backend.adapters());
+        });
+    }
+
+    fn set_dry_run(&mut self, enabled: bool) {
+        self.settings.dry_run = enabled;
+        self.backend = if enabled {
+            dry_run_backend(self.plan.clone())
+        } else {
+            default_backend()
+        };
+        log::info!("Dry run {}", if enabled { "enabled" } else { "disabled" });
+        if let Err(e) = save_settings(&self.settings) {
+            self.app_state = AppState::Error(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    /// The adapter was left pointing at the forwarder, so it has to be
+    /// listening again before anything resolves.
+    fn restart_forwarder(&mut self) {
+        let group = self.forwarder_group(&self.settings.forwarder.servers);
+        match Forwarder::start(group, self.forwarder_timeouts()) {
+            Ok(forwarder) => {
+                self.forwarder = Some(forwarder);
+                self.apply_routes();
+                self.reload_blocklists();
+            }
+            Err(e) => {
+                log::warn!("Could not restart the DNS forwarder: {}", e);
+                self.app_state = AppState::Error(e);
+            }
+        }
+    }
+
+    fn forwarder_group(&self, servers: &[String]) -> Group {
+        Group::new(
+            Upstream::from_servers(servers),
+            self.settings.forwarder.strategy,
+        )
+    }
+
+    fn forwarder_timeouts(&self) -> Timeouts {
+        let settings = &self.settings.forwarder;
+        Timeouts {
+            attempt: Duration::from_millis(settings.attempt_timeout_ms),
+            total: Duration::from_millis(settings.timeout_ms),
+        }
+    }
+
+    fn set_forwarder_strategy(&mut self, strategy: UpstreamStrategy) {
+        self.settings.forwarder.strategy = strategy;
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.set_upstreams(self.forwarder_group(&self.settings.forwarder.servers));
+        }
+        if let Err(e) = save_settings(&self.settings) {
+            self.rules_error = Some(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    /// Hands the routing rules to the running forwarder.
+    fn apply_routes(&mut self) {
+        let Some(forwarder) = &self.forwarder else {
+            return;
+        };
+        match Routes::compile(&self.routing_rules, &self.all_providers()) {
+            Ok(routes) => forwarder.set_routes(routes),
+            Err(e) => {
+                log::warn!("Routing rules not applied: {}", e);
+                self.app_state = AppState::Error(format!("Routing rules not applied: {}", e));
+            }
+        }
+    }
+
+    /// Reads the blocklists in the background; big lists take a while.
+    fn reload_blocklists(&mut self) {
+        if self.forwarder.is_none() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.blocklist_receiver = Some(receiver);
+        self.blocklist_summary = Some("Loading lists…".to_string());
+
+        let blocking = self.settings.forwarder.blocking.clone();
+        thread::spawn(move || {
+            let _ = sender.send(Blocklist::load(&blocking.blocklists, &blocking.allowlists));
+        });
+    }
+
+    fn poll_blocklists(&mut self) {
+        let Some(receiver) = &self.blocklist_receiver else {
+            return;
+        };
+        let Ok(result) = receiver.try_recv() else {
+            return;
+        };
+        self.blocklist_receiver = None;
+        match result {
+            Ok(blocklist) => {
+                self.blocklist_summary = Some(format!(
+                    "{} names blocked, {} allowed",
+                    blocklist.blocked_len(),
+                    blocklist.allowed_len()
+                ));
+                if let Some(forwarder) = &self.forwarder {
+                    forwarder.set_blocklist(blocklist, self.settings.forwarder.blocking.response);
+                }
+            }
+            Err(e) => {
+                log::warn!("Blocklists not loaded: {}", e);
+                self.blocklist_summary = None;
+                self.blocklists_error = Some(e);
+            }
+        }
+    }
+
+    fn set_block_response(&mut self, response: BlockResponse) {
+        self.settings.forwarder.blocking.response = response;
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.set_block_response(response);
+        }
+        if let Err(e) = save_settings(&self.settings) {
+            self.blocklists_error = Some(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    fn set_forwarder(&mut self, enabled: bool) {
+        if enabled {
+            let servers = self.selected_provider.servers().to_vec();
+            let group = self.forwarder_group(&servers);
+            if group.upstreams().is_empty() {
+                self.app_state = AppState::Error("No DNS servers to forward to".to_string());
+                return;
+            }
+            let forwarder = match Forwarder::start(group, self.forwarder_timeouts()) {
+                Ok(forwarder) => forwarder,
+                Err(e) => {
+                    self.app_state = AppState::Error(e);
+                    return;
+                }
+            };
+            self.settings.forwarder.servers = servers;
+            // The only adapter change the forwarder needs.
+            let loopback = ServerList::new([forwarder.address().ip().to_string()]);
+            self.handle_operation(DnsOperation::Set(DnsProvider::custom(loopback)));
+            if !matches!(self.app_state, AppState::Processing) {
+                return;
+            }
+            self.forwarder = Some(forwarder);
+            self.forwarder_pending = true;
+            self.apply_routes();
+            self.reload_blocklists();
+        } else {
+            self.forwarder = None;
+            self.handle_operation(DnsOperation::Set(self.selected_provider.clone()));
+            self.save_forwarder_enabled(false);
+        }
+    }
+
+    /// Keeps or stops the forwarder the adapter was just pointed at.
+    fn finish_forwarder(&mut self, succeeded: bool) {
+        if !succeeded {
+            self.forwarder = None;
+            return;
+        }
+        self.save_forwarder_enabled(true);
+    }
+
+    fn save_forwarder_enabled(&mut self, enabled: bool) {
+        self.settings.forwarder.enabled = enabled;
+        if let Err(e) = save_settings(&self.settings) {
+            self.app_state = AppState::Error(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    /// Points the running forwarder at `provider` instead of the adapter.
+    fn forward_to(&mut self, provider: &DnsProvider) {
+        let Some(forwarder) = &self.forwarder else {
+            return;
+        };
+        let servers = provider.servers().to_vec();
+        let group = self.forwarder_group(&servers);
+        if group.upstreams().is_empty() {
+            self.app_state = AppState::Error("No DNS servers to forward to".to_string());
+            return;
+        }
+        forwarder.set_upstreams(group);
+        self.settings.forwarder.servers = servers;
+        self.app_state = match save_settings(&self.settings) {
+            Ok(()) => AppState::Success(format!("Now forwarding to {}", provider.display_name())),
+            Err(e) => AppState::Error(format!("Failed to save settings: {}", e)),
+        };
+    }
+
+    /// A one-line summary and, for its tooltip, how each upstream is doing.
+    fn forwarder_status(&self) -> Option<(String, String)> {
+        let forwarder = self.forwarder.as_ref()?;
+        let stats = forwarder.stats();
+        let now = Instant::now();
+        let health: Vec<String> = forwarder
+            .health()
+            .iter()
+            .map(|(upstream, score)| {
+                format!(
+                    "{}: {} answered, {} failed{}",
+                    upstream,
+                    score.answered,
+                    score.failed,
+                    if score.is_ejected(now) {
+                        ", left out for now"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect();
+        let summary = if stats.blocked > 0 {
+            format!(
+                "{} queries, {} from cache, {} blocked",
+                stats.queries, stats.cache_hits, stats.blocked
+            )
+        } else {
+            format!("{} queries, {} from cache", stats.queries, stats.cache_hits)
+        };
+        Some((summary, health.join("\n")))
+    }
+
+    fn set_adapter_target(&mut self, target: AdapterTarget) {
+        self.settings.adapter_target = target;
+        if let Err(e) = save_settings(&self.settings) {
+            self.app_state = AppState::Error(format!("Failed to save settings: {}", e));
+        }
+    }
+
     fn handle_operation(&mut self, operation: DnsOperation) {
+        self.auto_pending = None;
+        if std::mem::take(&mut self.forwarder_pending) {
+            // Whatever comes next replaces the change that was to confirm it.
+            self.forwarder = None;
+        }
+        if let DnsOperation::Set(provider) = &operation {
+            if let Err(e) = provider.servers().validate() {
+                self.app_state = AppState::Error(e);
+                return;
+            }
+            if self.forwarder.is_some() {
+                self.forward_to(provider);
+                return;
+            }
+        }
+        // Both take the adapter off 127.0.0.1, so the forwarder has no use.
+        if matches!(operation, DnsOperation::Clear | DnsOperation::Undo(_))
+            && self.forwarder.take().is_some()
+        {
+            self.settings.forwarder.enabled = false;
+            if let Err(e) = save_settings(&self.settings) {
+                log::warn!("Failed to save settings: {}", e);
+            }
+        }
+
+        if matches!(operation, DnsOperation::Undo(_) | DnsOperation::Clear) {
+            // The adapter goes back to what it had before auto-select.
+            self.auto_current = None;
+        }
+
         self.app_state = AppState::Processing;
+        // Only a new change settles the one awaiting confirmation; tests and
+        // checks leave the countdown running.
+        if matches!(
+            operation,
+            DnsOperation::Set(_) | DnsOperation::Clear | DnsOperation::Undo(_)
+        ) {
+            self.keep_deadline = None;
+        }
+        self.awaiting_set = matches!(operation, DnsOperation::Set(_));
 
-        let adapter = get_active_adapter();
+        let adapter = match &self.settings.adapter_target {
+            AdapterTarget::Named(name) => Some(name.clone()),
+            AdapterTarget::Active | AdapterTarget::All => self.backend.active_adapter(),
+        };
         self.adapter = adapter.clone();
 
+        let targets: Vec<String> = match &self.settings.adapter_target {
+            AdapterTarget::All => self
+                .adapters
+                .iter()
+                .filter(|adapter| adapter.in_all())
+                .map(|adapter| adapter.name.clone())
+                .collect(),
+            _ => adapter.iter().cloned().collect(),
+        };
+
         let (sender, receiver) = mpsc::channel();
         self.operation_sender = Some(sender);
         self.operation_receiver = Some(receiver);
 
+        let (undo_sender, undo_receiver) = mpsc::channel();
+        self.undo_receiver = Some(undo_receiver);
+
         let adapter_for_thread = adapter;
         let sender_clone = self.operation_sender.clone();
+        let backend = self.backend.clone();
+        let mut health_check = self.settings.health_check.clone();
+        let dry_run = self.settings.dry_run;
+        // Nothing changes in a dry run, so there is nothing to check.
+        health_check.enabled &= !dry_run;
+        // Test also tries the selected provider's DoH and DoT endpoints.
+        let encrypted = Upstream::encrypted(&self.selected_provider, self.settings.doh_method);
 
         thread::spawn(move || {
             let result = match operation {
                 DnsOperation::Set(provider) => {
-                    if let Some(adapter) = &adapter_for_thread {
-                        let (primary, secondary) = provider.get_servers();
-                        set_dns_with_result(adapter, &primary, &secondary)
-                    } else {
-                        OperationResult::Error("No Internet Connection Found".to_string())
+                    let (result, snapshots) =
+                        set_and_verify(&*backend, &targets, provider.servers(), &health_check);
+                    if !snapshots.is_empty() && !dry_run {
+                        let _ = undo_sender.send(snapshots);
                     }
+                    result
                 }
                 DnsOperation::Clear => {
-                    if let Some(adapter) = &adapter_for_thread {
-                        clear_dns_with_result(adapter)
-                    } else {
-                        OperationResult::Error("No Internet Connection Found".to_string())
+                    let (result, snapshots) = apply_with_rollback(&*backend, &targets, |adapter| {
+                        backend.reset_dns(adapter)
+                    });
+                    if !snapshots.is_empty() && !dry_run {
+                        let _ = undo_sender.send(snapshots);
                     }
+                    result
                 }
+                DnsOperation::Undo(snapshots) => match restore_all(&*backend, &snapshots) {
+                    OperationResult::Success(_) => {
+                        let _ = undo_sender.send(Vec::new());
+                        OperationResult::Success("Previous DNS settings restored".to_string())
+                    }
+                    result => result,
+                },
                 DnsOperation::Test => {
                     if let Some(adapter) = &adapter_for_thread {
-                        let dns = get_current_dns(adapter);
-                        if dns.is_empty() {
+                        let mut upstreams = Upstream::from_servers(&backend.current_dns(adapter));
+                        if upstreams.is_empty() {
                             OperationResult::Warning("No DNS servers configured".to_string())
                         } else {
-                            OperationResult::Success(format!(
-                                "DNS test successful: {}",
-                                dns.join(", ")
-                            ))
+                            upstreams.extend(encrypted);
+                            dns::probe(
+                                &upstreams,
+                                &health_check.probe_domain,
+                                Duration::from_secs(health_check.timeout_secs),
+                            )
                         }
                     } else {
                         OperationResult::Error("No Internet Connection Found".to_string())
                     }
                 }
+                DnsOperation::CheckInterception => {
+                    if let Some(adapter) = &adapter_for_thread {
+                        let servers = interception::Server::to_check(&backend.current_dns(adapter));
+                        interception::check(
+                            &servers,
+                            interception::BOGUS_RESOLVER,
+                            &health_check.probe_domain,
+                            Duration::from_secs(health_check.timeout_secs),
+                        )
+                        .into()
+                    } else {
+                        OperationResult::Error("No Internet Connection Found".to_string())
+                    }
+                }
             };
 
             if let Some(s) = sender_clone {
@@ -132,13 +569,43 @@ impl MyApp {
     }
 
     fn handle_operation_result(&mut self, result: OperationResult) {
+        let confirm = std::mem::take(&mut self.awaiting_set);
+        let unconfirmed = std::mem::take(&mut self.reverting_unconfirmed);
+        let auto = self.auto_pending.take();
+        let forwarder_set = std::mem::take(&mut self.forwarder_pending);
+
+        let steps = self.plan.take();
+        if !steps.is_empty() && !matches!(result, OperationResult::Error(_)) {
+            if forwarder_set {
+                // Nothing points at the forwarder in a dry run.
+                self.finish_forwarder(false);
+            }
+            self.app_state = AppState::Success(format!(
+                "Dry run: {} steps planned, nothing was changed",
+                steps.len()
+            ));
+            self.last_plan = steps;
+            self.show_plan_window = true;
+            return;
+        }
+
         match result {
             OperationResult::Success(message) => {
-                self.app_state = AppState::Success(message);
+                self.app_state = if unconfirmed {
+                    AppState::Warning(
+                        "Changes were not confirmed; previous DNS settings restored".to_string(),
+                    )
+                } else {
+                    AppState::Success(message)
+                };
+                let keep_secs = self.settings.health_check.keep_changes_secs;
+                if confirm && keep_secs > 0 && !self.undo_snapshots.is_empty() {
+                    self.keep_deadline = Some(Instant::now() + Duration::from_secs(keep_secs));
+                }
                 if let Some(adapter) = &self.adapter {
-                    self.dns = get_current_dns(adapter);
-                    self.update_dns_state();
+                    self.dns_state = self.backend.dns_state(adapter);
                 }
+                self.refresh_adapters();
             }
             OperationResult::Error(message) => {
                 self.app_state = AppState::Error(message);
@@ -147,28 +614,565 @@ impl MyApp {
                 self.app_state = AppState::Warning(message);
             }
         }
+        if forwarder_set {
+            self.finish_forwarder(matches!(self.app_state, AppState::Success(_)));
+        }
+        // A failed automatic switch was rolled back or reverted by the
+        // health check, so nothing auto-selected is in use.
+        if let Some(provider) = auto {
+            self.auto_current =
+                matches!(self.app_state, AppState::Success(_)).then(|| (provider, Instant::now()));
+        }
+    }
+
+    fn render_secondary_viewport(&mut self, ctx: &egui::Context) {
+        if !self.show_second_window {
+            return;
+        }
+
+        let ping_value = self.ping_value;
+        let ping_history: Vec<f64> = self.ping_history.iter().copied().collect();
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(400.0, 300.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("ping");
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("Ping-Monitor")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(true)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    simple_window_frame(ctx, |ui| {
+                        render_ping_window_content(ui, ctx, ping_value, &ping_history);
+                    });
+                }
+            },
+        );
+
+        self.show_second_window = keep_open.get();
+        if !self.show_second_window {
+            let _ = self.ping_sender.take();
+            self.ping_receiver = None;
+            self.ping_value = 0.0;
+            self.ping_history.clear();
+        }
+    }
+
+    /// Takes in what the forwarder answered since the last frame, and starts
+    /// watching again when the forwarder was restarted. While paused nothing
+    /// is watched, so queries in between are not kept.
+    fn poll_query_log(&mut self) {
+        if self.query_log_paused {
+            self.query_log_receiver = None;
+            return;
+        }
+        if self.query_log_receiver.is_none() {
+            self.query_log_receiver = self.forwarder.as_ref().map(Forwarder::watch);
+        }
+        let Some(receiver) = &self.query_log_receiver else {
+            return;
+        };
+        loop {
+            match receiver.try_recv() {
+                Ok(event) => {
+                    if self.query_log.len() == QUERY_LOG_ROWS {
+                        self.query_log.pop_front();
+                    }
+                    self.query_log.push_back(event);
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.query_log_receiver = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn export_query_log(&mut self) {
+        let filter = &self.query_log_filter;
+        let shown = self.query_log.iter().filter(|event| event.matches(filter));
+        self.query_log_export = Some(
+            forwarder::to_jsonl(shown)
+                .and_then(|jsonl| save_query_log(&jsonl))
+                .map(|path| path.display().to_string()),
+        );
+    }
+
+    fn render_query_log_window(&mut self, ctx: &egui::Context) {
+        if !self.show_query_log_window {
+            return;
+        }
+        self.poll_query_log();
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(760.0, 440.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("query_log");
+
+        let export_requested = std::cell::Cell::new(false);
+        let clear_requested = std::cell::Cell::new(false);
+        let listening = self.forwarder.is_some();
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("Query Log")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(true)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                let query_log = &self.query_log;
+                let filter = &mut self.query_log_filter;
+                let paused = &mut self.query_log_paused;
+                let export = self.query_log_export.as_ref();
+                let export_requested = &export_requested;
+                let clear_requested = &clear_requested;
+
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    let shown: Vec<&QueryEvent> = query_log
+                        .iter()
+                        .filter(|event| event.matches(filter))
+                        .collect();
+                    simple_window_frame(ctx, |ui| {
+                        render_query_log_window_content(
+                            ui,
+                            ctx,
+                            &shown,
+                            query_log.len(),
+                            filter,
+                            paused,
+                            listening,
+                            export,
+                            || {
+                                export_requested.set(true);
+                            },
+                            || {
+                                clear_requested.set(true);
+                            },
+                        );
+                    });
+                }
+            },
+        );
+        ctx.request_repaint_after(Duration::from_millis(250));
+
+        if export_requested.get() {
+            self.export_query_log();
+        }
+        if clear_requested.get() {
+            self.query_log.clear();
+            self.query_log_export = None;
+        }
+
+        self.show_query_log_window = keep_open.get();
+        if !self.show_query_log_window {
+            self.query_log_receiver = None;
+            self.query_log.clear();
+            self.query_log_paused = false;
+            self.query_log_export = None;
+        }
+    }
+
+    fn start_benchmark(&mut self) {
+        let domains: Vec<String> = self
+            .benchmark_domains
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if domains.is_empty() {
+            self.benchmark_error = Some("Enter at least one domain".to_string());
+            return;
+        }
+        self.benchmark_error = None;
+        self.settings.benchmark.domains = domains;
+        if let Err(e) = save_settings(&self.settings) {
+            self.benchmark_error = Some(format!("Failed to save settings: {}", e));
+        }
+
+        let targets = benchmark::targets(&self.saved_dns_entries, self.settings.doh_method);
+        self.benchmark_total = targets.len();
+        self.benchmark_results.clear();
+
+        let (sender, receiver) = mpsc::channel();
+        self.benchmark_receiver = Some(receiver);
+        let settings = self.settings.benchmark.clone();
+        thread::spawn(move || benchmark::run(targets, &settings, sender));
+    }
+
+    /// Starts a measurement when one is due and switches provider when the
+    /// results call for it.
+    fn run_auto_select(&mut self) {
+        if !self.settings.auto_select.enabled {
+            return;
+        }
+
+        if let Some(receiver) = &self.auto_receiver {
+            match receiver.try_recv() {
+                Ok(stats) => {
+                    self.auto_receiver = None;
+                    self.apply_auto_select(&stats);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => self.auto_receiver = None,
+            }
+        }
+
+        let due = self.auto_next_run.is_none_or(|at| Instant::now() >= at);
+        if !due {
+            return;
+        }
+        let interval = Duration::from_secs(self.settings.auto_select.interval_secs);
+        self.auto_next_run = Some(Instant::now() + interval);
+
+        let targets = benchmark::targets(&self.saved_dns_entries, self.settings.doh_method);
+        let settings = self.settings.benchmark.clone();
+        let (sender, receiver) = mpsc::channel();
+        self.auto_receiver = Some(receiver);
+        thread::spawn(move || {
+            let (results, stats) = mpsc::channel();
+            benchmark::run(targets, &settings, results);
+            let _ = sender.send(stats.iter().collect());
+        });
+    }
+
+    fn apply_auto_select(&mut self, stats: &[ServerStats]) {
+        if matches!(self.app_state, AppState::Processing) || self.keep_deadline.is_some() {
+            log::info!("Auto-select skipped while another change is in progress");
+            return;
+        }
+
+        let settings = &self.settings.auto_select;
+        let scores = auto_select::provider_scores(stats, settings);
+        let current = self.auto_current.as_ref();
+        let since_switch = current.map(|(_, at)| at.elapsed()).unwrap_or_default();
+        let Some(switch) =
+            auto_select::decide(current.map(|(p, _)| p), since_switch, &scores, settings)
+        else {
+            return;
+        };
+
+        log::info!("Auto-select: {}", switch.reason);
+        self.auto_status = Some(switch.reason);
+        self.selected_provider = switch.provider.clone();
+        self.handle_operation(DnsOperation::Set(switch.provider.clone()));
+        // Nobody is around to confirm an automatic switch. The health check
+        // still reverts servers that do not resolve.
+        self.awaiting_set = false;
+        // The forwarder switches right away; the adapter reports back later.
+        match self.app_state {
+            AppState::Processing => self.auto_pending = Some(switch.provider),
+            AppState::Success(_) => self.auto_current = Some((switch.provider, Instant::now())),
+            _ => {}
+        }
+    }
+
+    fn set_auto_select(&mut self, settings: AutoSelectSettings) {
+        if settings.enabled != self.settings.auto_select.enabled {
+            self.auto_receiver = None;
+            self.auto_next_run = None;
+            self.auto_current = None;
+            self.auto_pending = None;
+            self.auto_status = None;
+        }
+        self.settings.auto_select = settings;
+        if let Err(e) = save_settings(&self.settings) {
+            self.app_state = AppState::Error(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    fn render_benchmark_window(&mut self, ctx: &egui::Context) {
+        if !self.show_benchmark_window {
+            return;
+        }
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(600.0, 480.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("benchmark");
+
+        let run_requested = std::cell::Cell::new(false);
+        let apply_requested = std::cell::Cell::new(false);
+        let progress = self
+            .benchmark_receiver
+            .is_some()
+            .then_some((self.benchmark_results.len(), self.benchmark_total));
+        let sort_before = (self.benchmark_sort, self.benchmark_descending);
+        let mut auto_select = self.settings.auto_select.clone();
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("DNS Benchmark")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(true)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                let run_requested = &run_requested;
+                let apply_requested = &apply_requested;
+                let settings = &mut self.settings.benchmark;
+                let auto_select = &mut auto_select;
+                let doh_method = &mut self.settings.doh_method;
+                let domains = &mut self.benchmark_domains;
+                let results = &self.benchmark_results;
+                let error = self.benchmark_error.as_deref();
+                let sort = &mut self.benchmark_sort;
+                let descending = &mut self.benchmark_descending;
+
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    simple_window_frame(ctx, |ui| {
+                        render_benchmark_window_content(
+                            ui,
+                            ctx,
+                            settings,
+                            auto_select,
+                            doh_method,
+                            domains,
+                            results,
+                            benchmark::fastest(results),
+                            progress,
+                            error,
+                            sort,
+                            descending,
+                            || {
+                                run_requested.set(true);
+                            },
+                            || {
+                                apply_requested.set(true);
+                            },
+                        );
+                    });
+                }
+            },
+        );
+
+        if sort_before != (self.benchmark_sort, self.benchmark_descending) {
+            benchmark::sort(
+                &mut self.benchmark_results,
+                self.benchmark_sort,
+                !self.benchmark_descending,
+            );
+        }
+        if auto_select != self.settings.auto_select {
+            self.set_auto_select(auto_select);
+        }
+        if run_requested.get() {
+            self.start_benchmark();
+        }
+        if apply_requested.get() {
+            if let Some(stats) = benchmark::fastest(&self.benchmark_results) {
+                self.selected_provider = stats.provider.clone();
+                self.handle_operation(DnsOperation::Set(stats.provider.clone()));
+            }
+        }
+
+        self.show_benchmark_window = keep_open.get();
+    }
+
+    /// The built-in providers, the custom servers when there are any and
+    /// the saved entries.
+    fn all_providers(&self) -> Vec<DnsProvider> {
+        let mut providers = DnsProvider::built_in();
+        if !self.custom_provider().servers().is_empty() {
+            providers.push(self.custom_provider());
+        }
+        providers.extend(self.saved_dns_entries.iter().map(DnsProvider::saved));
+        providers
+    }
+
+    fn start_compare(&mut self) {
+        let domain = self.compare_domain.trim().trim_end_matches('.').to_string();
+        if domain.is_empty() {
+            self.compare_error = Some("Enter a domain".to_string());
+            return;
+        }
+        self.compare_error = None;
+        self.compare_results.clear();
+
+        let providers = self.all_providers();
+        let timeout = Duration::from_secs(self.settings.health_check.timeout_secs);
+
+        let (sender, receiver) = mpsc::channel();
+        self.compare_receiver = Some(receiver);
+        thread::spawn(move || {
+            let _ = sender.send(compare::compare(providers, &domain, timeout));
+        });
+    }
+
+    fn render_compare_window(&mut self, ctx: &egui::Context) {
+        if !self.show_compare_window {
+            return;
+        }
+
+        if let Some(receiver) = &self.compare_receiver {
+            match receiver.try_recv() {
+                Ok(answers) => {
+                    self.compare_results = answers;
+                    self.compare_receiver = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.compare_error = Some("The comparison stopped unexpectedly".to_string());
+                    self.compare_receiver = None;
+                }
+            }
+        }
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(620.0, 360.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("compare");
+
+        let compare_requested = std::cell::Cell::new(false);
+        let running = self.compare_receiver.is_some();
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("Compare Answers")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(true)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                let compare_requested = &compare_requested;
+                let domain = &mut self.compare_domain;
+                let answers = &self.compare_results;
+                let error = self.compare_error.as_deref();
+
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    simple_window_frame(ctx, |ui| {
+                        render_compare_window_content(
+                            ui,
+                            ctx,
+                            domain,
+                            answers,
+                            running,
+                            error,
+                            || {
+                                compare_requested.set(true);
+                            },
+                        );
+                    });
+                }
+            },
+        );
+
+        if compare_requested.get() {
+            self.start_compare();
+        }
+
+        self.show_compare_window = keep_open.get();
     }
 
-    fn update_dns_state(&mut self) {
-        if self.dns.is_empty() {
-            self.dns_state = DnsState::None;
-        } else if self.dns.len() == 1 && self.dns[0].contains("dhcp") {
-            self.dns_state = DnsState::Dhcp;
-        } else {
-            self.dns_state = DnsState::Static(self.dns.clone());
+    fn start_reachability(&mut self) {
+        let hosts: Vec<String> = self
+            .reachability_hosts
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if hosts.is_empty() {
+            self.reachability_error = Some("Enter at least one host".to_string());
+            return;
         }
+        self.reachability_error = None;
+        self.settings.reachability.hosts = hosts.clone();
+        if let Err(e) = save_settings(&self.settings) {
+            self.reachability_error = Some(format!("Failed to save settings: {}", e));
+        }
+
+        let providers = self.all_providers();
+        self.reachability_providers = providers.iter().map(|p| p.display_name()).collect();
+        self.reachability_total = providers.len() * hosts.len();
+        self.reachability_results.clear();
+
+        let (sender, receiver) = mpsc::channel();
+        self.reachability_receiver = Some(receiver);
+        let timeout = Duration::from_millis(self.settings.reachability.timeout_ms);
+        thread::spawn(move || reachability::run(providers, &hosts, timeout, sender));
     }
 
-    fn render_secondary_viewport(&mut self, ctx: &egui::Context) {
-        if !self.show_second_window {
+    fn render_reachability_window(&mut self, ctx: &egui::Context) {
+        if !self.show_reachability_window {
             return;
         }
 
-        let ping_value = self.ping_value;
-        let ping_history: Vec<f64> = self.ping_history.iter().copied().collect();
+        if let Some(receiver) = &self.reachability_receiver {
+            loop {
+                match receiver.try_recv() {
+                    Ok(cell) => self.reachability_results.push(cell),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        self.reachability_receiver = None;
+                        break;
+                    }
+                }
+            }
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
 
         let keep_open = std::cell::Cell::new(true);
-        let window_size = egui::vec2(400.0, 300.0);
+        let window_size = egui::vec2(760.0, 400.0);
         let screen_center = ctx.input(|i| {
             let info = i.viewport();
             info.outer_rect
@@ -177,37 +1181,60 @@ impl MyApp {
                 .unwrap_or_else(|| egui::pos2(0.0, 0.0))
         });
         let position = screen_center - window_size / 2.0;
-        let viewport_id = egui::ViewportId::from_hash_of("ping");
+        let viewport_id = egui::ViewportId::from_hash_of("reachability");
+
+        let run_requested = std::cell::Cell::new(false);
+        let progress = self
+            .reachability_receiver
+            .is_some()
+            .then_some((self.reachability_results.len(), self.reachability_total));
 
         ctx.show_viewport_immediate(
             viewport_id,
             egui::ViewportBuilder::default()
-                .with_title("Ping-Monitor")
+                .with_title("Service Reachability")
                 .with_inner_size(window_size)
                 .with_position(position)
                 .with_resizable(true)
                 .with_decorations(false),
             {
                 let keep_open = &keep_open;
+                let run_requested = &run_requested;
+                let settings = &mut self.settings.reachability;
+                let hosts = &mut self.reachability_hosts;
+                let providers = &self.reachability_providers;
+                let cells = &self.reachability_results;
+                let error = self.reachability_error.as_deref();
+
                 move |ctx, _class| {
                     if ctx.input(|i| i.viewport().close_requested()) {
                         keep_open.set(false);
                     }
 
                     simple_window_frame(ctx, |ui| {
-                        render_ping_window_content(ui, ctx, ping_value, &ping_history);
+                        render_reachability_window_content(
+                            ui,
+                            ctx,
+                            settings,
+                            hosts,
+                            providers,
+                            cells,
+                            progress,
+                            error,
+                            || {
+                                run_requested.set(true);
+                            },
+                        );
                     });
                 }
             },
         );
 
-        self.show_second_window = keep_open.get();
-        if !self.show_second_window {
-            let _ = self.ping_sender.take();
-            self.ping_receiver = None;
-            self.ping_value = 0.0;
-            self.ping_history.clear();
+        if run_requested.get() {
+            self.start_reachability();
         }
+
+        self.show_reachability_window = keep_open.get();
     }
 
     fn render_custom_dns_window(&mut self, ctx: &egui::Context) {
@@ -216,7 +1243,7 @@ impl MyApp {
         }
 
         let keep_open = std::cell::Cell::new(true);
-        let window_size = egui::vec2(300.0, 250.0);
+        let window_size = egui::vec2(300.0, 400.0);
         let screen_center = ctx.input(|i| {
             let info = i.viewport();
             info.outer_rect
@@ -237,8 +1264,8 @@ impl MyApp {
                 .with_decorations(false),
             {
                 let keep_open = &keep_open;
-                let custom_primary = &mut self.custom_primary;
-                let custom_secondary = &mut self.custom_secondary;
+                let custom_servers = &mut self.custom_servers;
+                let custom_servers_v6 = &mut self.custom_servers_v6;
                 let clear_requested = std::cell::Cell::new(false);
 
                 move |ctx, _class| {
@@ -250,8 +1277,8 @@ impl MyApp {
                         render_custom_dns_window_content(
                             ui,
                             ctx,
-                            custom_primary,
-                            custom_secondary,
+                            custom_servers,
+                            custom_servers_v6,
                             || {
                                 keep_open.set(false);
                             },
@@ -262,8 +1289,8 @@ impl MyApp {
                     });
 
                     if clear_requested.get() {
-                        *custom_primary = String::new();
-                        *custom_secondary = String::new();
+                        custom_servers.clear();
+                        custom_servers_v6.clear();
                     }
                 }
             },
@@ -272,8 +1299,275 @@ impl MyApp {
         self.show_custom_dns_window = keep_open.get();
 
         if matches!(self.selected_provider, DnsProvider::Custom { .. }) {
-            self.selected_provider =
-                DnsProvider::custom(self.custom_primary.clone(), self.custom_secondary.clone());
+            self.selected_provider = self.custom_provider();
+        }
+    }
+
+    /// Puts back the settings from before the last change when the user did
+    /// not confirm it in time.
+    fn revert_unconfirmed(&mut self) {
+        self.reverting_unconfirmed = true;
+        self.handle_operation(DnsOperation::Undo(self.undo_snapshots.clone()));
+    }
+
+    /// Counts down the prompt to keep the last change and reverts it once
+    /// time is up. Returns whether the countdown is still running.
+    fn poll_keep_deadline(&mut self, now: Instant) -> bool {
+        let Some(deadline) = self.keep_deadline else {
+            return false;
+        };
+        let remaining = deadline.saturating_duration_since(now);
+        if remaining.is_zero() {
+            self.keep_deadline = None;
+            self.revert_unconfirmed();
+            return false;
+        }
+        self.app_state = AppState::Warning(format!(
+            "Keep these DNS settings? Reverting in {}s",
+            remaining.as_secs() + 1
+        ));
+        true
+    }
+
+    fn custom_provider(&self) -> DnsProvider {
+        DnsProvider::custom(ServerList::new(
+            self.custom_servers.iter().chain(&self.custom_servers_v6),
+        ))
+    }
+
+    fn render_rules_window(&mut self, ctx: &egui::Context) {
+        if !self.show_rules_window {
+            return;
+        }
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(340.0, 450.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("routing_rules");
+
+        let providers: Vec<String> = self
+            .all_providers()
+            .iter()
+            .filter(|provider| !matches!(provider, DnsProvider::Custom { .. }))
+            .map(DnsProvider::display_name)
+            .collect();
+        if self.new_rule.upstream.is_empty() {
+            self.new_rule.upstream = providers.first().cloned().unwrap_or_default();
+        }
+
+        let add_requested = std::cell::Cell::new(false);
+        let delete_requested = std::cell::Cell::new(None);
+        let mut strategy = self.settings.forwarder.strategy;
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("Routing Rules")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(false)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                let rules = &self.routing_rules;
+                let new_rule = &mut self.new_rule;
+                let strategy = &mut strategy;
+                let providers = &providers;
+                let add_requested = &add_requested;
+                let delete_requested = &delete_requested;
+                let rules_error = &self.rules_error;
+
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    simple_window_frame(ctx, |ui| {
+                        render_rules_window_content(
+                            ui,
+                            ctx,
+                            rules,
+                            new_rule,
+                            strategy,
+                            providers,
+                            rules_error.clone(),
+                            || {
+                                add_requested.set(true);
+                            },
+                            |index| {
+                                delete_requested.set(Some(index));
+                            },
+                            || {
+                                keep_open.set(false);
+                            },
+                        );
+                    });
+                }
+            },
+        );
+
+        if strategy != self.settings.forwarder.strategy {
+            self.set_forwarder_strategy(strategy);
+        }
+
+        let mut changed = false;
+        if add_requested.get() {
+            if let Err(e) = forwarder::validate(self.new_rule.kind, &self.new_rule.pattern) {
+                self.rules_error = Some(e);
+            } else if !providers.contains(&self.new_rule.upstream) {
+                self.rules_error = Some("Choose where matching names go".to_string());
+            } else {
+                self.routing_rules.push(RoutingRule {
+                    pattern: self.new_rule.pattern.trim().to_string(),
+                    ..self.new_rule.clone()
+                });
+                self.new_rule.pattern.clear();
+                changed = true;
+            }
+        }
+        if let Some(index) = delete_requested.get() {
+            self.routing_rules.remove(index);
+            changed = true;
+        }
+        if changed {
+            self.rules_error = match save_rules(&self.routing_rules) {
+                Ok(()) => None,
+                Err(e) => Some(format!("Failed to save rules: {}", e)),
+            };
+            self.apply_routes();
+        }
+
+        self.show_rules_window = keep_open.get();
+        if !self.show_rules_window {
+            self.new_rule.pattern.clear();
+            self.rules_error = None;
+        }
+    }
+
+    fn render_blocklists_window(&mut self, ctx: &egui::Context) {
+        if !self.show_blocklists_window {
+            return;
+        }
+
+        let keep_open = std::cell::Cell::new(true);
+        let window_size = egui::vec2(340.0, 420.0);
+        let screen_center = ctx.input(|i| {
+            let info = i.viewport();
+            info.outer_rect
+                .or(info.inner_rect)
+                .map(|rect| rect.center())
+                .unwrap_or_else(|| egui::pos2(0.0, 0.0))
+        });
+        let position = screen_center - window_size / 2.0;
+        let viewport_id = egui::ViewportId::from_hash_of("blocklists");
+
+        let add_requested = std::cell::Cell::new(None);
+        let remove_requested = std::cell::Cell::new(None);
+        let mut response = self.settings.forwarder.blocking.response;
+        let counts = self
+            .forwarder
+            .as_ref()
+            .map(|forwarder| forwarder.stats())
+            .map(|stats| (stats.blocked, stats.allowed));
+
+        ctx.show_viewport_immediate(
+            viewport_id,
+            egui::ViewportBuilder::default()
+                .with_title("Blocklists")
+                .with_inner_size(window_size)
+                .with_position(position)
+                .with_resizable(false)
+                .with_decorations(false),
+            {
+                let keep_open = &keep_open;
+                let blocking = &self.settings.forwarder.blocking;
+                let response = &mut response;
+                let path = &mut self.new_list_path;
+                let loaded = self.blocklist_summary.as_deref();
+                let add_requested = &add_requested;
+                let remove_requested = &remove_requested;
+                let blocklists_error = &self.blocklists_error;
+
+                move |ctx, _class| {
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        keep_open.set(false);
+                    }
+
+                    simple_window_frame(ctx, |ui| {
+                        render_blocklists_window_content(
+                            ui,
+                            ctx,
+                            blocking,
+                            response,
+                            path,
+                            counts,
+                            loaded,
+                            blocklists_error.clone(),
+                            |kind| {
+                                add_requested.set(Some(kind));
+                            },
+                            |kind, index| {
+                                remove_requested.set(Some((kind, index)));
+                            },
+                            || {
+                                keep_open.set(false);
+                            },
+                        );
+                    });
+                }
+            },
+        );
+        // The counters move while the window is open.
+        ctx.request_repaint_after(Duration::from_secs(1));
+
+        if response != self.settings.forwarder.blocking.response {
+            self.set_block_response(response);
+        }
+
+        let mut changed = false;
+        if let Some(kind) = add_requested.get() {
+            let path = self.new_list_path.trim().to_string();
+            if path.is_empty() {
+                self.blocklists_error = Some("Enter the path of a list".to_string());
+            } else if !std::path::Path::new(&path).is_file() {
+                self.blocklists_error = Some(format!("There is no file at {}", path));
+            } else {
+                let blocking = &mut self.settings.forwarder.blocking;
+                match kind {
+                    ListKind::Block => blocking.blocklists.push(path),
+                    ListKind::Allow => blocking.allowlists.push(path),
+                }
+                self.new_list_path.clear();
+                changed = true;
+            }
+        }
+        if let Some((kind, index)) = remove_requested.get() {
+            let blocking = &mut self.settings.forwarder.blocking;
+            match kind {
+                ListKind::Block => blocking.blocklists.remove(index),
+                ListKind::Allow => blocking.allowlists.remove(index),
+            };
+            changed = true;
+        }
+        if changed {
+            self.blocklists_error = match save_settings(&self.settings) {
+                Ok(()) => None,
+                Err(e) => Some(format!("Failed to save settings: {}", e)),
+            };
+            self.reload_blocklists();
+        }
+
+        self.show_blocklists_window = keep_open.get();
+        if !self.show_blocklists_window {
+            self.new_list_path.clear();
+            self.blocklists_error = None;
         }
     }
 
@@ -283,7 +1577,7 @@ impl MyApp {
         }
 
         let keep_open = std::cell::Cell::new(true);
-        let window_size = egui::vec2(300.0, 300.0);
+        let window_size = egui::vec2(300.0, 510.0);
         let screen_center = ctx.input(|i| {
             let info = i.viewport();
             info.outer_rect
@@ -308,8 +1602,10 @@ impl MyApp {
             {
                 let keep_open = &keep_open;
                 let name = &mut self.new_dns_name;
-                let primary = &mut self.new_dns_primary;
-                let secondary = &mut self.new_dns_secondary;
+                let servers = &mut self.new_dns_servers;
+                let servers_v6 = &mut self.new_dns_servers_v6;
+                let doh_url = &mut self.new_dns_doh_url;
+                let tls_name = &mut self.new_dns_tls_name;
                 let save_requested = &save_requested;
                 let should_close = &should_close;
                 let add_dns_error = &self.add_dns_error;
@@ -324,8 +1620,10 @@ impl MyApp {
                             ui,
                             ctx,
                             name,
-                            primary,
-                            secondary,
+                            servers,
+                            servers_v6,
+                            doh_url,
+                            tls_name,
                             add_dns_error.clone(),
                             || {
                                 save_requested.set(true);
@@ -345,10 +1643,12 @@ impl MyApp {
 
         if save_requested.get() {
             let name_valid = !self.new_dns_name.trim().is_empty();
-            let primary_valid = !self.new_dns_primary.trim().is_empty()
-                && crate::ui::is_valid_ip(&self.new_dns_primary);
-            let secondary_valid = !self.new_dns_secondary.trim().is_empty()
-                && crate::ui::is_valid_ip(&self.new_dns_secondary);
+            let servers =
+                ServerList::new(self.new_dns_servers.iter().chain(&self.new_dns_servers_v6));
+            let doh_url =
+                Some(self.new_dns_doh_url.trim().to_string()).filter(|url| !url.is_empty());
+            let tls_name =
+                Some(self.new_dns_tls_name.trim().to_string()).filter(|name| !name.is_empty());
 
             let name_trimmed = self.new_dns_name.trim();
             let name_exists = self
@@ -361,32 +1661,40 @@ impl MyApp {
                     "A DNS entry with the name '{}' already exists",
                     name_trimmed
                 ));
-            } else if name_valid && primary_valid && secondary_valid {
+            } else if !name_valid {
+                self.add_dns_error = Some("Please enter a name".to_string());
+            } else if let Err(e) = IpFamily::V4
+                .check(&self.new_dns_servers)
+                .and_then(|_| IpFamily::V6.check(&self.new_dns_servers_v6))
+                .and_then(|_| servers.validate())
+            {
+                self.add_dns_error = Some(e);
+            } else if let Some(Err(e)) = doh_url.as_deref().map(HttpsUrl::parse) {
+                self.add_dns_error = Some(e);
+            } else if let Some(Err(e)) = tls_name.as_deref().map(dns::tls_server_name) {
+                self.add_dns_error = Some(e);
+            } else {
                 self.add_dns_error = None;
                 let entry = SavedDnsEntry {
                     name: name_trimmed.to_string(),
-                    primary: self.new_dns_primary.trim().to_string(),
-                    secondary: self.new_dns_secondary.trim().to_string(),
+                    servers,
+                    doh_url,
+                    tls_name,
                 };
 
                 if let Err(e) = add_saved_dns(entry.clone()) {
                     self.add_dns_error = Some(format!("Failed to save DNS: {}", e));
                 } else {
                     self.saved_dns_entries.push(entry.clone());
-                    self.selected_provider = DnsProvider::saved(
-                        entry.name.clone(),
-                        entry.primary.clone(),
-                        entry.secondary.clone(),
-                    );
+                    self.selected_provider = DnsProvider::saved(&entry);
                     self.app_state = AppState::Success("DNS saved successfully!".to_string());
                     self.new_dns_name.clear();
-                    self.new_dns_primary.clear();
-                    self.new_dns_secondary.clear();
+                    self.new_dns_servers.clear();
+                    self.new_dns_servers_v6.clear();
+                    self.new_dns_doh_url.clear();
+                    self.new_dns_tls_name.clear();
                     should_close.set(true);
                 }
-            } else {
-                self.add_dns_error =
-                    Some("Please enter a valid name and DNS IP addresses".to_string());
             }
         }
 
@@ -398,8 +1706,10 @@ impl MyApp {
 
         if !self.show_add_dns_window {
             self.new_dns_name.clear();
-            self.new_dns_primary.clear();
-            self.new_dns_secondary.clear();
+            self.new_dns_servers.clear();
+            self.new_dns_servers_v6.clear();
+            self.new_dns_doh_url.clear();
+            self.new_dns_tls_name.clear();
             self.add_dns_error = None;
         }
     }
@@ -448,8 +1758,24 @@ impl eframe::App for MyApp {
             });
         }
 
+        if self.poll_keep_deadline(Instant::now()) {
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+
+        if let Some(receiver) = &self.undo_receiver {
+            if let Ok(snapshots) = receiver.try_recv() {
+                self.undo_snapshots = snapshots;
+                self.undo_receiver = None;
+            }
+        }
+
         if let Some(receiver) = &self.operation_receiver {
             if let Ok(result) = receiver.try_recv() {
+                if let Some(receiver) = self.undo_receiver.take() {
+                    if let Ok(snapshots) = receiver.try_recv() {
+                        self.undo_snapshots = snapshots;
+                    }
+                }
                 self.handle_operation_result(result);
                 self.operation_receiver = None;
                 self.operation_sender = None;
@@ -459,6 +1785,36 @@ impl eframe::App for MyApp {
             }
         }
 
+        if let Some(receiver) = &self.adapters_receiver {
+            if let Ok(adapters) = receiver.try_recv() {
+                self.adapters = adapters;
+                self.adapters_receiver = None;
+                ctx.request_repaint();
+            }
+        }
+
+        if let Some(receiver) = &self.benchmark_receiver {
+            loop {
+                match receiver.try_recv() {
+                    Ok(stats) => self.benchmark_results.push(stats),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        self.benchmark_receiver = None;
+                        break;
+                    }
+                }
+            }
+            benchmark::sort(
+                &mut self.benchmark_results,
+                self.benchmark_sort,
+                !self.benchmark_descending,
+            );
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        self.run_auto_select();
+        self.poll_blocklists();
+
         if let Some(ping_rx) = &self.ping_receiver {
             if let Ok(ping) = ping_rx.try_recv() {
                 self.ping_value = ping;
@@ -490,13 +1846,58 @@ impl eframe::App for MyApp {
                         ui.vertical(|ui| {
                             ui.add_space(12.0);
                             let mut test_dns = false;
-                            render_status_section(ui, &dns_state, &self.app_state, || {
-                                test_dns = true;
-                            });
+                            let mut check_interception = false;
+                            let mut undo = false;
+                            render_status_section(
+                                ui,
+                                &dns_state,
+                                &self.app_state,
+                                self.auto_status.as_deref(),
+                                || {
+                                    test_dns = true;
+                                },
+                                || {
+                                    check_interception = true;
+                                },
+                                (!self.undo_snapshots.is_empty()).then_some(|| {
+                                    undo = true;
+                                }),
+                            );
                             if test_dns {
                                 self.handle_operation(DnsOperation::Test);
                             }
+                            if check_interception {
+                                self.handle_operation(DnsOperation::CheckInterception);
+                            }
+                            if undo {
+                                self.handle_operation(DnsOperation::Undo(
+                                    self.undo_snapshots.clone(),
+                                ));
+                            }
                             render_app_state(ui, &self.app_state);
+                            if self.keep_deadline.is_some() {
+                                let mut keep = false;
+                                let mut revert = false;
+                                render_keep_changes(
+                                    ui,
+                                    || {
+                                        keep = true;
+                                    },
+                                    || {
+                                        revert = true;
+                                    },
+                                );
+                                if keep {
+                                    self.keep_deadline = None;
+                                    self.app_state =
+                                        AppState::Success("DNS settings kept".to_string());
+                                }
+                                if revert {
+                                    self.handle_operation(DnsOperation::Undo(
+                                        self.undo_snapshots.clone(),
+                                    ));
+                                }
+                            }
                         });
                         if ui
                             .ctx()
@@ -524,9 +1925,29 @@ impl eframe::App for MyApp {
                                             .color(egui::Color32::WHITE)
                                             .size(18.0),
                                     );
+                                    let mut target_changed = None;
+                                    let mut refresh_adapters = false;
+                                    render_adapter_selection(
+                                        ui,
+                                        &self.adapters,
+                                        &self.settings.adapter_target,
+                                        self.adapters_receiver.is_some(),
+                                        |target| {
+                                            target_changed = Some(target);
+                                        },
+                                        || {
+                                            refresh_adapters = true;
+                                        },
+                                    );
+                                    if let Some(target) = target_changed {
+                                        self.set_adapter_target(target);
+                                    }
+                                    if refresh_adapters {
+                                        self.refresh_adapters();
+                                    }
+
                                     let selected_provider = self.selected_provider.clone();
-                                    let custom_primary = self.custom_primary.clone();
-                                    let custom_secondary = self.custom_secondary.clone();
+                                    let custom_provider = self.custom_provider();
                                     let mut provider_changed = None;
                                     let mut open_custom = false;
 
@@ -535,8 +1956,7 @@ impl eframe::App for MyApp {
                                     render_provider_selection(
                                         ui,
                                         &selected_provider,
-                                        &custom_primary,
-                                        &custom_secondary,
+                                        &custom_provider,
                                         &saved_entries,
                                         |provider| {
                                             provider_changed = Some(provider);
@@ -592,6 +2012,45 @@ impl eframe::App for MyApp {
                                 delete_callback,
                             );
 
+                            let mut dry_run = self.settings.dry_run;
+                            if render_dry_run_toggle(ui, &mut dry_run) {
+                                self.set_dry_run(dry_run);
+                            }
+
+                            let mut forwarding = self.forwarder.is_some();
+                            let status = self.forwarder_status();
+                            let status = status
+                                .as_ref()
+                                .map(|(summary, health)| (summary.as_str(), health.as_str()));
+                            let mut open_rules = false;
+                            let mut open_blocklists = false;
+                            let mut open_query_log = false;
+                            if render_forwarder_toggle(
+                                ui,
+                                &mut forwarding,
+                                status,
+                                || {
+                                    open_rules = true;
+                                },
+                                || {
+                                    open_blocklists = true;
+                                },
+                                || {
+                                    open_query_log = true;
+                                },
+                            ) {
+                                self.set_forwarder(forwarding);
+                            }
+                            if open_rules {
+                                self.show_rules_window = true;
+                            }
+                            if open_blocklists {
+                                self.show_blocklists_window = true;
+                            }
+                            if open_query_log {
+                                self.show_query_log_window = true;
+                            }
+
                             if let Some(name) = delete_entry_name.take() {
                                 if let Err(e) = delete_saved_dns(&name) {
                                     self.app_state =
@@ -630,8 +2089,35 @@ impl eframe::App for MyApp {
             || {
                 PING_REQUEST.store(true, Ordering::SeqCst);
             },
+            || {
+                BENCHMARK_REQUEST.store(true, Ordering::SeqCst);
+            },
+            || {
+                COMPARE_REQUEST.store(true, Ordering::SeqCst);
+            },
+            || {
+                REACHABILITY_REQUEST.store(true, Ordering::SeqCst);
+            },
         );
 
+        if REACHABILITY_REQUEST.swap(false, Ordering::SeqCst) {
+            if !self.show_reachability_window {
+                self.reachability_hosts = self.settings.reachability.hosts.join("\n");
+            }
+            self.show_reachability_window = true;
+        }
+
+        if COMPARE_REQUEST.swap(false, Ordering::SeqCst) {
+            self.show_compare_window = true;
+        }
+
+        if BENCHMARK_REQUEST.swap(false, Ordering::SeqCst) {
+            if !self.show_benchmark_window {
+                self.benchmark_domains = self.settings.benchmark.domains.join("\n");
+            }
+            self.show_benchmark_window = true;
+        }
+
         if PING_REQUEST.swap(false, Ordering::SeqCst) {
             if self.ping_sender.is_none() {
                 let (tx, rx) = mpsc::channel::<f64>();
@@ -654,6 +2140,28 @@ impl eframe::App for MyApp {
         self.render_secondary_viewport(ctx);
         self.render_custom_dns_window(ctx);
         self.render_add_dns_window(ctx);
+        self.render_rules_window(ctx);
+        self.render_blocklists_window(ctx);
+        self.render_query_log_window(ctx);
+        self.render_benchmark_window(ctx);
+        self.render_compare_window(ctx);
+        self.render_reachability_window(ctx);
+
+        if self.show_plan_window {
+            let mut close = false;
+            egui::Window::new("Dry Run Plan")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    render_plan_window_content(ui, &self.last_plan, || {
+                        close = true;
+                    });
+                });
+            if close {
+                self.show_plan_window = false;
+            }
+        }
 
         if self.show_clear_confirmation {
             use ui_colors::{BUTTON_SUCCESS, BUTTON_TEXT};
@@ -729,3 +2237,54 @@ fn get_ping() -> f64 {
         Err(_) => 0.0,
     }
 }
+
+/// Applies `servers` to `targets` and, if enabled, checks that they actually
+/// resolve. Servers that fail the check are reverted straight away.
+fn set_and_verify(
+    backend: &dyn DnsBackend,
+    targets: &[String],
+    servers: &[String],
+    health_check: &HealthCheckSettings,
+) -> (OperationResult, Vec<DnsSnapshot>) {
+    let (result, snapshots) = apply_with_rollback(backend, targets, |adapter| {
+        backend.set_dns(adapter, servers)
+    });
+    if !health_check.enabled || snapshots.is_empty() {
+        return (result, snapshots);
+    }
+
+    let timeout = Duration::from_secs(health_check.timeout_secs);
+    match health::check(servers, &health_check.domains, timeout) {
+        Ok(()) => (result, snapshots),
+        Err(e) => {
+            log::warn!("{}", e);
+            let result = match restore_all(backend, &snapshots) {
+                OperationResult::Error(restore) => {
+                    OperationResult::Error(format!("{}\nReverting also failed: {}", e, restore))
+                }
+                _ => OperationResult::Warning(format!("{}; previous DNS settings restored", e)),
+            };
+            (result, Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_test_during_the_countdown_still_reverts() {
+        let mut app = MyApp::with_backend(SharedBackend::default());
+        let now = Instant::now();
+        app.undo_snapshots = vec![DnsSnapshot::new("Ethernet", DnsState::Dhcp)];
+        app.keep_deadline = Some(now + Duration::from_secs(10));
+
+        app.handle_operation(DnsOperation::Test);
+        assert!(app.poll_keep_deadline(now));
+
+        assert!(!app.poll_keep_deadline(now + Duration::from_secs(10)));
+        assert!(app.reverting_unconfirmed);
+        assert!(matches!(app.app_state, AppState::Processing));
+    }
+}
@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use crate::benchmark::ServerStats;
+use crate::domain::{AutoSelectSettings, DnsProvider};
+
+/// A provider worth switching to and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Switch {
+    pub provider: DnsProvider,
+    pub reason: String,
+}
+
+/// Lower is better: the median latency in milliseconds plus a penalty for
+/// lost or failed queries. `None` when the server never answered.
+pub fn score(stats: &ServerStats, settings: &AutoSelectSettings) -> Option<f64> {
+    let median = stats.median?;
+    Some(median.as_secs_f64() * 1000.0 + stats.failure_rate() * settings.failure_penalty_ms as f64)
+}
+
+/// Every provider that answered at all with the score of its best server,
+/// best first.
+pub fn provider_scores(
+    stats: &[ServerStats],
+    settings: &AutoSelectSettings,
+) -> Vec<(DnsProvider, f64)> {
+    let mut scores: Vec<(DnsProvider, f64)> = Vec::new();
+    for server in stats {
+        let Some(score) = score(server, settings) else {
+            continue;
+        };
+        match scores.iter_mut().find(|(p, _)| *p == server.provider) {
+            Some((_, best)) => *best = best.min(score),
+            None => scores.push((server.provider.clone(), score)),
+        }
+    }
+    scores.sort_by(|a, b| a.1.total_cmp(&b.1));
+    scores
+}
+
+/// Decides whether to leave `current`, which was picked `since_switch` ago.
+///
+/// A provider that stopped answering is replaced straight away. Otherwise the
+/// best provider has to beat the current one by the hysteresis margin, and
+/// only once the dwell time is over.
+pub fn decide(
+    current: Option<&DnsProvider>,
+    since_switch: Duration,
+    scores: &[(DnsProvider, f64)],
+    settings: &AutoSelectSettings,
+) -> Option<Switch> {
+    let (best, best_score) = scores.first()?;
+    let Some(current) = current else {
+        return Some(Switch {
+            provider: best.clone(),
+            reason: format!(
+                "{} is the fastest working provider ({:.0} ms)",
+                best.display_name(),
+                best_score
+            ),
+        });
+    };
+    if current == best {
+        return None;
+    }
+
+    let Some((_, current_score)) = scores.iter().find(|(p, _)| p == current) else {
+        return Some(Switch {
+            provider: best.clone(),
+            reason: format!(
+                "{} stopped answering, switched to {} ({:.0} ms)",
+                current.display_name(),
+                best.display_name(),
+                best_score
+            ),
+        });
+    };
+    if since_switch < Duration::from_secs(settings.dwell_secs) {
+        return None;
+    }
+
+    let margin = 1.0 - settings.hysteresis_percent as f64 / 100.0;
+    (*best_score < current_score * margin).then(|| Switch {
+        provider: best.clone(),
+        reason: format!(
+            "{} scored {:.0} ms against {:.0} ms for {}",
+            best.display_name(),
+            best_score,
+            current_score,
+            current.display_name()
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Upstream;
+
+    fn stats(provider: &DnsProvider, median_ms: Option<u64>, timeouts: usize) -> ServerStats {
+        ServerStats {
+            provider: provider.clone(),
+            server: Upstream::Udp("192.0.2.1:53".parse().unwrap()),
+            queries: 10,
+            min: median_ms.map(Duration::from_millis),
+            median: median_ms.map(Duration::from_millis),
+            p95: median_ms.map(Duration::from_millis),
+            timeouts,
+            errors: 0,
+            last_error: None,
+        }
+    }
+
+    fn settings() -> AutoSelectSettings {
+        AutoSelectSettings {
+            enabled: true,
+            dwell_secs: 600,
+            hysteresis_percent: 20,
+            failure_penalty_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    const SETTLED: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn failures_weigh_against_latency() {
+        let (electro, quad9) = (DnsProvider::electro(), DnsProvider::quad9());
+
+        let scores = provider_scores(
+            &[
+                stats(&electro, Some(20), 2),
+                stats(&quad9, Some(60), 0),
+                stats(&quad9, Some(40), 0),
+                stats(&DnsProvider::radar(), None, 10),
+            ],
+            &settings(),
+        );
+
+        assert_eq!(scores, [(quad9, 40.0), (electro, 220.0)]);
+    }
+
+    #[test]
+    fn first_pick_takes_the_best() {
+        let scores = [(DnsProvider::quad9(), 30.0)];
+
+        let switch = decide(None, Duration::ZERO, &scores, &settings()).unwrap();
+
+        assert_eq!(switch.provider, DnsProvider::quad9());
+        assert_eq!(
+            switch.reason,
+            "Quad9 is the fastest working provider (30 ms)"
+        );
+    }
+
+    #[test]
+    fn small_improvements_do_not_switch() {
+        let current = DnsProvider::electro();
+        let scores = [(DnsProvider::quad9(), 85.0), (current.clone(), 100.0)];
+
+        assert_eq!(decide(Some(&current), SETTLED, &scores, &settings()), None);
+
+        let scores = [(DnsProvider::quad9(), 79.0), (current.clone(), 100.0)];
+        let switch = decide(Some(&current), SETTLED, &scores, &settings()).unwrap();
+        assert_eq!(
+            switch.reason,
+            "Quad9 scored 79 ms against 100 ms for Electro"
+        );
+    }
+
+    #[test]
+    fn dwell_time_holds_until_the_current_provider_fails() {
+        let current = DnsProvider::electro();
+        let recently = Duration::from_secs(60);
+
+        let scores = [(DnsProvider::quad9(), 10.0), (current.clone(), 100.0)];
+        assert_eq!(decide(Some(&current), recently, &scores, &settings()), None);
+
+        let scores = [(DnsProvider::quad9(), 10.0)];
+        let switch = decide(Some(&current), recently, &scores, &settings()).unwrap();
+        assert_eq!(switch.provider, DnsProvider::quad9());
+        assert_eq!(
+            switch.reason,
+            "Electro stopped answering, switched to Quad9 (10 ms)"
+        );
+    }
+
+    #[test]
+    fn staying_on_the_best_provider_is_not_a_switch() {
+        let current = DnsProvider::quad9();
+        let scores = [(current.clone(), 10.0)];
+
+        assert_eq!(decide(Some(&current), SETTLED, &scores, &settings()), None);
+        assert_eq!(decide(None, SETTLED, &[], &settings()), None);
+    }
+}
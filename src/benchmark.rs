@@ -0,0 +1,446 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{DotConnection, QueryError, Rcode, RecordType, Response, Upstream};
+use crate::domain::{BenchmarkSettings, CacheMode, DnsProvider, DohMethod, SavedDnsEntry};
+
+/// Servers that lose more than this share of their queries are never picked
+/// as the fastest, however quick their answers are.
+const MAX_FAILURE_RATE: f64 = 0.1;
+
+static COLD_LABEL: AtomicU32 = AtomicU32::new(0);
+
+/// One server of one provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub provider: DnsProvider,
+    pub server: Upstream,
+}
+
+/// Every server of every built-in provider followed by the saved entries.
+/// Providers with DoH or DoT endpoints get them as more servers.
+pub fn targets(saved: &[SavedDnsEntry], doh_method: DohMethod) -> Vec<Target> {
+    DnsProvider::built_in()
+        .into_iter()
+        .chain(saved.iter().map(DnsProvider::saved))
+        .flat_map(|provider| {
+            let mut upstreams = Upstream::from_servers(provider.servers());
+            upstreams.extend(Upstream::encrypted(&provider, doh_method));
+            upstreams
+                .into_iter()
+                .map(|server| Target {
+                    provider: provider.clone(),
+                    server,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// What one server did during a benchmark run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    pub provider: DnsProvider,
+    pub server: Upstream,
+    pub queries: usize,
+    pub min: Option<Duration>,
+    pub median: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub timeouts: usize,
+    /// Replies other than NOERROR/NXDOMAIN and queries that failed outright.
+    pub errors: usize,
+    pub last_error: Option<String>,
+}
+
+impl ServerStats {
+    pub fn timeout_rate(&self) -> f64 {
+        rate(self.timeouts, self.queries)
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        rate(self.timeouts + self.errors, self.queries)
+    }
+}
+
+fn rate(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Benchmarks every target in parallel and sends each server's stats as soon
+/// as that server is done.
+pub fn run(targets: Vec<Target>, settings: &BenchmarkSettings, results: mpsc::Sender<ServerStats>) {
+    thread::scope(|scope| {
+        for target in targets {
+            let results = results.clone();
+            scope.spawn(move || {
+                let _ = results.send(benchmark_server(target, settings));
+            });
+        }
+    });
+}
+
+fn benchmark_server(target: Target, settings: &BenchmarkSettings) -> ServerStats {
+    let timeout = Duration::from_millis(settings.timeout_ms);
+    let mut session = Session {
+        server: &target.server,
+        connection: None,
+    };
+    if settings.cache_mode == CacheMode::Warm {
+        session.resolve(&settings.domains, timeout);
+    }
+
+    let mut latencies = Vec::new();
+    let mut timeouts = 0;
+    let mut errors = 0;
+    let mut last_error = None;
+    for _ in 0..settings.rounds {
+        let names: Vec<String> = settings
+            .domains
+            .iter()
+            .map(|domain| match settings.cache_mode {
+                CacheMode::Warm => domain.clone(),
+                CacheMode::Cold => cold_name(domain),
+            })
+            .collect();
+        for (name, result) in names.iter().zip(session.resolve(&names, timeout)) {
+            match result {
+                Ok(response) => {
+                    let rcode = response.message.rcode();
+                    // Random cold-cache names usually do not exist, which is
+                    // still a complete answer.
+                    if rcode == Rcode::NOERROR || rcode == Rcode::NXDOMAIN {
+                        latencies.push(response.latency);
+                    } else {
+                        errors += 1;
+                        last_error = Some(format!("{} for {}", rcode, name));
+                    }
+                }
+                Err(QueryError::Timeout(_)) => timeouts += 1,
+                Err(QueryError::Certificate(e) | QueryError::Failed(e)) => {
+                    errors += 1;
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    latencies.sort();
+    ServerStats {
+        provider: target.provider,
+        server: target.server,
+        queries: settings.rounds * settings.domains.len(),
+        min: latencies.first().copied(),
+        median: percentile(&latencies, 0.5),
+        p95: percentile(&latencies, 0.95),
+        timeouts,
+        errors,
+        last_error,
+    }
+}
+
+/// Sends the queries of one round. DoT keeps its connection between rounds
+/// and pipelines each round over it; other upstreams ask one name at a time.
+struct Session<'a> {
+    server: &'a Upstream,
+    connection: Option<DotConnection>,
+}
+
+impl Session<'_> {
+    fn resolve(
+        &mut self,
+        names: &[String],
+        timeout: Duration,
+    ) -> Vec<Result<Response, QueryError>> {
+        let Upstream::Tls { server, name } = self.server else {
+            return names
+                .iter()
+                .map(|name| self.server.resolve(name, RecordType::A, timeout))
+                .collect();
+        };
+
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => match DotConnection::open(*server, name, timeout) {
+                Ok(connection) => connection,
+                Err(e) => return vec![Err(e); names.len()],
+            },
+        };
+        let results = connection.pipeline(names, RecordType::A, timeout);
+        // After a failure the stream may be out of step, so the next round
+        // starts over on a fresh connection.
+        if results.iter().all(Result::is_ok) {
+            self.connection = Some(connection);
+        }
+        results
+    }
+}
+
+/// A name under `domain` that no resolver has cached yet.
+fn cold_name(domain: &str) -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let counter = COLD_LABEL.fetch_add(1, AtomicOrdering::Relaxed);
+    format!("dnsight-{:08x}{:04x}.{}", nanos, counter & 0xffff, domain)
+}
+
+/// Nearest-rank percentile of already sorted samples.
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// The server with the lowest median among those that answered reliably.
+pub fn fastest(stats: &[ServerStats]) -> Option<&ServerStats> {
+    stats
+        .iter()
+        .filter(|s| s.median.is_some() && s.failure_rate() <= MAX_FAILURE_RATE)
+        .min_by_key(|s| s.median)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortColumn {
+    Provider,
+    Server,
+    Min,
+    #[default]
+    Median,
+    P95,
+    Timeouts,
+    Errors,
+}
+
+/// Sorts the table by `column`. Servers without a single answer always end
+/// up last, whichever way the latency columns are sorted.
+pub fn sort(stats: &mut [ServerStats], column: SortColumn, ascending: bool) {
+    stats.sort_by(|a, b| {
+        let ordering = match column {
+            SortColumn::Provider => a.provider.display_name().cmp(&b.provider.display_name()),
+            SortColumn::Server => a.server.cmp(&b.server),
+            SortColumn::Min => return compare_latency(a.min, b.min, ascending),
+            SortColumn::Median => return compare_latency(a.median, b.median, ascending),
+            SortColumn::P95 => return compare_latency(a.p95, b.p95, ascending),
+            SortColumn::Timeouts => a.timeout_rate().total_cmp(&b.timeout_rate()),
+            SortColumn::Errors => a.errors.cmp(&b.errors),
+        };
+        if ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    });
+}
+
+fn compare_latency(a: Option<Duration>, b: Option<Duration>, ascending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if ascending => a.cmp(&b),
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{testing, Message};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::{Arc, Mutex};
+
+    /// Local UDP resolver that answers every query after `delay` and drops
+    /// every `drop_every`-th one (never when zero). Records the names asked.
+    fn stand_in(delay: Duration, drop_every: usize) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let seen = names.clone();
+        let mut received = 0;
+        let address = testing::resolver(move |query| {
+            received += 1;
+            seen.lock().unwrap().push(query.questions[0].name.clone());
+            if drop_every > 0 && received % drop_every == 0 {
+                return Vec::new();
+            }
+            thread::sleep(delay);
+            vec![Message::reply_to(query, Rcode::NOERROR)]
+        });
+        (address, names)
+    }
+
+    fn target(name: &str, server: SocketAddr) -> Target {
+        Target {
+            provider: DnsProvider::Saved {
+                name: name.to_string(),
+                servers: Default::default(),
+                doh_url: None,
+                tls_name: None,
+            },
+            server: Upstream::Udp(server),
+        }
+    }
+
+    fn settings(cache_mode: CacheMode) -> BenchmarkSettings {
+        BenchmarkSettings {
+            domains: vec!["example.com".to_string(), "example.org".to_string()],
+            rounds: 5,
+            timeout_ms: 300,
+            cache_mode,
+        }
+    }
+
+    fn benchmark(targets: Vec<Target>, settings: &BenchmarkSettings) -> Vec<ServerStats> {
+        let (sender, receiver) = mpsc::channel();
+        run(targets, settings, sender);
+        receiver.iter().collect()
+    }
+
+    #[test]
+    fn reports_latency_and_loss_per_server() {
+        let (fast, _) = stand_in(Duration::ZERO, 0);
+        let (slow, _) = stand_in(Duration::from_millis(40), 0);
+        let (lossy, _) = stand_in(Duration::ZERO, 2);
+
+        let mut stats = benchmark(
+            vec![
+                target("Slow", slow),
+                target("Fast", fast),
+                target("Lossy", lossy),
+            ],
+            &settings(CacheMode::Cold),
+        );
+        sort(&mut stats, SortColumn::Provider, true);
+
+        let [fast, lossy, slow] = &stats[..] else {
+            panic!("expected three results, got {:?}", stats);
+        };
+        assert_eq!(fast.queries, 10);
+        assert_eq!((fast.timeouts, fast.errors), (0, 0));
+        assert!(fast.median < Some(Duration::from_millis(40)));
+        assert!(slow.min >= Some(Duration::from_millis(40)));
+        assert!(slow.p95 >= slow.median && slow.median >= slow.min);
+        assert_eq!(lossy.timeouts, 5);
+        assert_eq!(lossy.timeout_rate(), 0.5);
+        assert_eq!(fastest(&stats).map(|s| &s.server), Some(&fast.server));
+    }
+
+    #[test]
+    fn cold_mode_never_repeats_a_name() {
+        let (server, names) = stand_in(Duration::ZERO, 0);
+
+        benchmark(vec![target("Cold", server)], &settings(CacheMode::Cold));
+
+        let mut names = names.lock().unwrap().clone();
+        assert_eq!(names.len(), 10);
+        assert!(names
+            .iter()
+            .all(|n| n.ends_with(".example.com") || n.ends_with(".example.org")));
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 10);
+    }
+
+    #[test]
+    fn warm_mode_primes_each_domain_without_timing_it() {
+        let (server, names) = stand_in(Duration::ZERO, 0);
+
+        let stats = benchmark(vec![target("Warm", server)], &settings(CacheMode::Warm));
+
+        assert_eq!(stats[0].queries, 10);
+        let names = names.lock().unwrap();
+        assert_eq!(names.len(), 12);
+        assert_eq!(names[..2], ["example.com", "example.org"]);
+    }
+
+    #[test]
+    fn silent_servers_sort_last_and_are_never_fastest() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (server, _) = stand_in(Duration::ZERO, 0);
+        let mut settings = settings(CacheMode::Warm);
+        settings.rounds = 1;
+        settings.timeout_ms = 100;
+
+        let mut stats = benchmark(
+            vec![
+                target("Silent", silent.local_addr().unwrap()),
+                target("Up", server),
+            ],
+            &settings,
+        );
+
+        for ascending in [true, false] {
+            sort(&mut stats, SortColumn::Median, ascending);
+            assert_eq!(stats[1].provider.display_name(), "Silent");
+        }
+        assert_eq!(stats[1].timeouts, 2);
+        assert_eq!(stats[1].median, None);
+        assert_eq!(fastest(&stats).unwrap().server, Upstream::Udp(server));
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let samples: Vec<Duration> = (1..=20).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&samples, 0.5), Some(Duration::from_millis(10)));
+        assert_eq!(percentile(&samples, 0.95), Some(Duration::from_millis(19)));
+        assert_eq!(
+            percentile(&samples[..1], 0.95),
+            Some(Duration::from_millis(1))
+        );
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn targets_cover_built_in_and_saved_providers() {
+        let saved = SavedDnsEntry {
+            name: "Home".to_string(),
+            servers: crate::domain::ServerList::new(["192.168.1.1"]),
+            doh_url: Some("https://router.home/dns-query".to_string()),
+            tls_name: Some("router.home".to_string()),
+        };
+        let udp = |server: &str| Upstream::Udp(server.parse().unwrap());
+
+        let targets = targets(&[saved], DohMethod::Post);
+
+        assert_eq!(targets[0].server, udp("78.157.42.100:53"));
+        assert!(targets.iter().any(|t| t.server == udp("[2620:fe::fe]:53")));
+        assert!(targets.iter().any(|t| t.server
+            == Upstream::Https {
+                url: "https://dns.quad9.net/dns-query".to_string(),
+                method: DohMethod::Post,
+            }));
+        assert!(targets.iter().any(|t| t.server
+            == Upstream::Tls {
+                server: "9.9.9.9:853".parse().unwrap(),
+                name: "dns.quad9.net".to_string(),
+            }));
+        let home: Vec<&Upstream> = targets
+            .iter()
+            .filter(|t| t.provider.display_name() == "Home")
+            .map(|t| &t.server)
+            .collect();
+        assert_eq!(
+            home,
+            [
+                &udp("192.168.1.1:53"),
+                &Upstream::Https {
+                    url: "https://router.home/dns-query".to_string(),
+                    method: DohMethod::Post,
+                },
+                &Upstream::Tls {
+                    server: "192.168.1.1:853".parse().unwrap(),
+                    name: "router.home".to_string(),
+                }
+            ]
+        );
+    }
+}
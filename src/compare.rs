@@ -0,0 +1,386 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{self, QueryError, Rcode, RecordData, RecordType, DNS_PORT};
+use crate::domain::DnsProvider;
+
+/// How one provider answered the A and AAAA questions for a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The addresses it returned, sorted. Both lists may be empty when the
+    /// name exists without addresses.
+    Addresses {
+        v4: Vec<IpAddr>,
+        v6: Vec<IpAddr>,
+    },
+    /// Addresses that lead to a block page or nowhere instead of the
+    /// service, like the 10.10.34.x filtering pages.
+    Sinkhole(Vec<IpAddr>),
+    NxDomain,
+    /// Any other response code, such as SERVFAIL or REFUSED.
+    Error(Rcode),
+    /// None of the provider's servers answered.
+    Unreachable(String),
+}
+
+impl Outcome {
+    /// Outcomes of the same kind count as agreeing, except that address
+    /// lists also have to overlap.
+    fn kind(&self) -> u8 {
+        match self {
+            Outcome::Addresses { .. } => 0,
+            Outcome::Sinkhole(_) => 1,
+            Outcome::NxDomain => 2,
+            Outcome::Error(_) => 3,
+            Outcome::Unreachable(_) => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Addresses { v4, v6 } if v4.is_empty() && v6.is_empty() => {
+                f.write_str("No addresses")
+            }
+            Outcome::Addresses { .. } => f.write_str("OK"),
+            Outcome::Sinkhole(addresses) => write!(f, "Sinkhole {}", join(addresses)),
+            Outcome::NxDomain => f.write_str("NXDOMAIN"),
+            Outcome::Error(rcode) => write!(f, "{}", rcode),
+            Outcome::Unreachable(error) => write!(f, "No answer: {}", error),
+        }
+    }
+}
+
+/// One row of the comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderAnswer {
+    pub provider: DnsProvider,
+    /// The server that answered, when one did.
+    pub server: Option<SocketAddr>,
+    pub outcome: Outcome,
+    /// The answer disagrees with what most providers said.
+    pub divergent: bool,
+}
+
+/// Asks every provider for the A and AAAA records of `domain` in parallel
+/// and marks the answers that stand out. Rows keep the order of
+/// `providers`.
+pub fn compare(
+    providers: Vec<DnsProvider>,
+    domain: &str,
+    timeout: Duration,
+) -> Vec<ProviderAnswer> {
+    let targets = providers
+        .into_iter()
+        .map(|provider| {
+            let servers = provider
+                .servers()
+                .iter()
+                .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+                .collect();
+            (provider, servers)
+        })
+        .collect();
+    compare_servers(targets, domain, timeout)
+}
+
+fn compare_servers(
+    targets: Vec<(DnsProvider, Vec<SocketAddr>)>,
+    domain: &str,
+    timeout: Duration,
+) -> Vec<ProviderAnswer> {
+    let mut answers: Vec<ProviderAnswer> = thread::scope(|scope| {
+        let handles: Vec<_> = targets
+            .into_iter()
+            .map(|(provider, servers)| {
+                scope.spawn(move || {
+                    let (server, outcome) = ask(&servers, domain, timeout);
+                    ProviderAnswer {
+                        provider,
+                        server,
+                        outcome,
+                        divergent: false,
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    });
+    mark_divergent(&mut answers);
+    answers
+}
+
+/// Tries the servers in order until one answers both questions.
+fn ask(servers: &[SocketAddr], domain: &str, timeout: Duration) -> (Option<SocketAddr>, Outcome) {
+    let mut last_error = "no servers configured".to_string();
+    for server in servers {
+        match outcome(*server, domain, timeout) {
+            Ok(outcome) => return (Some(*server), outcome),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    (None, Outcome::Unreachable(last_error))
+}
+
+fn outcome(server: SocketAddr, domain: &str, timeout: Duration) -> Result<Outcome, QueryError> {
+    let mut addresses = [Vec::new(), Vec::new()];
+    for (qtype, found) in [RecordType::A, RecordType::Aaaa]
+        .into_iter()
+        .zip(&mut addresses)
+    {
+        let message = dns::query(server, domain, qtype, timeout)?.message;
+        match message.rcode() {
+            Rcode::NOERROR => {}
+            Rcode::NXDOMAIN => return Ok(Outcome::NxDomain),
+            other => return Ok(Outcome::Error(other)),
+        }
+        // CNAME chains come along in the answer section; only the
+        // addresses matter here.
+        found.extend(
+            message
+                .answers
+                .iter()
+                .filter_map(|record| match record.data {
+                    RecordData::A(ip) => Some(IpAddr::V4(ip)),
+                    RecordData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                    _ => None,
+                }),
+        );
+        found.sort();
+        found.dedup();
+    }
+
+    let [v4, v6] = addresses;
+    if v4.iter().chain(&v6).any(|ip| is_sinkhole(*ip)) {
+        return Ok(Outcome::Sinkhole(v4.into_iter().chain(v6).collect()));
+    }
+    Ok(Outcome::Addresses { v4, v6 })
+}
+
+/// Addresses no public service lives at: the national filtering range
+/// 10.10.34.0/24, and private, loopback, link-local or unspecified ones.
+pub fn is_sinkhole(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.octets()[..3] == [10, 10, 34]
+                || ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip == Ipv4Addr::BROADCAST
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+        }
+    }
+}
+
+/// Flags answers whose kind differs from the most common one among the
+/// providers that answered, and address lists that share nothing with any
+/// other provider's. CDNs hand out different addresses to different
+/// resolvers, so only a completely disjoint list stands out.
+fn mark_divergent(answers: &mut [ProviderAnswer]) {
+    let answered: Vec<&Outcome> = answers
+        .iter()
+        .map(|answer| &answer.outcome)
+        .filter(|outcome| !matches!(outcome, Outcome::Unreachable(_)))
+        .collect();
+    let mut counts = [0usize; 5];
+    for outcome in &answered {
+        counts[outcome.kind() as usize] += 1;
+    }
+    // Ties go to the earlier kind, so plain addresses win over a sinkhole.
+    let majority = (0..counts.len())
+        .rev()
+        .max_by_key(|&kind| counts[kind])
+        .unwrap_or(0) as u8;
+
+    let flags: Vec<bool> = answers
+        .iter()
+        .enumerate()
+        .map(|(index, answer)| match &answer.outcome {
+            Outcome::Unreachable(_) => false,
+            Outcome::Addresses { v4, v6 } if majority == 0 => {
+                let own: Vec<&IpAddr> = v4.iter().chain(v6).collect();
+                let others: Vec<&IpAddr> = answers
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .filter_map(|(_, other)| match &other.outcome {
+                        Outcome::Addresses { v4, v6 } => Some(v4.iter().chain(v6)),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect();
+                !own.is_empty() && !others.is_empty() && !own.iter().any(|ip| others.contains(ip))
+            }
+            outcome => outcome.kind() != majority,
+        })
+        .collect();
+    for (answer, divergent) in answers.iter_mut().zip(flags) {
+        answer.divergent = divergent;
+    }
+}
+
+pub fn join(addresses: &[IpAddr]) -> String {
+    if addresses.is_empty() {
+        return "—".to_string();
+    }
+    addresses
+        .iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{testing, Message, RecordData};
+    use std::net::UdpSocket;
+
+    /// Local resolver that answers A queries with `v4` and AAAA queries
+    /// with nothing, or with `rcode` alone when it is not NOERROR.
+    fn fake_resolver(rcode: Rcode, v4: &[[u8; 4]]) -> SocketAddr {
+        let v4: Vec<RecordData> = v4.iter().map(|&ip| RecordData::A(ip.into())).collect();
+        testing::resolver(move |query| {
+            let reply = if rcode != Rcode::NOERROR {
+                Message::reply_to(query, rcode)
+            } else if query.questions[0].qtype == RecordType::A {
+                testing::answer(query, 60, v4.clone())
+            } else {
+                testing::answer(query, 60, [])
+            };
+            vec![reply]
+        })
+    }
+
+    fn provider(name: &str) -> DnsProvider {
+        DnsProvider::Saved {
+            name: name.to_string(),
+            servers: Default::default(),
+            doh_url: None,
+            tls_name: None,
+        }
+    }
+
+    fn v4(octets: [u8; 4]) -> IpAddr {
+        IpAddr::from(octets)
+    }
+
+    #[test]
+    fn answers_are_classified_and_outliers_flagged() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let targets = vec![
+            (
+                provider("A"),
+                vec![fake_resolver(
+                    Rcode::NOERROR,
+                    &[[198, 51, 100, 1], [198, 51, 100, 2]],
+                )],
+            ),
+            (
+                provider("B"),
+                vec![fake_resolver(Rcode::NOERROR, &[[198, 51, 100, 2]])],
+            ),
+            (
+                provider("Filtered"),
+                vec![fake_resolver(Rcode::NOERROR, &[[10, 10, 34, 36]])],
+            ),
+            (
+                provider("Poisoned"),
+                vec![fake_resolver(Rcode::NOERROR, &[[203, 0, 113, 7]])],
+            ),
+            (
+                provider("Missing"),
+                vec![fake_resolver(Rcode::NXDOMAIN, &[])],
+            ),
+            (
+                provider("Broken"),
+                vec![fake_resolver(Rcode::SERVFAIL, &[])],
+            ),
+            (
+                provider("Fallback"),
+                vec![
+                    silent.local_addr().unwrap(),
+                    fake_resolver(Rcode::NOERROR, &[[198, 51, 100, 1]]),
+                ],
+            ),
+        ];
+
+        let answers = compare_servers(targets, "example.com", Duration::from_millis(300));
+
+        let rows: Vec<(String, String, bool)> = answers
+            .iter()
+            .map(|a| {
+                (
+                    a.provider.display_name(),
+                    a.outcome.to_string(),
+                    a.divergent,
+                )
+            })
+            .collect();
+        let row = |name: &str, outcome: &str, divergent| {
+            (name.to_string(), outcome.to_string(), divergent)
+        };
+        assert_eq!(
+            rows,
+            [
+                row("A", "OK", false),
+                row("B", "OK", false),
+                row("Filtered", "Sinkhole 10.10.34.36", true),
+                row("Poisoned", "OK", true),
+                row("Missing", "NXDOMAIN", true),
+                row("Broken", "SERVFAIL", true),
+                row("Fallback", "OK", false),
+            ]
+        );
+        assert_eq!(
+            answers[0].outcome,
+            Outcome::Addresses {
+                v4: vec![v4([198, 51, 100, 1]), v4([198, 51, 100, 2])],
+                v6: Vec::new(),
+            }
+        );
+        assert_ne!(answers[6].server, Some(silent.local_addr().unwrap()));
+    }
+
+    #[test]
+    fn unreachable_providers_are_not_divergent() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let targets = vec![
+            (provider("Up"), vec![fake_resolver(Rcode::NXDOMAIN, &[])]),
+            (provider("Down"), vec![silent.local_addr().unwrap()]),
+            (provider("Empty"), Vec::new()),
+        ];
+
+        let answers = compare_servers(targets, "missing.example", Duration::from_millis(200));
+
+        assert_eq!(answers[0].outcome, Outcome::NxDomain);
+        assert!(matches!(answers[1].outcome, Outcome::Unreachable(_)));
+        assert_eq!(
+            answers[2].outcome,
+            Outcome::Unreachable("no servers configured".to_string())
+        );
+        assert!(answers.iter().all(|a| !a.divergent));
+    }
+
+    #[test]
+    fn sinkhole_ranges() {
+        assert!(is_sinkhole(v4([10, 10, 34, 34])));
+        assert!(is_sinkhole(v4([0, 0, 0, 0])));
+        assert!(is_sinkhole(v4([127, 0, 0, 1])));
+        assert!(is_sinkhole("::".parse().unwrap()));
+        assert!(!is_sinkhole(v4([93, 184, 216, 34])));
+        assert!(!is_sinkhole("2606:2800:220:1::".parse().unwrap()));
+    }
+}
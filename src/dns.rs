@@ -0,0 +1,169 @@
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use crate::domain::{DnsProvider, DohMethod, OperationResult};
+
+mod client;
+mod doh;
+mod dot;
+mod https;
+#[cfg(test)]
+pub mod testing;
+mod wire;
+
+pub use client::{query, read_framed, write_framed, QueryError, Response, Transport};
+pub use dot::{tls_server_name, DotConnection, DOT_PORT};
+pub use https::{default_tls, head, HttpsUrl};
+pub use wire::{Message, Rcode, Record, RecordData, RecordType};
+
+pub const DNS_PORT: u16 = 53;
+
+/// Somewhere queries can be sent.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Upstream {
+    /// Plain DNS over UDP, falling back to TCP for truncated answers.
+    Udp(SocketAddr),
+    /// DNS-over-HTTPS.
+    Https { url: String, method: DohMethod },
+    /// DNS-over-TLS, checking the certificate against `name`.
+    Tls { server: SocketAddr, name: String },
+}
+
+impl Upstream {
+    /// Plain DNS upstreams for every server that is an IP address.
+    pub fn from_servers(servers: &[String]) -> Vec<Self> {
+        servers
+            .iter()
+            .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+            .map(|ip| Upstream::Udp(SocketAddr::new(ip, DNS_PORT)))
+            .collect()
+    }
+
+    /// The encrypted endpoints `provider` publishes: DoH, and DoT on its
+    /// first server.
+    pub fn encrypted(provider: &DnsProvider, doh_method: DohMethod) -> Vec<Self> {
+        let mut upstreams = Vec::new();
+        if let Some(url) = provider.doh_url() {
+            upstreams.push(Upstream::Https {
+                url: url.to_string(),
+                method: doh_method,
+            });
+        }
+        let first = provider
+            .servers()
+            .first()
+            .and_then(|s| s.trim().parse().ok());
+        if let (Some(name), Some(ip)) = (provider.tls_name(), first) {
+            upstreams.push(Upstream::Tls {
+                server: SocketAddr::new(ip, DOT_PORT),
+                name: name.to_string(),
+            });
+        }
+        upstreams
+    }
+
+    pub fn resolve(
+        &self,
+        name: &str,
+        qtype: RecordType,
+        timeout: Duration,
+    ) -> Result<Response, QueryError> {
+        match self {
+            Upstream::Udp(server) => query(*server, name, qtype, timeout),
+            Upstream::Https { url, method } => doh::query(url, *method, name, qtype, timeout),
+            Upstream::Tls {
+                server,
+                name: tls_name,
+            } => DotConnection::open(*server, tls_name, timeout)?.query(name, qtype, timeout),
+        }
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Upstream::Udp(server) if server.port() == DNS_PORT => write!(f, "{}", server.ip()),
+            Upstream::Udp(server) => write!(f, "{}", server),
+            Upstream::Https { url, .. } => f.write_str(url),
+            Upstream::Tls { server, name } => write!(f, "{} ({})", server, name),
+        }
+    }
+}
+
+/// How many answers a probe line lists before summarising the rest.
+const LISTED_ANSWERS: usize = 3;
+
+/// Resolves `domain` through every upstream in parallel and reports one line
+/// per upstream with the rcode, the answers and the latency.
+pub fn probe(upstreams: &[Upstream], domain: &str, timeout: Duration) -> OperationResult {
+    let lines: Vec<(bool, String)> = thread::scope(|scope| {
+        let handles: Vec<_> = upstreams
+            .iter()
+            .map(|upstream| scope.spawn(move || probe_upstream(upstream, domain, timeout)))
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    });
+
+    let resolved = lines.iter().filter(|(ok, _)| *ok).count();
+    let report = lines
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if resolved == upstreams.len() {
+        OperationResult::Success(report)
+    } else if resolved > 0 {
+        OperationResult::Warning(report)
+    } else {
+        OperationResult::Error(report)
+    }
+}
+
+fn probe_upstream(upstream: &Upstream, domain: &str, timeout: Duration) -> (bool, String) {
+    match upstream.resolve(domain, RecordType::A, timeout) {
+        Ok(response) => {
+            let message = &response.message;
+            let answers: Vec<String> = message
+                .answers
+                .iter()
+                .map(|record| record.data.to_string())
+                .collect();
+            let mut listed = answers
+                .iter()
+                .take(LISTED_ANSWERS)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            if answers.len() > LISTED_ANSWERS {
+                listed.push_str(&format!(" +{} more", answers.len() - LISTED_ANSWERS));
+            }
+            if listed.is_empty() {
+                listed = "no answers".to_string();
+            }
+
+            let transport = match response.transport {
+                Transport::Udp => "",
+                Transport::Tcp => " over TCP",
+                Transport::Https => "",
+                Transport::Tls => " over TLS, handshake and certificate OK",
+            };
+            (
+                message.rcode() == Rcode::NOERROR && !answers.is_empty(),
+                format!(
+                    "{}: {} in {} ms{} ({})",
+                    upstream,
+                    message.rcode(),
+                    response.latency.as_millis(),
+                    transport,
+                    listed
+                ),
+            )
+        }
+        Err(e) => (false, format!("{}: {}", upstream, e)),
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::dns::wire::{Message, RecordType};
+
+/// Largest reply accepted over UDP. Anything bigger arrives truncated and is
+/// fetched again over TCP.
+const UDP_BUFFER: usize = 4096;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Https,
+    Tls,
+}
+
+/// Why a query got no usable reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    /// The server stayed silent for the whole timeout.
+    Timeout(String),
+    /// The TLS certificate is not valid for the name that was asked for.
+    Certificate(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Timeout(message)
+            | QueryError::Certificate(message)
+            | QueryError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for QueryError {
+    fn from(message: String) -> Self {
+        QueryError::Failed(message)
+    }
+}
+
+impl From<QueryError> for String {
+    fn from(error: QueryError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A decoded reply together with how long it took to arrive.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub message: Message,
+    pub latency: Duration,
+    pub transport: Transport,
+}
+
+/// Resolves `name` against `server` over UDP, repeating the query over TCP
+/// when the UDP reply comes back truncated. `timeout` applies to each
+/// transport separately.
+pub fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: RecordType,
+    timeout: Duration,
+) -> Result<Response, QueryError> {
+    let query = Message::query(next_id(), name, qtype);
+    let bytes = query.encode()?;
+    let started = Instant::now();
+
+    let message = query_udp(server, &bytes, query.id, timeout)?;
+    if !message.truncated {
+        return Ok(Response {
+            message,
+            latency: started.elapsed(),
+            transport: Transport::Udp,
+        });
+    }
+
+    let message = query_tcp(server, &bytes, query.id, timeout)?;
+    Ok(Response {
+        message,
+        latency: started.elapsed(),
+        transport: Transport::Tcp,
+    })
+}
+
+pub(super) fn next_id() -> u16 {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default();
+    NEXT_ID.fetch_add(1, Ordering::Relaxed) ^ seed
+}
+
+fn query_udp(
+    server: SocketAddr,
+    bytes: &[u8],
+    id: u16,
+    timeout: Duration,
+) -> Result<Message, QueryError> {
+    let bind = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    socket.send_to(bytes, server).map_err(|e| e.to_string())?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; UDP_BUFFER];
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer).map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                timed_out(server, timeout)
+            }
+            _ => QueryError::Failed(e.to_string()),
+        })?;
+        // Ignore stray or spoofed datagrams instead of failing on them.
+        if from == server {
+            if let Ok(message) = Message::decode(&buffer[..len]) {
+                if message.id == id && message.response {
+                    return Ok(message);
+                }
+            }
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(timed_out(server, timeout));
+        }
+        socket
+            .set_read_timeout(Some(remaining))
+            .map_err(|e| e.to_string())?;
+    }
+}
+
+fn timed_out(server: SocketAddr, timeout: Duration) -> QueryError {
+    QueryError::Timeout(format!(
+        "{} did not answer within {} ms",
+        server,
+        timeout.as_millis()
+    ))
+}
+
+fn query_tcp(
+    server: SocketAddr,
+    bytes: &[u8],
+    id: u16,
+    timeout: Duration,
+) -> Result<Message, QueryError> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)
+        .map_err(|e| format!("TCP connection to {} failed: {}", server, e))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    let message = write_framed(&mut stream, bytes)
+        .and_then(|_| read_framed(&mut stream))
+        .map_err(|e| format!("TCP query to {} failed: {}", server, e))?;
+    let message = Message::decode(&message)?;
+    if message.id != id {
+        return Err(format!("{} answered a different query", server).into());
+    }
+    Ok(message)
+}
+
+/// Writes one message with the two-byte length prefix DNS uses over streams.
+pub fn write_framed(stream: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    framed.extend_from_slice(bytes);
+    stream.write_all(&framed)
+}
+
+/// Reads one length-prefixed message from a stream.
+pub fn read_framed(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing;
+    use crate::dns::wire::{Rcode, RecordData};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    fn answer(query: &Message, truncated: bool) -> Message {
+        if truncated {
+            let mut reply = Message::reply_to(query, Rcode::NOERROR);
+            reply.truncated = true;
+            reply
+        } else {
+            testing::answer(query, 60, [RecordData::A(Ipv4Addr::new(192, 0, 2, 1))])
+        }
+    }
+
+    /// UDP stand-in that first sends a reply with the wrong id, then the
+    /// real one, with the TC bit set when `truncate` is true.
+    fn udp_stand_in(socket: UdpSocket, truncate: bool) {
+        testing::serve(socket, move |query| {
+            let mut wrong = query.clone();
+            wrong.id = query.id.wrapping_add(1);
+            vec![answer(&wrong, false), answer(query, truncate)]
+        });
+    }
+
+    #[test]
+    fn udp_answer_skips_mismatched_ids() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        udp_stand_in(socket, false);
+
+        let response = query(server, "example.com", RecordType::A, Duration::from_secs(2)).unwrap();
+
+        assert_eq!(response.transport, Transport::Udp);
+        assert_eq!(response.message.rcode(), Rcode::NOERROR);
+        assert_eq!(response.message.answers[0].data.to_string(), "192.0.2.1");
+    }
+
+    #[test]
+    fn truncated_answer_is_retried_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        udp_stand_in(UdpSocket::bind(server).unwrap(), true);
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = Message::decode(&read_framed(&mut stream).unwrap()).unwrap();
+            write_framed(&mut stream, &answer(&query, false).encode().unwrap()).unwrap();
+        });
+
+        let response = query(server, "example.com", RecordType::A, Duration::from_secs(2)).unwrap();
+
+        assert_eq!(response.transport, Transport::Tcp);
+        assert_eq!(response.message.answers.len(), 1);
+    }
+
+    #[test]
+    fn silent_server_times_out() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+
+        let error = query(
+            server,
+            "example.com",
+            RecordType::A,
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            QueryError::Timeout(format!("{} did not answer within 100 ms", server))
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustls::ClientConfig;
+
+use crate::dns::https::{self, HttpsUrl};
+use crate::dns::wire::{Message, RecordType};
+use crate::dns::{QueryError, Response, Transport};
+use crate::domain::DohMethod;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Resolves `name` through the DNS-over-HTTPS endpoint at `url`.
+pub fn query(
+    url: &str,
+    method: DohMethod,
+    name: &str,
+    qtype: RecordType,
+    timeout: Duration,
+) -> Result<Response, QueryError> {
+    query_with(url, method, name, qtype, timeout, https::default_tls())
+}
+
+pub fn query_with(
+    url: &str,
+    method: DohMethod,
+    name: &str,
+    qtype: RecordType,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Result<Response, QueryError> {
+    let mut endpoint = HttpsUrl::parse(url)?;
+    // RFC 8484 asks for id 0 so identical queries are cacheable.
+    let bytes = Message::query(0, name, qtype).encode()?;
+    let started = Instant::now();
+
+    let response = match method {
+        DohMethod::Get => {
+            let separator = if endpoint.path.contains('?') {
+                '&'
+            } else {
+                '?'
+            };
+            endpoint.path = format!(
+                "{}{}dns={}",
+                endpoint.path,
+                separator,
+                URL_SAFE_NO_PAD.encode(&bytes)
+            );
+            https::request(
+                &endpoint,
+                "GET",
+                &[("Accept", DNS_MESSAGE)],
+                &[],
+                timeout,
+                tls,
+            )?
+        }
+        DohMethod::Post => https::request(
+            &endpoint,
+            "POST",
+            &[("Accept", DNS_MESSAGE), ("Content-Type", DNS_MESSAGE)],
+            &bytes,
+            timeout,
+            tls,
+        )?,
+    };
+
+    if response.status != 200 {
+        return Err(format!("{} answered HTTP {}", url, response.status).into());
+    }
+    let content_type = response.header("Content-Type").unwrap_or_default();
+    if !content_type.starts_with(DNS_MESSAGE) {
+        return Err(format!(
+            "{} answered with '{}' instead of a DNS message",
+            url, content_type
+        )
+        .into());
+    }
+    let message = Message::decode(&response.body)?;
+    if !message.response {
+        return Err(format!("{} did not send a DNS response", url).into());
+    }
+
+    Ok(Response {
+        message,
+        latency: started.elapsed(),
+        transport: Transport::Https,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing::certificate;
+    use crate::dns::wire::{Rcode, Record, RecordData};
+    use rustls::{ServerConnection, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// Local HTTPS resolver. Answers every DoH query with 192.0.2.1, or with
+    /// `status` and no body when that is not 200. Records each request line.
+    fn stand_in(status: u16) -> (String, Arc<ClientConfig>, Arc<Mutex<Vec<String>>>) {
+        let (server_config, client_config) = certificate();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/dns-query",
+            listener.local_addr().unwrap().port()
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let connection = ServerConnection::new(server_config.clone()).unwrap();
+                let mut tls = StreamOwned::new(connection, stream);
+                let Some((request_line, body)) = read_request(&mut tls) else {
+                    continue;
+                };
+                seen.lock().unwrap().push(request_line.clone());
+
+                let query = match request_line.split_once("?dns=") {
+                    Some((_, encoded)) => {
+                        let encoded = encoded.split(' ').next().unwrap();
+                        URL_SAFE_NO_PAD.decode(encoded).unwrap()
+                    }
+                    None => body,
+                };
+                let reply = if status == 200 {
+                    let query = Message::decode(&query).unwrap();
+                    let mut reply = Message::reply_to(&query, Rcode::NOERROR);
+                    reply.answers.push(Record {
+                        name: query.questions[0].name.clone(),
+                        rtype: RecordType::A,
+                        ttl: 300,
+                        data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                    });
+                    reply.encode().unwrap()
+                } else {
+                    Vec::new()
+                };
+
+                let head = format!(
+                    "HTTP/1.1 {} Test\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    DNS_MESSAGE,
+                    reply.len()
+                );
+                let _ = tls.write_all(head.as_bytes());
+                let _ = tls.write_all(&reply);
+                tls.conn.send_close_notify();
+                let _ = tls.flush();
+            }
+        });
+        (url, client_config, requests)
+    }
+
+    /// The request line and body, or `None` when the handshake failed.
+    fn read_request(stream: &mut impl Read) -> Option<(String, Vec<u8>)> {
+        let mut data = Vec::new();
+        let mut byte = [0u8; 1];
+        while !data.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).ok()?;
+            data.push(byte[0]);
+        }
+        let head = String::from_utf8(data).unwrap();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map(|length| length.parse().unwrap())
+            .unwrap_or(0);
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((head.lines().next()?.to_string(), body))
+    }
+
+    #[test]
+    fn get_sends_the_query_in_the_dns_parameter() {
+        let (url, tls, requests) = stand_in(200);
+
+        let response = query_with(
+            &url,
+            DohMethod::Get,
+            "example.com",
+            RecordType::A,
+            Duration::from_secs(5),
+            tls,
+        )
+        .unwrap();
+
+        assert_eq!(response.transport, Transport::Https);
+        assert_eq!(response.message.answers[0].data.to_string(), "192.0.2.1");
+        let request = requests.lock().unwrap()[0].clone();
+        // Id 0 and RD set: AAABAAABAAAAAAAA is the fixed header.
+        assert!(
+            request.starts_with("GET /dns-query?dns=AAABAAABAAAAAAAAB2V4YW1wbGUDY29tAAABAAE "),
+            "{}",
+            request
+        );
+    }
+
+    #[test]
+    fn post_sends_the_query_as_the_body() {
+        let (url, tls, requests) = stand_in(200);
+
+        let response = query_with(
+            &url,
+            DohMethod::Post,
+            "example.org",
+            RecordType::A,
+            Duration::from_secs(5),
+            tls,
+        )
+        .unwrap();
+
+        assert_eq!(response.message.questions[0].name, "example.org");
+        assert_eq!(requests.lock().unwrap()[0], "POST /dns-query HTTP/1.1");
+    }
+
+    #[test]
+    fn http_errors_are_reported() {
+        let (url, tls, _) = stand_in(415);
+
+        let error = query_with(
+            &url,
+            DohMethod::Post,
+            "example.com",
+            RecordType::A,
+            Duration::from_secs(5),
+            tls,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            QueryError::Failed(format!("{} answered HTTP 415", url))
+        );
+    }
+
+    #[test]
+    fn untrusted_certificates_are_rejected() {
+        let (url, _, requests) = stand_in(200);
+
+        let error = query(
+            &url,
+            DohMethod::Get,
+            "example.com",
+            RecordType::A,
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("certificate"), "{}", error);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use std::io::{ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+
+use crate::dns::client::{next_id, read_framed};
+use crate::dns::https;
+use crate::dns::wire::{Message, RecordType};
+use crate::dns::{QueryError, Response, Transport};
+
+pub const DOT_PORT: u16 = 853;
+
+/// Checks that `name` can be matched against a certificate.
+pub fn tls_server_name(name: &str) -> Result<ServerName<'static>, String> {
+    ServerName::try_from(name.trim().to_string())
+        .map_err(|_| format!("'{}' is not a valid TLS server name", name))
+}
+
+/// An open DNS-over-TLS connection (RFC 7858). It stays usable for further
+/// queries until one of them fails.
+pub struct DotConnection {
+    stream: StreamOwned<ClientConnection, TcpStream>,
+    server: SocketAddr,
+}
+
+impl DotConnection {
+    /// Connects to `server` and completes the TLS handshake, checking that
+    /// the certificate is valid for `tls_name`.
+    pub fn open(server: SocketAddr, tls_name: &str, timeout: Duration) -> Result<Self, QueryError> {
+        Self::open_with(server, tls_name, timeout, https::default_tls())
+    }
+
+    pub fn open_with(
+        server: SocketAddr,
+        tls_name: &str,
+        timeout: Duration,
+        tls: Arc<ClientConfig>,
+    ) -> Result<Self, QueryError> {
+        let socket = TcpStream::connect_timeout(&server, timeout).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => QueryError::Timeout(format!(
+                "{} did not accept a connection within {} ms",
+                server,
+                timeout.as_millis()
+            )),
+            _ => QueryError::Failed(format!("TLS connection to {} failed: {}", server, e)),
+        })?;
+        socket
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| socket.set_write_timeout(Some(timeout)))
+            .map_err(|e| e.to_string())?;
+
+        let name = tls_server_name(tls_name)?;
+        let connection = ClientConnection::new(tls, name).map_err(|e| e.to_string())?;
+        let mut stream = StreamOwned::new(connection, socket);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(|e| match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => QueryError::Timeout(format!(
+                        "TLS handshake with {} at {} timed out",
+                        tls_name, server
+                    )),
+                    _ => QueryError::Failed(format!(
+                        "TLS handshake with {} at {} failed: {}",
+                        tls_name, server, e
+                    )),
+                })?;
+        }
+
+        Ok(Self { stream, server })
+    }
+
+    pub fn query(
+        &mut self,
+        name: &str,
+        qtype: RecordType,
+        timeout: Duration,
+    ) -> Result<Response, QueryError> {
+        let names = [name.to_string()];
+        self.pipeline(&names, qtype, timeout)
+            .pop()
+            .expect("one result per query")
+    }
+
+    /// Sends every query before reading any answer. Answers may arrive in
+    /// any order and are matched by id; each latency runs from sending the
+    /// batch to that answer arriving.
+    pub fn pipeline(
+        &mut self,
+        names: &[String],
+        qtype: RecordType,
+        timeout: Duration,
+    ) -> Vec<Result<Response, QueryError>> {
+        let first_id = next_id();
+        let mut ids = Vec::with_capacity(names.len());
+        let mut bytes = Vec::new();
+        for (offset, name) in names.iter().enumerate() {
+            let id = first_id.wrapping_add(offset as u16);
+            match Message::query(id, name, qtype).encode() {
+                Ok(query) => {
+                    bytes.extend_from_slice(&(query.len() as u16).to_be_bytes());
+                    bytes.extend_from_slice(&query);
+                    ids.push(Some(id));
+                }
+                Err(_) => ids.push(None),
+            }
+        }
+
+        let mut results: Vec<Option<Result<Response, QueryError>>> = ids
+            .iter()
+            .zip(names)
+            .map(|(id, name)| {
+                id.is_none()
+                    .then(|| Err(format!("'{}' is not a valid domain name", name).into()))
+            })
+            .collect();
+
+        let started = Instant::now();
+        if let Err(e) = self
+            .stream
+            .write_all(&bytes)
+            .and_then(|_| self.stream.flush())
+        {
+            let error = self.io_error(e, timeout);
+            return fill(results, &error);
+        }
+
+        while results.iter().any(Option::is_none) {
+            let message = match read_framed(&mut self.stream) {
+                Ok(bytes) => Message::decode(&bytes),
+                Err(e) => {
+                    let error = self.io_error(e, timeout);
+                    return fill(results, &error);
+                }
+            };
+            // A reply that cannot be decoded or matched is skipped; the
+            // queries still waiting time out if nothing else arrives.
+            let Ok(message) = message else { continue };
+            let Some(slot) = ids.iter().position(|id| *id == Some(message.id)) else {
+                continue;
+            };
+            if results[slot].is_none() {
+                results[slot] = Some(Ok(Response {
+                    message,
+                    latency: started.elapsed(),
+                    transport: Transport::Tls,
+                }));
+            }
+        }
+        fill(results, &QueryError::Failed(String::new()))
+    }
+
+    fn io_error(&self, error: std::io::Error, timeout: Duration) -> QueryError {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => QueryError::Timeout(format!(
+                "{} did not answer within {} ms",
+                self.server,
+                timeout.as_millis()
+            )),
+            _ => QueryError::Failed(format!("TLS query to {} failed: {}", self.server, error)),
+        }
+    }
+}
+
+fn fill(
+    results: Vec<Option<Result<Response, QueryError>>>,
+    error: &QueryError,
+) -> Vec<Result<Response, QueryError>> {
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(error.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::client::write_framed;
+    use crate::dns::testing::certificate;
+    use crate::dns::wire::Rcode;
+    use rustls::{ServerConfig, ServerConnection};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Local DoT resolver that reads `batch` queries, then answers them in
+    /// reverse order. Counts the connections it accepts.
+    fn stand_in(server_config: Arc<ServerConfig>, batch: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let connection = ServerConnection::new(server_config.clone()).unwrap();
+                let mut tls = StreamOwned::new(connection, stream);
+                loop {
+                    let mut queries = Vec::new();
+                    while queries.len() < batch {
+                        match read_framed(&mut tls) {
+                            Ok(bytes) => queries.push(Message::decode(&bytes).unwrap()),
+                            Err(_) => break,
+                        }
+                    }
+                    if queries.len() < batch {
+                        break;
+                    }
+                    for query in queries.iter().rev() {
+                        let reply = Message::reply_to(query, Rcode::NOERROR);
+                        write_framed(&mut tls, &reply.encode().unwrap()).unwrap();
+                    }
+                    tls.flush().unwrap();
+                }
+            }
+        });
+        (address, connections)
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn pipelined_answers_are_matched_out_of_order_on_one_connection() {
+        let (server_config, client_config) = certificate();
+        let (server, connections) = stand_in(server_config, 3);
+        let timeout = Duration::from_secs(5);
+
+        let mut connection =
+            DotConnection::open_with(server, "localhost", timeout, client_config).unwrap();
+        for _ in 0..2 {
+            let results = connection.pipeline(
+                &names(&["a.example", "b.example", "c.example"]),
+                RecordType::A,
+                timeout,
+            );
+            let answered: Vec<String> = results
+                .into_iter()
+                .map(|result| result.unwrap().message.questions[0].name.clone())
+                .collect();
+            assert_eq!(answered, ["a.example", "b.example", "c.example"]);
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn single_queries_reuse_the_connection() {
+        let (server_config, client_config) = certificate();
+        let (server, connections) = stand_in(server_config, 1);
+        let timeout = Duration::from_secs(5);
+
+        let mut connection =
+            DotConnection::open_with(server, "localhost", timeout, client_config).unwrap();
+        for name in ["a.example", "b.example"] {
+            let response = connection.query(name, RecordType::Aaaa, timeout).unwrap();
+            assert_eq!(response.transport, Transport::Tls);
+            assert_eq!(response.message.questions[0].name, name);
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn certificates_for_another_name_fail_the_handshake() {
+        let (server_config, client_config) = certificate();
+        let (server, _) = stand_in(server_config, 1);
+
+        let error =
+            DotConnection::open_with(server, "dns.example", Duration::from_secs(5), client_config)
+                .err()
+                .unwrap();
+
+        let message = error.to_string();
+        assert!(
+            message.starts_with(&format!(
+                "TLS handshake with dns.example at {} failed",
+                server
+            )),
+            "{}",
+            message
+        );
+        assert!(message.contains("certificate"), "{}", message);
+    }
+
+    #[test]
+    fn untrusted_certificates_fail_the_handshake() {
+        let (server_config, _) = certificate();
+        let (server, _) = stand_in(server_config, 1);
+
+        let error = DotConnection::open(server, "localhost", Duration::from_secs(5))
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().contains("UnknownIssuer"), "{}", error);
+    }
+}
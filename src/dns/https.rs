@@ -0,0 +1,391 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::dns::QueryError;
+
+/// Largest response head accepted before giving up on a server.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// The parts of an `https://` URL needed to send a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpsUrl {
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub path: String,
+}
+
+impl HttpsUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .trim()
+            .strip_prefix("https://")
+            .ok_or_else(|| format!("'{}' is not an https:// URL", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("'{}' has an unclosed IPv6 address", url))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("'{}' has no host", url));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("'{}' has an invalid port", url))?,
+            None => 443,
+        };
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for HttpsUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "https://[{}]:{}{}", self.host, self.port, self.path)
+        } else {
+            write!(f, "https://{}:{}{}", self.host, self.port, self.path)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// TLS settings trusting the bundled web PKI roots.
+pub fn default_tls() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+/// Opens a TLS connection to `host:port`, trying every address the host
+/// resolves to.
+pub fn connect(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Result<StreamOwned<ClientConnection, TcpStream>, QueryError> {
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?;
+
+    let mut last_error = format!("{} has no addresses", host);
+    let mut stream = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                last_error = format!("{} did not accept a connection", address);
+            }
+            Err(e) => last_error = format!("Connection to {} failed: {}", address, e),
+        }
+    }
+    let stream = stream.ok_or(QueryError::Failed(last_error))?;
+    secure(stream, host, timeout, tls)
+}
+
+/// Starts TLS for `host` on an already connected socket. The handshake
+/// itself happens with the first write.
+fn secure(
+    socket: TcpStream,
+    host: &str,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Result<StreamOwned<ClientConnection, TcpStream>, QueryError> {
+    socket
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| socket.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|_| format!("'{}' is not a valid server name", host))?;
+    let connection = ClientConnection::new(tls, name).map_err(|e| e.to_string())?;
+    Ok(StreamOwned::new(connection, socket))
+}
+
+/// Sends one HTTP/1.1 request and reads the whole response.
+pub fn request(
+    url: &HttpsUrl,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Result<HttpResponse, QueryError> {
+    let mut stream = connect(&url.host, url.port, timeout, tls)?;
+    exchange(&mut stream, url, method, headers, body, timeout)
+}
+
+/// Sends `HEAD /` for `host` over an already connected socket, so the
+/// caller decides which address is used.
+pub fn head(
+    socket: TcpStream,
+    host: &str,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Result<HttpResponse, QueryError> {
+    let url = HttpsUrl {
+        host: host.to_string(),
+        port: socket.peer_addr().map(|a| a.port()).unwrap_or(443),
+        path: "/".to_string(),
+    };
+    let mut stream = secure(socket, host, timeout, tls)?;
+    exchange(
+        &mut stream,
+        &url,
+        "HEAD",
+        &[("User-Agent", "DNsight")],
+        &[],
+        timeout,
+    )
+}
+
+fn exchange(
+    stream: &mut StreamOwned<ClientConnection, TcpStream>,
+    url: &HttpsUrl,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<HttpResponse, QueryError> {
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, url.path, url.host
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    stream
+        .write_all(&bytes)
+        .and_then(|_| stream.flush())
+        .map_err(|e| io_error(url, timeout, e))?;
+
+    read_response(stream, method != "HEAD").map_err(|e| io_error(url, timeout, e))
+}
+
+fn io_error(url: &HttpsUrl, timeout: Duration, error: std::io::Error) -> QueryError {
+    let tls_error = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+    if let Some(e @ rustls::Error::InvalidCertificate(_)) = tls_error {
+        return QueryError::Certificate(format!(
+            "Certificate is not valid for {}: {}",
+            url.host, e
+        ));
+    }
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => QueryError::Timeout(format!(
+            "{} did not answer within {} ms",
+            url,
+            timeout.as_millis()
+        )),
+        _ => QueryError::Failed(format!("Request to {} failed: {}", url, error)),
+    }
+}
+
+/// Reads a response head and then, unless it answers a HEAD request, as
+/// much body as it announces.
+fn read_response(stream: &mut impl Read, with_body: bool) -> std::io::Result<HttpResponse> {
+    let mut buffer = Vec::new();
+    let head_end = loop {
+        if let Some(end) = find(&buffer, b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEAD {
+            return Err(invalid("response head is too large"));
+        }
+        if read_some(stream, &mut buffer)? == 0 {
+            return Err(invalid("connection closed before the response head"));
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: buffer[head_end + 4..].to_vec(),
+    };
+    if !with_body {
+        response.body.clear();
+    } else if let Some(length) = response.header("Content-Length") {
+        let length: usize = length
+            .parse()
+            .map_err(|_| invalid("invalid Content-Length"))?;
+        while response.body.len() < length {
+            if read_some(stream, &mut response.body)? == 0 {
+                return Err(invalid("connection closed mid-body"));
+            }
+        }
+        response.body.truncate(length);
+    } else if response
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        while find(&response.body, b"0\r\n\r\n").is_none() {
+            if read_some(stream, &mut response.body)? == 0 {
+                break;
+            }
+        }
+        response.body = dechunk(&response.body)?;
+    } else {
+        while read_some(stream, &mut response.body)? > 0 {}
+    }
+    Ok(response)
+}
+
+/// Appends whatever the stream has next. A peer that hangs up without a TLS
+/// close_notify counts as the end of the stream, as browsers treat it.
+fn read_some(stream: &mut impl Read, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+    let mut chunk = [0u8; 4096];
+    match stream.read(&mut chunk) {
+        Ok(read) => {
+            buffer.extend_from_slice(&chunk[..read]);
+            Ok(read)
+        }
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = find(body, b"\r\n").ok_or_else(|| invalid("truncated chunk"))?;
+        let size = std::str::from_utf8(&body[..line_end])
+            .ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next()?.trim(), 16).ok())
+            .ok_or_else(|| invalid("invalid chunk size"))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        if body.len() < size + 2 {
+            return Err(invalid("truncated chunk"));
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_split_into_host_port_and_path() {
+        assert_eq!(
+            HttpsUrl::parse("https://dns.quad9.net/dns-query").unwrap(),
+            HttpsUrl {
+                host: "dns.quad9.net".to_string(),
+                port: 443,
+                path: "/dns-query".to_string(),
+            }
+        );
+        assert_eq!(
+            HttpsUrl::parse("https://[2620:fe::fe]:8443").unwrap(),
+            HttpsUrl {
+                host: "2620:fe::fe".to_string(),
+                port: 8443,
+                path: "/".to_string(),
+            }
+        );
+        assert!(HttpsUrl::parse("http://dns.quad9.net/dns-query").is_err());
+        assert!(HttpsUrl::parse("https://:443/").is_err());
+        assert!(HttpsUrl::parse("https://host:port/").is_err());
+    }
+
+    #[test]
+    fn chunked_and_plain_bodies_are_read() {
+        let chunked =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let response = read_response(&mut &chunked[..], true).unwrap();
+        assert_eq!(response.body, b"abcde");
+
+        let plain = b"HTTP/1.1 404 Not Found\r\nServer: test\r\n\r\nmissing";
+        let response = read_response(&mut &plain[..], true).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.header("server"), Some("test"));
+        assert_eq!(response.body, b"missing");
+
+        let head = b"HTTP/1.1 451 Unavailable\r\nContent-Length: 1200\r\n\r\n";
+        let response = read_response(&mut &head[..], false).unwrap();
+        assert_eq!(response.status, 451);
+        assert!(response.body.is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+use crate::dns::{Message, Rcode, Record, RecordData};
+
+/// A self-signed certificate for `localhost` and a client config that
+/// trusts it.
+pub fn certificate() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+    let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = CertificateDer::from(key.cert.der().to_vec());
+    let private = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.signing_key.serialize_der()));
+
+    let server = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], private)
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (Arc::new(server), Arc::new(client))
+}
+
+/// Local UDP resolver that sends back every message `replies` returns for
+/// a query, in order. Returning none drops the query.
+pub fn resolver(replies: impl FnMut(&Message) -> Vec<Message> + Send + 'static) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    serve(socket, replies);
+    address
+}
+
+/// Like [`resolver`], on a socket the test already bound.
+pub fn serve(
+    socket: UdpSocket,
+    mut replies: impl FnMut(&Message) -> Vec<Message> + Send + 'static,
+) {
+    thread::spawn(move || {
+        let mut buffer = [0u8; 512];
+        while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+            let Ok(query) = Message::decode(&buffer[..len]) else {
+                continue;
+            };
+            for reply in replies(&query) {
+                let _ = socket.send_to(&reply.encode().unwrap(), from);
+            }
+        }
+    });
+}
+
+/// A NOERROR reply to `query` with one record of the asked name and type
+/// per item of `data`.
+pub fn answer(query: &Message, ttl: u32, data: impl IntoIterator<Item = RecordData>) -> Message {
+    let question = &query.questions[0];
+    let mut reply = Message::reply_to(query, Rcode::NOERROR);
+    reply.answers = data
+        .into_iter()
+        .map(|data| Record {
+            name: question.name.clone(),
+            rtype: question.qtype,
+            ttl,
+            data,
+        })
+        .collect();
+    reply
+}
@@ -0,0 +1,635 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const CLASS_IN: u16 = 1;
+const MAX_LABEL_LEN: usize = 63;
+/// Pointer chains longer than this are treated as a loop.
+const MAX_POINTERS: usize = 32;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Mx,
+    Txt,
+    Ns,
+    Soa,
+    Ptr,
+    Other(u16),
+}
+
+impl RecordType {
+    pub fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Other(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            2 => RecordType::Ns,
+            5 => RecordType::Cname,
+            6 => RecordType::Soa,
+            12 => RecordType::Ptr,
+            15 => RecordType::Mx,
+            16 => RecordType::Txt,
+            28 => RecordType::Aaaa,
+            other => RecordType::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Mx => write!(f, "MX"),
+            RecordType::Txt => write!(f, "TXT"),
+            RecordType::Ns => write!(f, "NS"),
+            RecordType::Soa => write!(f, "SOA"),
+            RecordType::Ptr => write!(f, "PTR"),
+            RecordType::Other(code) => write!(f, "TYPE{}", code),
+        }
+    }
+}
+
+/// Response code from the low four bits of the header flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rcode(pub u8);
+
+impl Rcode {
+    pub const NOERROR: Rcode = Rcode(0);
+    pub const FORMERR: Rcode = Rcode(1);
+    pub const SERVFAIL: Rcode = Rcode(2);
+    pub const NXDOMAIN: Rcode = Rcode(3);
+    pub const REFUSED: Rcode = Rcode(5);
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "NOERROR"),
+            1 => write!(f, "FORMERR"),
+            2 => write!(f, "SERVFAIL"),
+            3 => write!(f, "NXDOMAIN"),
+            4 => write!(f, "NOTIMP"),
+            5 => write!(f, "REFUSED"),
+            other => write!(f, "RCODE{}", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: RecordType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Txt(Vec<String>),
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Other(Vec<u8>),
+}
+
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::A(ip) => write!(f, "{}", ip),
+            RecordData::Aaaa(ip) => write!(f, "{}", ip),
+            RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => {
+                write!(f, "{}", name)
+            }
+            RecordData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RecordData::Txt(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|s| format!("\"{}\"", s)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RecordData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RecordData::Other(bytes) => write!(f, "\\# {}", bytes.len()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub rtype: RecordType,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// A whole DNS message. Only the parts a stub resolver cares about are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Message {
+    pub id: u16,
+    pub response: bool,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Message {
+    /// A recursive query for `name`.
+    pub fn query(id: u16, name: &str, qtype: RecordType) -> Self {
+        Self {
+            id,
+            recursion_desired: true,
+            questions: vec![Question {
+                name: name.to_string(),
+                qtype,
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// An empty response to `query` carrying `rcode`, ready for answers.
+    pub fn reply_to(query: &Message, rcode: Rcode) -> Self {
+        Self {
+            id: query.id,
+            response: true,
+            recursion_desired: query.recursion_desired,
+            recursion_available: true,
+            rcode: rcode.0,
+            questions: query.questions.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn rcode(&self) -> Rcode {
+        Rcode(self.rcode)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut flags = u16::from(self.rcode & 0x0f);
+        for (set, flag) in [
+            (self.response, FLAG_RESPONSE),
+            (self.authoritative, FLAG_AUTHORITATIVE),
+            (self.truncated, FLAG_TRUNCATED),
+            (self.recursion_desired, FLAG_RECURSION_DESIRED),
+            (self.recursion_available, FLAG_RECURSION_AVAILABLE),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut out = Vec::with_capacity(512);
+        for value in [
+            self.id,
+            flags,
+            self.questions.len() as u16,
+            self.answers.len() as u16,
+            self.authority.len() as u16,
+            self.additional.len() as u16,
+        ] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+
+        for question in &self.questions {
+            encode_name(&mut out, &question.name)?;
+            out.extend_from_slice(&question.qtype.code().to_be_bytes());
+            out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            encode_record(&mut out, record)?;
+        }
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            let name = reader.name()?;
+            let qtype = RecordType::from_code(reader.u16()?);
+            reader.u16()?;
+            questions.push(Question { name, qtype });
+        }
+
+        let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+        for (section, count) in sections.iter_mut().zip(&counts[1..]) {
+            for _ in 0..*count {
+                section.push(reader.record()?);
+            }
+        }
+        let [answers, authority, additional] = sections;
+
+        Ok(Self {
+            id,
+            response: flags & FLAG_RESPONSE != 0,
+            authoritative: flags & FLAG_AUTHORITATIVE != 0,
+            truncated: flags & FLAG_TRUNCATED != 0,
+            recursion_desired: flags & FLAG_RECURSION_DESIRED != 0,
+            recursion_available: flags & FLAG_RECURSION_AVAILABLE != 0,
+            rcode: (flags & 0x0f) as u8,
+            questions,
+            answers,
+            authority,
+            additional,
+        })
+    }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) -> Result<(), String> {
+    let name = name.trim_end_matches('.');
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err(format!("'{}' is not a valid domain name", name));
+            }
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+    }
+    out.push(0);
+    Ok(())
+}
+
+fn encode_record(out: &mut Vec<u8>, record: &Record) -> Result<(), String> {
+    encode_name(out, &record.name)?;
+    out.extend_from_slice(&record.rtype.code().to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+
+    let mut data = Vec::new();
+    match &record.data {
+        RecordData::A(ip) => data.extend_from_slice(&ip.octets()),
+        RecordData::Aaaa(ip) => data.extend_from_slice(&ip.octets()),
+        RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => {
+            encode_name(&mut data, name)?
+        }
+        RecordData::Mx {
+            preference,
+            exchange,
+        } => {
+            data.extend_from_slice(&preference.to_be_bytes());
+            encode_name(&mut data, exchange)?;
+        }
+        RecordData::Txt(strings) => {
+            for string in strings {
+                if string.is_empty() {
+                    data.push(0);
+                }
+                for chunk in string.as_bytes().chunks(255) {
+                    data.push(chunk.len() as u8);
+                    data.extend_from_slice(chunk);
+                }
+            }
+        }
+        RecordData::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => {
+            encode_name(&mut data, mname)?;
+            encode_name(&mut data, rname)?;
+            for value in [serial, refresh, retry, expire, minimum] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        RecordData::Other(bytes) => data.extend_from_slice(bytes),
+    }
+
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(&data);
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("DNS message is truncated".to_string());
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name starting at the current position.
+    fn name(&mut self) -> Result<String, String> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut resume = None;
+        let mut pointers = 0;
+
+        loop {
+            let len = *self
+                .bytes
+                .get(pos)
+                .ok_or("DNS message is truncated".to_string())? as usize;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .bytes
+                        .get(pos + 1..pos + 1 + len)
+                        .ok_or("DNS message is truncated".to_string())?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                }
+                0xc0 => {
+                    let low = *self
+                        .bytes
+                        .get(pos + 1)
+                        .ok_or("DNS message is truncated".to_string())?;
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err("DNS name compression loop".to_string());
+                    }
+                    resume.get_or_insert(pos + 2);
+                    pos = ((len & 0x3f) << 8) | low as usize;
+                }
+                _ => return Err("Unsupported DNS label type".to_string()),
+            }
+        }
+
+        self.pos = resume.unwrap_or(pos);
+        Ok(labels.join("."))
+    }
+
+    fn record(&mut self) -> Result<Record, String> {
+        let name = self.name()?;
+        let rtype = RecordType::from_code(self.u16()?);
+        self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("DNS message is truncated".to_string());
+        }
+
+        let data = match rtype {
+            RecordType::A if len == 4 => {
+                let b = self.take(4)?;
+                RecordData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            RecordType::Aaaa if len == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(self.take(16)?);
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            RecordType::Cname => RecordData::Cname(self.name()?),
+            RecordType::Ns => RecordData::Ns(self.name()?),
+            RecordType::Ptr => RecordData::Ptr(self.name()?),
+            RecordType::Mx => RecordData::Mx {
+                preference: self.u16()?,
+                exchange: self.name()?,
+            },
+            RecordType::Txt => {
+                let mut strings = Vec::new();
+                while self.pos < end {
+                    let len = self.u8()? as usize;
+                    strings.push(String::from_utf8_lossy(self.take(len)?).into_owned());
+                }
+                RecordData::Txt(strings)
+            }
+            RecordType::Soa => RecordData::Soa {
+                mname: self.name()?,
+                rname: self.name()?,
+                serial: self.u32()?,
+                refresh: self.u32()?,
+                retry: self.u32()?,
+                expire: self.u32()?,
+                minimum: self.u32()?,
+            },
+            _ => RecordData::Other(self.take(len)?.to_vec()),
+        };
+
+        if self.pos != end {
+            return Err(format!("Malformed {} record for {}", rtype, name));
+        }
+        Ok(Record {
+            name,
+            rtype,
+            ttl,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, data: RecordData) -> Record {
+        let rtype = match &data {
+            RecordData::A(_) => RecordType::A,
+            RecordData::Aaaa(_) => RecordType::Aaaa,
+            RecordData::Cname(_) => RecordType::Cname,
+            RecordData::Ns(_) => RecordType::Ns,
+            RecordData::Ptr(_) => RecordType::Ptr,
+            RecordData::Mx { .. } => RecordType::Mx,
+            RecordData::Txt(_) => RecordType::Txt,
+            RecordData::Soa { .. } => RecordType::Soa,
+            RecordData::Other(_) => RecordType::Other(99),
+        };
+        Record {
+            name: name.to_string(),
+            rtype,
+            ttl: 300,
+            data,
+        }
+    }
+
+    #[test]
+    fn query_bytes() {
+        let bytes = Message::query(0xbeef, "example.com.", RecordType::Aaaa)
+            .encode()
+            .unwrap();
+        assert_eq!(
+            bytes,
+            [
+                0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p',
+                b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 28, 0, 1
+            ]
+        );
+    }
+
+    #[test]
+    fn every_record_type_round_trips() {
+        let query = Message::query(7, "example.com", RecordType::A);
+        let mut reply = Message::reply_to(&query, Rcode::NOERROR);
+        reply.answers = vec![
+            record(
+                "example.com",
+                RecordData::A(Ipv4Addr::new(93, 184, 216, 34)),
+            ),
+            record(
+                "example.com",
+                RecordData::Aaaa("2606:2800:220:1::".parse().unwrap()),
+            ),
+            record("www.example.com", RecordData::Cname("example.com".into())),
+            record(
+                "example.com",
+                RecordData::Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".into(),
+                },
+            ),
+            record(
+                "example.com",
+                RecordData::Txt(vec!["v=spf1 -all".into(), String::new()]),
+            ),
+            record("example.com", RecordData::Ns("a.iana-servers.net".into())),
+            record(
+                "34.216.184.93.in-addr.arpa",
+                RecordData::Ptr("example.com".into()),
+            ),
+        ];
+        reply.authority = vec![record(
+            "example.com",
+            RecordData::Soa {
+                mname: "ns.icann.org".into(),
+                rname: "noc.dns.icann.org".into(),
+                serial: 2024081401,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 3600,
+            },
+        )];
+        reply.additional = vec![record("example.com", RecordData::Other(vec![1, 2, 3]))];
+
+        let decoded = Message::decode(&reply.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded, reply);
+        assert_eq!(decoded.rcode().to_string(), "NOERROR");
+    }
+
+    #[test]
+    fn compressed_names_are_followed() {
+        // Answer for example.com whose owner and CNAME target both point back
+        // into the question.
+        let bytes = [
+            0x12, 0x34, 0x81, 0x83, 0, 1, 0, 1, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p', b'l',
+            b'e', 3, b'c', b'o', b'm', 0, 0, 5, 0, 1, 0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3,
+            b'w', b'w', b'w', 0xc0, 12,
+        ];
+
+        let message = Message::decode(&bytes).unwrap();
+
+        assert_eq!(message.rcode(), Rcode::NXDOMAIN);
+        assert!(message.response && message.recursion_available);
+        assert_eq!(message.answers[0].name, "example.com");
+        assert_eq!(
+            message.answers[0].data,
+            RecordData::Cname("www.example.com".into())
+        );
+    }
+
+    #[test]
+    fn pointer_loops_and_truncation_are_errors() {
+        let looping = [
+            0, 1, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 12, 0, 1, 0, 1,
+        ];
+        assert_eq!(
+            Message::decode(&looping),
+            Err("DNS name compression loop".to_string())
+        );
+
+        let bytes = Message::query(1, "example.com", RecordType::A)
+            .encode()
+            .unwrap();
+        assert!(Message::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        assert!(Message::query(1, "a..b", RecordType::A).encode().is_err());
+        let long = "a".repeat(64);
+        assert!(Message::query(1, &long, RecordType::A).encode().is_err());
+    }
+}
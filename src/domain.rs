@@ -1,104 +1,265 @@
+use std::net::IpAddr;
+use std::ops::Deref;
+
 use serde::{Deserialize, Serialize};
 
+/// An ordered list of DNS server addresses. The first one is preferred and
+/// the rest are tried in order; IPv4 and IPv6 addresses may be mixed.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ServerList(Vec<String>);
+
+impl ServerList {
+    /// Trims every address and drops the blank ones.
+    pub fn new<I, S>(servers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self(
+            servers
+                .into_iter()
+                .map(|server| server.as_ref().trim().to_string())
+                .filter(|server| !server.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.0.is_empty() {
+            return Err("Enter at least one DNS server".to_string());
+        }
+        for (position, server) in self.0.iter().enumerate() {
+            if server.parse::<IpAddr>().is_err() {
+                return Err(format!("'{}' is not a valid IP address", server));
+            }
+            if self.0[..position].contains(server) {
+                return Err(format!("{} is listed more than once", server));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which address family an input field takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn label(&self) -> &'static str {
+        match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+
+    /// Whether `server` is an address of this family.
+    pub fn accepts(&self, server: &str) -> bool {
+        match server.trim().parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => *self == IpFamily::V4,
+            Ok(IpAddr::V6(_)) => *self == IpFamily::V6,
+            Err(_) => false,
+        }
+    }
+
+    /// Fails on the first address typed into the list of the other family.
+    pub fn check(&self, servers: &[String]) -> Result<(), String> {
+        match servers
+            .iter()
+            .map(|server| server.trim())
+            .find(|server| server.parse::<IpAddr>().is_ok() && !self.accepts(server))
+        {
+            Some(server) => Err(format!("{} is not an {} address", server, self.label())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Deref for ServerList {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedDnsEntryFile")]
 pub struct SavedDnsEntry {
     pub name: String,
-    pub primary: String,
-    pub     secondary: String,
+    pub servers: ServerList,
+    /// DNS-over-HTTPS endpoint of the same resolver, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doh_url: Option<String>,
+    /// Name on the resolver's certificate, for DNS-over-TLS on port 853.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_name: Option<String>,
+}
+
+/// What `saved_dns.json` may contain. Files written before server lists
+/// existed carry `primary`/`secondary` and the optional IPv6 pair instead.
+#[derive(Deserialize)]
+struct SavedDnsEntryFile {
+    name: String,
+    #[serde(default)]
+    servers: Vec<String>,
+    #[serde(default)]
+    primary: String,
+    #[serde(default)]
+    secondary: String,
+    #[serde(default)]
+    primary_v6: String,
+    #[serde(default)]
+    secondary_v6: String,
+    #[serde(default)]
+    doh_url: Option<String>,
+    #[serde(default)]
+    tls_name: Option<String>,
+}
+
+impl From<SavedDnsEntryFile> for SavedDnsEntry {
+    fn from(file: SavedDnsEntryFile) -> Self {
+        let servers = if file.servers.is_empty() {
+            ServerList::new([
+                file.primary,
+                file.secondary,
+                file.primary_v6,
+                file.secondary_v6,
+            ])
+        } else {
+            ServerList::new(file.servers)
+        };
+        Self {
+            name: file.name,
+            servers,
+            doh_url: file.doh_url.filter(|url| !url.trim().is_empty()),
+            tls_name: file.tls_name.filter(|name| !name.trim().is_empty()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DnsProvider {
     Electro {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Radar {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Shekan {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Bogzar {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Quad9 {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Custom {
-        primary: String,
-        secondary: String,
+        servers: ServerList,
     },
     Saved {
         name: String,
-        primary: String,
-        secondary: String,
+        servers: ServerList,
+        doh_url: Option<String>,
+        tls_name: Option<String>,
     },
 }
 
 impl DnsProvider {
     pub fn electro() -> Self {
         Self::Electro {
-            primary: "78.157.42.100".to_string(),
-            secondary: "78.157.42.101".to_string(),
+            servers: ServerList::new(["78.157.42.100", "78.157.42.101"]),
         }
     }
 
     pub fn radar() -> Self {
         Self::Radar {
-            primary: "10.202.10.10".to_string(),
-            secondary: "10.202.10.11".to_string(),
+            servers: ServerList::new(["10.202.10.10", "10.202.10.11"]),
         }
     }
 
     pub fn shekan() -> Self {
         Self::Shekan {
-            primary: "178.22.122.100".to_string(),
-            secondary: "185.51.200.2".to_string(),
+            servers: ServerList::new(["178.22.122.100", "185.51.200.2"]),
         }
     }
 
     pub fn bogzar() -> Self {
         Self::Bogzar {
-            primary: "185.55.226.26".to_string(),
-            secondary: "185.55.225.25".to_string(),
+            servers: ServerList::new(["185.55.226.26", "185.55.225.25"]),
         }
     }
 
     pub fn quad9() -> Self {
         Self::Quad9 {
-            primary: "9.9.9.9".to_string(),
-            secondary: "149.112.112.112".to_string(),
+            servers: ServerList::new(["9.9.9.9", "149.112.112.112", "2620:fe::fe", "2620:fe::9"]),
         }
     }
 
-    pub fn custom(primary: String, secondary: String) -> Self {
-        Self::Custom { primary, secondary }
+    /// The providers that ship with the app, in the order they are shown.
+    pub fn built_in() -> Vec<Self> {
+        vec![
+            Self::electro(),
+            Self::radar(),
+            Self::shekan(),
+            Self::bogzar(),
+            Self::quad9(),
+        ]
+    }
+
+    pub fn custom(servers: ServerList) -> Self {
+        Self::Custom { servers }
     }
 
-    pub fn saved(name: String, primary: String, secondary: String) -> Self {
+    pub fn saved(entry: &SavedDnsEntry) -> Self {
         Self::Saved {
-            name,
-            primary,
-            secondary,
+            name: entry.name.clone(),
+            servers: entry.servers.clone(),
+            doh_url: entry.doh_url.clone(),
+            tls_name: entry.tls_name.clone(),
         }
     }
 
-    pub fn get_servers(&self) -> (String, String) {
+    pub fn servers(&self) -> &ServerList {
         match self {
-            DnsProvider::Electro { primary, secondary }
-            | DnsProvider::Radar { primary, secondary }
-            | DnsProvider::Shekan { primary, secondary }
-            | DnsProvider::Bogzar { primary, secondary }
-            | DnsProvider::Quad9 { primary, secondary }
-            | DnsProvider::Custom { primary, secondary }
-            | DnsProvider::Saved {
-                primary, secondary, ..
-            } => (primary.clone(), secondary.clone()),
+            DnsProvider::Electro { servers }
+            | DnsProvider::Radar { servers }
+            | DnsProvider::Shekan { servers }
+            | DnsProvider::Bogzar { servers }
+            | DnsProvider::Quad9 { servers }
+            | DnsProvider::Custom { servers }
+            | DnsProvider::Saved { servers, .. } => servers,
+        }
+    }
+
+    /// The provider's DNS-over-HTTPS endpoint, where it publishes one.
+    pub fn doh_url(&self) -> Option<&str> {
+        match self {
+            DnsProvider::Shekan { .. } => Some("https://free.shecan.ir/dns-query"),
+            DnsProvider::Quad9 { .. } => Some("https://dns.quad9.net/dns-query"),
+            DnsProvider::Saved { doh_url, .. } => doh_url.as_deref(),
+            DnsProvider::Electro { .. }
+            | DnsProvider::Radar { .. }
+            | DnsProvider::Bogzar { .. }
+            | DnsProvider::Custom { .. } => None,
+        }
+    }
+
+    /// The name on the provider's DNS-over-TLS certificate, where it
+    /// serves DoT.
+    pub fn tls_name(&self) -> Option<&str> {
+        match self {
+            DnsProvider::Shekan { .. } => Some("free.shecan.ir"),
+            DnsProvider::Quad9 { .. } => Some("dns.quad9.net"),
+            DnsProvider::Saved { tls_name, .. } => tls_name.as_deref(),
+            DnsProvider::Electro { .. }
+            | DnsProvider::Radar { .. }
+            | DnsProvider::Bogzar { .. }
+            | DnsProvider::Custom { .. } => None,
         }
     }
 
@@ -120,6 +281,10 @@ pub enum DnsOperation {
     Set(DnsProvider),
     Clear,
     Test,
+    /// Looks for a middlebox answering port 53 in place of the resolvers.
+    CheckInterception,
+    /// Puts adapters back to the snapshots taken before the last change.
+    Undo(Vec<DnsSnapshot>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -129,6 +294,38 @@ pub enum OperationResult {
     Warning(String),
 }
 
+impl OperationResult {
+    /// Folds the per-adapter results of one operation into a single result.
+    /// A single result is passed through unchanged.
+    pub fn combine(results: Vec<(String, OperationResult)>) -> OperationResult {
+        if results.len() == 1 {
+            return results.into_iter().next().unwrap().1;
+        }
+
+        let mut failures = Vec::new();
+        let mut warnings = Vec::new();
+        for (adapter, result) in &results {
+            match result {
+                OperationResult::Success(_) => {}
+                OperationResult::Error(message) => {
+                    failures.push(format!("{}: {}", adapter, message))
+                }
+                OperationResult::Warning(message) => {
+                    warnings.push(format!("{}: {}", adapter, message))
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            OperationResult::Error(failures.join("\n"))
+        } else if !warnings.is_empty() {
+            OperationResult::Warning(warnings.join("\n"))
+        } else {
+            OperationResult::Success(format!("Applied to {} adapters", results.len()))
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum AppState {
     #[default]
@@ -139,6 +336,417 @@ pub enum AppState {
     Warning(String),
 }
 
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AdapterInfo {
+    pub index: u32,
+    pub name: String,
+    pub kind: String,
+    pub status: String,
+    pub connected: bool,
+    /// Tunnels, VPN adapters, virtual switches and loopback.
+    pub r#virtual: bool,
+    pub dns: Vec<String>,
+}
+
+impl AdapterInfo {
+    /// Whether `AdapterTarget::All` changes this adapter.
+    pub fn in_all(&self) -> bool {
+        self.connected && !self.r#virtual
+    }
+}
+
+/// Which adapters `DnsOperation::Set` and `DnsOperation::Clear` act on.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AdapterTarget {
+    /// Whatever adapter currently carries the connection.
+    #[default]
+    Active,
+    Named(String),
+    All,
+}
+
+impl AdapterTarget {
+    pub fn display_name(&self) -> String {
+        match self {
+            AdapterTarget::Active => "Active adapter".to_string(),
+            AdapterTarget::Named(name) => name.clone(),
+            AdapterTarget::All => "All adapters".to_string(),
+        }
+    }
+}
+
+/// Preferences that survive restarts. Every field has a default so older
+/// settings files keep loading as new options are added.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    pub adapter_target: AdapterTarget,
+    pub health_check: HealthCheckSettings,
+    /// Show the commands a change would run instead of running them.
+    pub dry_run: bool,
+    pub benchmark: BenchmarkSettings,
+    pub auto_select: AutoSelectSettings,
+    pub doh_method: DohMethod,
+    pub reachability: ReachabilitySettings,
+    pub forwarder: ForwarderSettings,
+}
+
+/// How DNS-over-HTTPS queries are sent (RFC 8484).
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub enum DohMethod {
+    /// The query goes base64url-encoded in the `dns` parameter, which lets
+    /// HTTP caches answer repeated queries.
+    #[default]
+    Get,
+    Post,
+}
+
+/// What happens right after new DNS servers are applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckSettings {
+    pub enabled: bool,
+    /// Every one of these has to resolve through the new servers.
+    pub domains: Vec<String>,
+    pub timeout_secs: u64,
+    /// How long the user has to confirm the change before it is reverted.
+    /// Zero keeps changes that pass the check without asking.
+    pub keep_changes_secs: u64,
+    /// Domain the Test button resolves through each configured server.
+    pub probe_domain: String,
+}
+
+impl Default for HealthCheckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            domains: vec![
+                "google.com".to_string(),
+                "cloudflare.com".to_string(),
+                "wikipedia.org".to_string(),
+            ],
+            timeout_secs: 3,
+            keep_changes_secs: 15,
+            probe_domain: "example.com".to_string(),
+        }
+    }
+}
+
+/// Whether benchmark queries may be answered from the resolver's cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CacheMode {
+    /// Each domain is queried once before timing starts, so the timed
+    /// queries measure cached answers.
+    #[default]
+    Warm,
+    /// Every query asks for a fresh random subdomain, so the resolver has to
+    /// go upstream each time.
+    Cold,
+}
+
+/// How the provider benchmark queries each server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkSettings {
+    pub domains: Vec<String>,
+    /// Timed queries per domain and server.
+    pub rounds: usize,
+    pub timeout_ms: u64,
+    pub cache_mode: CacheMode,
+}
+
+impl Default for BenchmarkSettings {
+    fn default() -> Self {
+        Self {
+            domains: vec![
+                "google.com".to_string(),
+                "youtube.com".to_string(),
+                "wikipedia.org".to_string(),
+                "github.com".to_string(),
+                "cloudflare.com".to_string(),
+            ],
+            rounds: 5,
+            timeout_ms: 2000,
+            cache_mode: CacheMode::Warm,
+        }
+    }
+}
+
+/// Periodically benchmarks every provider and switches to the best one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoSelectSettings {
+    pub enabled: bool,
+    /// Time between two measurements.
+    pub interval_secs: u64,
+    /// Minimum time to stay on a provider after switching to it, unless it
+    /// stops answering altogether.
+    pub dwell_secs: u64,
+    /// How much lower, in percent, another provider's score has to be before
+    /// it replaces the current one.
+    pub hysteresis_percent: u32,
+    /// Added to a server's median latency in proportion to the share of
+    /// queries it loses; losing every query costs the full amount.
+    pub failure_penalty_ms: u64,
+}
+
+impl Default for AutoSelectSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 300,
+            dwell_secs: 900,
+            hysteresis_percent: 20,
+            failure_penalty_ms: 1000,
+        }
+    }
+}
+
+/// Services checked by the reachability grid.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReachabilitySettings {
+    pub hosts: Vec<String>,
+    /// Limit for each step: the lookup, the connect and the HEAD request.
+    pub timeout_ms: u64,
+}
+
+impl Default for ReachabilitySettings {
+    fn default() -> Self {
+        Self {
+            hosts: vec![
+                "registry.npmjs.org".to_string(),
+                "registry-1.docker.io".to_string(),
+                "developer.android.com".to_string(),
+                "console.cloud.google.com".to_string(),
+                "pkg.go.dev".to_string(),
+            ],
+            timeout_ms: 4000,
+        }
+    }
+}
+
+/// The local caching forwarder the adapter can point at instead of a
+/// provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForwarderSettings {
+    /// Started with the app, since the adapter still points at it.
+    pub enabled: bool,
+    /// Servers of the provider queries are forwarded to.
+    pub servers: Vec<String>,
+    /// How the selected provider's servers share the queries.
+    pub strategy: UpstreamStrategy,
+    /// Wait for one server before trying the next.
+    pub attempt_timeout_ms: u64,
+    /// Wait for the last server tried, or for a race as a whole.
+    pub timeout_ms: u64,
+    pub blocking: BlockingSettings,
+}
+
+impl Default for ForwarderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            servers: Vec::new(),
+            strategy: UpstreamStrategy::default(),
+            attempt_timeout_ms: 400,
+            timeout_ms: 2000,
+            blocking: BlockingSettings::default(),
+        }
+    }
+}
+
+/// Domain lists the forwarder answers itself instead of forwarding.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockingSettings {
+    /// Hosts files, AdBlock `||domain^` lists or plain domain lists.
+    pub blocklists: Vec<String>,
+    /// Lists in the same formats whose names are never blocked.
+    pub allowlists: Vec<String>,
+    pub response: BlockResponse,
+}
+
+/// What a blocked name resolves to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockResponse {
+    #[default]
+    NxDomain,
+    /// 0.0.0.0 for A and :: for AAAA, no records for other types.
+    Unspecified,
+    Refused,
+}
+
+impl BlockResponse {
+    pub const ALL: [BlockResponse; 3] = [
+        BlockResponse::NxDomain,
+        BlockResponse::Unspecified,
+        BlockResponse::Refused,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BlockResponse::NxDomain => "NXDOMAIN",
+            BlockResponse::Unspecified => "0.0.0.0",
+            BlockResponse::Refused => "REFUSED",
+        }
+    }
+}
+
+/// How the forwarder spreads queries over a provider's servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStrategy {
+    /// In order, moving on after a short timeout.
+    #[default]
+    Failover,
+    /// All at once, taking the first good answer.
+    Race,
+    /// Each query starts at the next server, failing over from there.
+    RoundRobin,
+}
+
+impl UpstreamStrategy {
+    pub const ALL: [UpstreamStrategy; 3] = [
+        UpstreamStrategy::Failover,
+        UpstreamStrategy::Race,
+        UpstreamStrategy::RoundRobin,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            UpstreamStrategy::Failover => "Failover",
+            UpstreamStrategy::Race => "Race",
+            UpstreamStrategy::RoundRobin => "Round-robin",
+        }
+    }
+}
+
+/// How a routing rule's pattern is compared with a query name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    /// The name itself and everything under it.
+    #[default]
+    Suffix,
+    Exact,
+    /// A regular expression, matched case-insensitively.
+    Regex,
+    /// `*` for any run of characters and `?` for one, over the whole name.
+    Wildcard,
+}
+
+impl MatchKind {
+    pub const ALL: [MatchKind; 4] = [
+        MatchKind::Suffix,
+        MatchKind::Exact,
+        MatchKind::Regex,
+        MatchKind::Wildcard,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MatchKind::Suffix => "Suffix",
+            MatchKind::Exact => "Exact",
+            MatchKind::Regex => "Regex",
+            MatchKind::Wildcard => "Wildcard",
+        }
+    }
+}
+
+/// Sends queries for names matching `pattern` to the provider named
+/// `upstream` instead of the selected one.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub kind: MatchKind,
+    pub pattern: String,
+    /// Display name of a built-in provider or a saved entry.
+    pub upstream: String,
+    #[serde(default)]
+    pub strategy: UpstreamStrategy,
+}
+
+/// What `rules.json` contains. The first matching rule wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RulesFile {
+    pub version: u32,
+    pub rules: Vec<RoutingRule>,
+}
+
+impl RulesFile {
+    /// Bumped whenever the file changes in a way older versions would
+    /// misread.
+    pub const VERSION: u32 = 1;
+
+    pub fn new(rules: Vec<RoutingRule>) -> Self {
+        Self {
+            version: Self::VERSION,
+            rules,
+        }
+    }
+
+    pub fn parse(json: &str) -> Result<Vec<RoutingRule>, String> {
+        let file: RulesFile =
+            serde_json::from_str(json).map_err(|e| format!("Invalid rules file: {}", e))?;
+        if file.version > Self::VERSION {
+            return Err(format!(
+                "The rules file is version {}, this DNSight only reads up to {}",
+                file.version,
+                Self::VERSION
+            ));
+        }
+        Ok(file.rules)
+    }
+}
+
+/// An adapter's DNS configuration as it was before a change, kept per
+/// protocol so an IPv6 configured by DHCP is not restored as static.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsSnapshot {
+    pub adapter: String,
+    pub ipv4: DnsState,
+    pub ipv6: DnsState,
+}
+
+impl DnsSnapshot {
+    /// Splits the state of the whole adapter, for backends that do not keep
+    /// the protocols apart.
+    pub fn new(adapter: &str, state: DnsState) -> Self {
+        let (ipv4, ipv6) = match state {
+            DnsState::Static(servers) => {
+                let (ipv6, ipv4): (Vec<String>, Vec<String>) = servers
+                    .into_iter()
+                    .partition(|server| matches!(server.parse::<IpAddr>(), Ok(IpAddr::V6(_))));
+                (DnsState::from_static(ipv4), DnsState::from_static(ipv6))
+            }
+            state => (state.clone(), state),
+        };
+        Self {
+            adapter: adapter.to_string(),
+            ipv4,
+            ipv6,
+        }
+    }
+
+    /// The static servers of both protocols, IPv4 first, or `None` when
+    /// neither had any.
+    pub fn static_servers(&self) -> Option<Vec<String>> {
+        let servers: Vec<String> = [&self.ipv4, &self.ipv6]
+            .into_iter()
+            .filter_map(|state| match state {
+                DnsState::Static(servers) => Some(servers.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        (!servers.is_empty()).then_some(servers)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum DnsState {
     Static(Vec<String>),
@@ -147,8 +755,129 @@ pub enum DnsState {
     None,
 }
 
+impl DnsState {
+    fn from_static(servers: Vec<String>) -> Self {
+        if servers.is_empty() {
+            DnsState::None
+        } else {
+            DnsState::Static(servers)
+        }
+    }
+}
+
 impl Default for DnsProvider {
     fn default() -> Self {
         DnsProvider::electro()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_entries_from_pairs_still_load() {
+        let json = r#"[
+            {"name": "Home", "primary": "1.1.1.1", "secondary": "1.0.0.1"},
+            {"name": "Quad9", "primary": "9.9.9.9", "secondary": "", "primary_v6": "2620:fe::fe"}
+        ]"#;
+
+        let entries: Vec<SavedDnsEntry> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(&entries[0].servers[..], ["1.1.1.1", "1.0.0.1"]);
+        assert_eq!(&entries[1].servers[..], ["9.9.9.9", "2620:fe::fe"]);
+    }
+
+    #[test]
+    fn addresses_are_checked_against_their_own_family() {
+        assert!(IpFamily::V4.accepts(" 9.9.9.9 "));
+        assert!(!IpFamily::V4.accepts("2620:fe::fe"));
+        assert!(IpFamily::V6.accepts("2620:fe::fe"));
+        assert!(!IpFamily::V6.accepts("9.9.9.9"));
+        assert!(!IpFamily::V6.accepts("not an address"));
+
+        let ipv6 = vec![
+            "2620:fe::fe".to_string(),
+            String::new(),
+            "9.9.9.9".to_string(),
+        ];
+        assert_eq!(
+            IpFamily::V6.check(&ipv6),
+            Err("9.9.9.9 is not an IPv6 address".to_string())
+        );
+        assert_eq!(IpFamily::V4.check(&["1.1.1.1".to_string()]), Ok(()));
+    }
+
+    #[test]
+    fn rules_files_from_newer_versions_are_refused() {
+        let rules = vec![RoutingRule {
+            kind: MatchKind::Wildcard,
+            pattern: "*.corp.internal".to_string(),
+            upstream: "Office".to_string(),
+            strategy: UpstreamStrategy::Race,
+        }];
+        let json = serde_json::to_string(&RulesFile::new(rules.clone())).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"version":1,"rules":[{"kind":"wildcard","pattern":"*.corp.internal","upstream":"Office","strategy":"race"}]}"#
+        );
+        assert_eq!(RulesFile::parse(&json).unwrap(), rules);
+        let without_strategy = json.replace(r#","strategy":"race""#, "");
+        assert_eq!(
+            RulesFile::parse(&without_strategy).unwrap()[0].strategy,
+            UpstreamStrategy::Failover
+        );
+        assert!(RulesFile::parse(r#"{"version":2,"rules":[]}"#)
+            .unwrap_err()
+            .contains("version 2"));
+    }
+
+    #[test]
+    fn saved_entries_round_trip_as_lists() {
+        let entry = SavedDnsEntry {
+            name: "Lab".to_string(),
+            servers: ServerList::new(["10.0.0.1", "10.0.0.2", "10.0.0.3"]),
+            doh_url: None,
+            tls_name: None,
+        };
+
+        let json = serde_json::to_string(&entry).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"name":"Lab","servers":["10.0.0.1","10.0.0.2","10.0.0.3"]}"#
+        );
+        assert_eq!(serde_json::from_str::<SavedDnsEntry>(&json).unwrap(), entry);
+
+        let entry = SavedDnsEntry {
+            doh_url: Some("https://dns.example/dns-query".to_string()),
+            tls_name: Some("dns.example".to_string()),
+            ..entry
+        };
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json
+            .ends_with(r#""doh_url":"https://dns.example/dns-query","tls_name":"dns.example"}"#));
+        assert_eq!(serde_json::from_str::<SavedDnsEntry>(&json).unwrap(), entry);
+    }
+
+    #[test]
+    fn server_list_validation() {
+        assert!(ServerList::new(["1.1.1.1"]).validate().is_ok());
+        assert!(ServerList::new([" 2606:4700::1111 ", ""])
+            .validate()
+            .is_ok());
+        assert_eq!(
+            ServerList::new(["", " "]).validate(),
+            Err("Enter at least one DNS server".to_string())
+        );
+        assert_eq!(
+            ServerList::new(["1.1.1.1", "1.1.1"]).validate(),
+            Err("'1.1.1' is not a valid IP address".to_string())
+        );
+        assert_eq!(
+            ServerList::new(["1.1.1.1", "8.8.8.8", "1.1.1.1"]).validate(),
+            Err("1.1.1.1 is listed more than once".to_string())
+        );
+    }
+}
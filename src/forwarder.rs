@@ -0,0 +1,623 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::dns::{self, Message, Rcode, RecordType, Upstream, DNS_PORT};
+use crate::domain::BlockResponse;
+
+mod blocklist;
+mod cache;
+mod querylog;
+mod rules;
+mod upstreams;
+
+pub use blocklist::Blocklist;
+use cache::Cache;
+use querylog::QueryLog;
+pub use querylog::{to_jsonl, QueryEvent, Source};
+pub use rules::{validate, Routes};
+use upstreams::Health;
+pub use upstreams::{Group, Score, Timeouts};
+
+/// Where the forwarder listens and what the adapter is pointed at.
+const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+const CACHE_CAPACITY: usize = 10_000;
+const OPT: RecordType = RecordType::Other(41);
+/// How often the listeners look at the stop flag.
+const POLL: Duration = Duration::from_millis(200);
+/// Threads answering UDP queries, and how many queries may wait for one.
+/// Queries beyond that are dropped and the client asks again.
+const UDP_WORKERS: usize = 16;
+const UDP_BACKLOG: usize = 256;
+/// TCP connections served at once. Further ones are closed right away.
+const TCP_CONNECTIONS: usize = 32;
+
+/// Counters shown next to the forwarder switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub cached: usize,
+    /// Answered by the forwarder itself because the name is on a blocklist.
+    pub blocked: u64,
+    /// Passed on to the cache and the upstreams.
+    pub allowed: u64,
+}
+
+struct Shared {
+    group: RwLock<Arc<Group>>,
+    routes: RwLock<Routes>,
+    health: Arc<Health>,
+    blocklist: RwLock<Arc<Blocklist>>,
+    block_response: RwLock<BlockResponse>,
+    cache: Mutex<Cache>,
+    log: QueryLog,
+    timeouts: Timeouts,
+    stop: AtomicBool,
+    queries: AtomicU64,
+    cache_hits: AtomicU64,
+    blocked: AtomicU64,
+    allowed: AtomicU64,
+}
+
+/// A caching stub resolver on loopback that forwards to the selected
+/// provider. Switching provider only swaps the upstream group, so the adapter
+/// keeps pointing at the forwarder. Stops when dropped, and the port is
+/// free again once dropping returns.
+pub struct Forwarder {
+    shared: Arc<Shared>,
+    address: SocketAddr,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl Forwarder {
+    /// Listens on 127.0.0.1:53.
+    pub fn start(group: Group, timeouts: Timeouts) -> Result<Self, String> {
+        Self::start_on(SocketAddr::new(LOOPBACK, DNS_PORT), group, timeouts)
+    }
+
+    /// Listens for UDP and TCP on `address`. Port 0 picks a free port, the
+    /// same one for both.
+    pub fn start_on(address: SocketAddr, group: Group, timeouts: Timeouts) -> Result<Self, String> {
+        let udp = UdpSocket::bind(address)
+            .map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+        let address = udp.local_addr().map_err(|e| e.to_string())?;
+        let tcp = TcpListener::bind(address)
+            .map_err(|e| format!("Could not listen on {} over TCP: {}", address, e))?;
+        udp.set_read_timeout(Some(POLL))
+            .and_then(|_| tcp.set_nonblocking(true))
+            .map_err(|e| e.to_string())?;
+
+        let shared = Arc::new(Shared {
+            group: RwLock::new(Arc::new(group)),
+            routes: RwLock::new(Routes::default()),
+            health: Arc::new(Health::default()),
+            blocklist: RwLock::new(Arc::new(Blocklist::default())),
+            block_response: RwLock::new(BlockResponse::default()),
+            cache: Mutex::new(Cache::new(CACHE_CAPACITY)),
+            log: QueryLog::default(),
+            timeouts,
+            stop: AtomicBool::new(false),
+            queries: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            allowed: AtomicU64::new(0),
+        });
+
+        let udp_shared = shared.clone();
+        let tcp_shared = shared.clone();
+        let listeners = vec![
+            thread::spawn(move || serve_udp(udp, udp_shared)),
+            thread::spawn(move || serve_tcp(tcp, tcp_shared)),
+        ];
+
+        log::info!("DNS forwarder listening on {}", address);
+        Ok(Self {
+            shared,
+            address,
+            listeners,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Sends every later query to `group`. The cache is emptied so nothing
+    /// the previous provider said is served any more.
+    pub fn set_upstreams(&self, group: Group) {
+        let names: Vec<String> = group.upstreams().iter().map(|u| u.to_string()).collect();
+        log::info!(
+            "DNS forwarder now forwards to {} ({})",
+            names.join(", "),
+            group.strategy().label()
+        );
+        *self.shared.group.write().unwrap() = Arc::new(group);
+        self.shared.cache.lock().unwrap().clear();
+    }
+
+    /// Sends names matching a rule to that rule's upstreams rather than
+    /// the selected provider's. Empties the cache like `set_upstreams`.
+    pub fn set_routes(&self, routes: Routes) {
+        let count = routes.len();
+        *self.shared.routes.write().unwrap() = routes;
+        self.shared.cache.lock().unwrap().clear();
+        log::info!("DNS forwarder routing with {} rules", count);
+    }
+
+    /// Answers names on `blocklist` with `response` instead of forwarding
+    /// them.
+    pub fn set_blocklist(&self, blocklist: Blocklist, response: BlockResponse) {
+        log::info!(
+            "DNS forwarder blocking {} names, allowing {}, answering {}",
+            blocklist.blocked_len(),
+            blocklist.allowed_len(),
+            response.label()
+        );
+        *self.shared.blocklist.write().unwrap() = Arc::new(blocklist);
+        *self.shared.block_response.write().unwrap() = response;
+    }
+
+    pub fn set_block_response(&self, response: BlockResponse) {
+        *self.shared.block_response.write().unwrap() = response;
+    }
+
+    /// Every query answered from now on, until the receiver is dropped.
+    pub fn watch(&self) -> std::sync::mpsc::Receiver<QueryEvent> {
+        self.shared.log.subscribe()
+    }
+
+    /// How every upstream used so far has been doing.
+    pub fn health(&self) -> Vec<(Upstream, Score)> {
+        self.shared.health.scores()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            queries: self.shared.queries.load(Ordering::Relaxed),
+            cache_hits: self.shared.cache_hits.load(Ordering::Relaxed),
+            cached: self.shared.cache.lock().unwrap().len(),
+            blocked: self.shared.blocked.load(Ordering::Relaxed),
+            allowed: self.shared.allowed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        for listener in self.listeners.drain(..) {
+            let _ = listener.join();
+        }
+        log::info!("DNS forwarder on {} stopped", self.address);
+    }
+}
+
+/// Reads queries and hands them to a fixed set of workers. Returns once
+/// every worker is done with the socket.
+fn serve_udp(socket: UdpSocket, shared: Arc<Shared>) {
+    let socket = Arc::new(socket);
+    let (queries, queue) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(UDP_BACKLOG);
+    let queue = Arc::new(Mutex::new(queue));
+    let workers: Vec<JoinHandle<()>> = (0..UDP_WORKERS)
+        .map(|_| {
+            let socket = socket.clone();
+            let shared = shared.clone();
+            let queue = queue.clone();
+            thread::spawn(move || loop {
+                let Ok((query, from)) = queue.lock().unwrap().recv() else {
+                    return;
+                };
+                if let Some(reply) = answer(&shared, &query, from, true) {
+                    let _ = socket.send_to(&reply, from);
+                }
+            })
+        })
+        .collect();
+
+    let mut buffer = [0u8; 4096];
+    while !shared.stop.load(Ordering::SeqCst) {
+        let (len, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            // Timeouts, and on Windows the ICMP port unreachable left by
+            // replies to clients that already gave up.
+            Err(_) => continue,
+        };
+        let _ = queries.try_send((buffer[..len].to_vec(), from));
+    }
+
+    drop(queries);
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+/// Serves up to `TCP_CONNECTIONS` clients at a time. Connections are left
+/// to end on their own, which takes at most their read timeout.
+fn serve_tcp(listener: TcpListener, shared: Arc<Shared>) {
+    let mut connections: Vec<JoinHandle<()>> = Vec::new();
+    while !shared.stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, from)) => {
+                connections.retain(|connection| !connection.is_finished());
+                if connections.len() >= TCP_CONNECTIONS {
+                    continue;
+                }
+                let shared = shared.clone();
+                connections.push(thread::spawn(move || {
+                    serve_connection(stream, from, &shared)
+                }));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL / 4),
+            Err(_) => thread::sleep(POLL),
+        }
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, from: SocketAddr, shared: &Shared) {
+    if stream.set_nonblocking(false).is_err()
+        || stream
+            .set_read_timeout(Some(shared.timeouts.total * 2))
+            .is_err()
+    {
+        return;
+    }
+    while let Ok(query) = dns::read_framed(&mut stream) {
+        let Some(reply) = answer(shared, &query, from, false) else {
+            return;
+        };
+        if dns::write_framed(&mut stream, &reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// The encoded reply to one client query, from the cache or the first
+/// upstream that answers. `None` for packets that are not queries at all.
+fn answer(shared: &Shared, query: &[u8], client: SocketAddr, udp: bool) -> Option<Vec<u8>> {
+    let started = Instant::now();
+    let query = Message::decode(query).ok()?;
+    if query.response {
+        return None;
+    }
+    shared.queries.fetch_add(1, Ordering::Relaxed);
+    let (mut reply, source, upstream) = lookup(shared, &query);
+    shared.log.publish(|| {
+        let question = query.questions.first();
+        QueryEvent {
+            timestamp_ms: QueryEvent::now_ms(),
+            client: client.to_string(),
+            name: question.map(|q| q.name.clone()).unwrap_or_default(),
+            qtype: question.map(|q| q.qtype.to_string()).unwrap_or_default(),
+            upstream: upstream.map(|upstream| upstream.to_string()),
+            rcode: reply.rcode().to_string(),
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+            source,
+        }
+    });
+
+    reply.id = query.id;
+    reply.response = true;
+    reply.recursion_desired = query.recursion_desired;
+    reply.recursion_available = true;
+    reply.questions = query.questions.clone();
+
+    let mut bytes = reply.encode().ok()?;
+    let limit = if query.additional.iter().any(|r| r.rtype == OPT) {
+        1232
+    } else {
+        512
+    };
+    if udp && bytes.len() > limit {
+        reply.truncated = true;
+        reply.answers.clear();
+        reply.authority.clear();
+        reply.additional.clear();
+        bytes = reply.encode().ok()?;
+    }
+    Some(bytes)
+}
+
+/// The reply before it is fitted to the client, where it came from and,
+/// when forwarded, the upstream that gave it.
+fn lookup(shared: &Shared, query: &Message) -> (Message, Source, Option<Upstream>) {
+    let [question] = query.questions.as_slice() else {
+        return (
+            Message::reply_to(query, Rcode::FORMERR),
+            Source::Forwarder,
+            None,
+        );
+    };
+
+    let blocklist = shared.blocklist.read().unwrap().clone();
+    if blocklist.is_blocked(&question.name) {
+        shared.blocked.fetch_add(1, Ordering::Relaxed);
+        let response = *shared.block_response.read().unwrap();
+        let reply = blocklist::blocked_reply(query, response);
+        return (reply, Source::Blocked, None);
+    }
+    shared.allowed.fetch_add(1, Ordering::Relaxed);
+
+    let key = Cache::key(&question.name, question.qtype);
+    let cached = shared.cache.lock().unwrap().get(&key, Instant::now());
+    if let Some(reply) = cached {
+        shared.cache_hits.fetch_add(1, Ordering::Relaxed);
+        return (reply, Source::Cache, None);
+    }
+    match resolve(shared, &question.name, question.qtype) {
+        Some((upstream, mut reply)) => {
+            // The client's EDNS options are not passed on, so the
+            // upstream's OPT record does not belong in the answer.
+            reply.additional.retain(|record| record.rtype != OPT);
+            reply.truncated = false;
+            shared
+                .cache
+                .lock()
+                .unwrap()
+                .insert(key, &reply, Instant::now());
+            (reply, Source::Upstream, Some(upstream))
+        }
+        None => (
+            Message::reply_to(query, Rcode::SERVFAIL),
+            Source::Forwarder,
+            None,
+        ),
+    }
+}
+
+fn resolve(shared: &Shared, name: &str, qtype: RecordType) -> Option<(Upstream, Message)> {
+    let routed = shared.routes.read().unwrap().group_for(name);
+    let group = routed.unwrap_or_else(|| shared.group.read().unwrap().clone());
+    group.resolve(name, qtype, &shared.health, shared.timeouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{testing, Record, RecordData};
+    use crate::domain::{MatchKind, UpstreamStrategy};
+    use std::io::Read;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::AtomicUsize;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    /// Short enough that a dead upstream is given up on well within TIMEOUT.
+    const UPSTREAM_TIMEOUTS: Timeouts = Timeouts {
+        attempt: Duration::from_millis(300),
+        total: Duration::from_millis(300),
+    };
+
+    /// Local upstream answering A queries with `ip` and a 300 s TTL, and
+    /// "missing." names with NXDOMAIN and an SOA. Counts the queries.
+    pub(super) fn upstream(ip: [u8; 4]) -> (Upstream, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let seen = count.clone();
+        let address = testing::resolver(move |query| {
+            seen.fetch_add(1, Ordering::SeqCst);
+            if !query.questions[0].name.starts_with("missing.") {
+                return vec![testing::answer(
+                    query,
+                    300,
+                    [RecordData::A(Ipv4Addr::from(ip))],
+                )];
+            }
+            let mut reply = Message::reply_to(query, Rcode::NXDOMAIN);
+            reply.authority.push(Record {
+                name: "example".to_string(),
+                rtype: RecordType::Soa,
+                ttl: 600,
+                data: RecordData::Soa {
+                    mname: "ns.example".to_string(),
+                    rname: "hostmaster.example".to_string(),
+                    serial: 1,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1_209_600,
+                    minimum: 60,
+                },
+            });
+            vec![reply]
+        });
+        (Upstream::Udp(address), count)
+    }
+
+    /// An upstream that never answers. It stays silent while the socket
+    /// is kept.
+    pub(super) fn silent() -> (Upstream, UdpSocket) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        (Upstream::Udp(socket.local_addr().unwrap()), socket)
+    }
+
+    fn failover(upstreams: Vec<Upstream>) -> Group {
+        Group::new(upstreams, UpstreamStrategy::Failover)
+    }
+
+    fn start(upstreams: Vec<Upstream>) -> Forwarder {
+        let address = "127.0.0.1:0".parse().unwrap();
+        Forwarder::start_on(address, failover(upstreams), UPSTREAM_TIMEOUTS).unwrap()
+    }
+
+    fn first_answer(forwarder: &Forwarder, name: &str) -> String {
+        let response = dns::query(forwarder.address(), name, RecordType::A, TIMEOUT).unwrap();
+        response.message.answers[0].data.to_string()
+    }
+
+    #[test]
+    fn repeated_queries_are_answered_from_the_cache() {
+        let (upstream, count) = upstream([192, 0, 2, 1]);
+        let forwarder = start(vec![upstream]);
+
+        assert_eq!(first_answer(&forwarder, "example.com"), "192.0.2.1");
+        assert_eq!(first_answer(&forwarder, "EXAMPLE.com"), "192.0.2.1");
+        let missing = dns::query(
+            forwarder.address(),
+            "missing.example",
+            RecordType::A,
+            TIMEOUT,
+        )
+        .unwrap();
+        assert_eq!(missing.message.rcode(), Rcode::NXDOMAIN);
+        dns::query(
+            forwarder.address(),
+            "missing.example",
+            RecordType::A,
+            TIMEOUT,
+        )
+        .unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            forwarder.stats(),
+            Stats {
+                queries: 4,
+                cache_hits: 2,
+                cached: 2,
+                blocked: 0,
+                allowed: 4
+            }
+        );
+    }
+
+    #[test]
+    fn switching_upstreams_takes_effect_immediately() {
+        let (first, _) = upstream([192, 0, 2, 1]);
+        let (second, count) = upstream([198, 51, 100, 1]);
+        let forwarder = start(vec![first]);
+        assert_eq!(first_answer(&forwarder, "example.com"), "192.0.2.1");
+
+        forwarder.set_upstreams(failover(vec![second]));
+
+        assert_eq!(first_answer(&forwarder, "example.com"), "198.51.100.1");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn stopping_frees_the_port() {
+        let (upstream, _) = upstream([192, 0, 2, 1]);
+        let forwarder = start(vec![upstream]);
+        let address = forwarder.address();
+        assert_eq!(first_answer(&forwarder, "example.com"), "192.0.2.1");
+
+        drop(forwarder);
+
+        UdpSocket::bind(address).unwrap();
+        TcpListener::bind(address).unwrap();
+    }
+
+    #[test]
+    fn tcp_connections_beyond_the_limit_are_closed() {
+        let forwarder = start(Vec::new());
+        let connect = || {
+            let stream = TcpStream::connect(forwarder.address()).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            stream
+        };
+        let open: Vec<TcpStream> = (0..TCP_CONNECTIONS).map(|_| connect()).collect();
+
+        // Served connections wait twice the upstream timeout for a query.
+        let started = Instant::now();
+        let mut extra = connect();
+        assert_eq!(extra.read(&mut [0u8; 2]).unwrap(), 0);
+        assert!(started.elapsed() < UPSTREAM_TIMEOUTS.total);
+
+        drop(open);
+    }
+
+    #[test]
+    fn matching_names_go_to_the_rule_upstream() {
+        let (default, _) = upstream([192, 0, 2, 1]);
+        let (office, count) = upstream([10, 1, 0, 1]);
+        let forwarder = start(vec![default]);
+        assert_eq!(first_answer(&forwarder, "git.corp.internal"), "192.0.2.1");
+
+        let mut routes = Routes::default();
+        routes
+            .push(
+                MatchKind::Wildcard,
+                "*.corp.internal",
+                failover(vec![office]),
+            )
+            .unwrap();
+        forwarder.set_routes(routes);
+
+        assert_eq!(first_answer(&forwarder, "git.corp.internal"), "10.1.0.1");
+        assert_eq!(first_answer(&forwarder, "example.com"), "192.0.2.1");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn blocked_names_are_answered_without_asking_upstream() {
+        let (upstream, count) = upstream([192, 0, 2, 1]);
+        let forwarder = start(vec![upstream]);
+        let mut blocklist = Blocklist::default();
+        blocklist.add(blocklist::parse("||ads.example^\n@@||ok.ads.example^"));
+        forwarder.set_blocklist(blocklist, BlockResponse::Unspecified);
+
+        assert_eq!(first_answer(&forwarder, "x.ads.example"), "0.0.0.0");
+        forwarder.set_block_response(BlockResponse::NxDomain);
+        let response =
+            dns::query(forwarder.address(), "ads.example", RecordType::A, TIMEOUT).unwrap();
+        assert_eq!(response.message.rcode(), Rcode::NXDOMAIN);
+        assert_eq!(first_answer(&forwarder, "ok.ads.example"), "192.0.2.1");
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let stats = forwarder.stats();
+        assert_eq!((stats.blocked, stats.allowed), (2, 1));
+    }
+
+    #[test]
+    fn watchers_see_where_each_answer_came_from() {
+        let (upstream, _) = upstream([192, 0, 2, 1]);
+        let server = upstream.to_string();
+        let forwarder = start(vec![upstream]);
+        let mut blocklist = Blocklist::default();
+        blocklist.add(blocklist::parse("ads.example"));
+        forwarder.set_blocklist(blocklist, BlockResponse::NxDomain);
+        let events = forwarder.watch();
+
+        first_answer(&forwarder, "example.com");
+        first_answer(&forwarder, "example.com");
+        dns::query(forwarder.address(), "ads.example", RecordType::A, TIMEOUT).unwrap();
+
+        let events: Vec<QueryEvent> = events.try_iter().collect();
+        let sources: Vec<Source> = events.iter().map(|event| event.source).collect();
+        assert_eq!(sources, [Source::Upstream, Source::Cache, Source::Blocked]);
+        assert_eq!(events[0].upstream, Some(server));
+        assert_eq!(events[0].qtype, "A");
+        assert_eq!(events[1].upstream, None);
+        assert_eq!(events[2].rcode, "NXDOMAIN");
+        assert!(events[2].client.starts_with("127.0.0.1:"));
+    }
+
+    #[test]
+    fn dead_upstreams_are_skipped_and_servfail_when_all_fail() {
+        let (dead, _socket) = silent();
+        let (alive, _) = upstream([192, 0, 2, 7]);
+
+        let forwarder = start(vec![dead.clone(), alive]);
+        assert_eq!(first_answer(&forwarder, "example.com"), "192.0.2.7");
+
+        forwarder.set_upstreams(failover(vec![dead]));
+        let response =
+            dns::query(forwarder.address(), "example.com", RecordType::A, TIMEOUT).unwrap();
+        assert_eq!(response.message.rcode(), Rcode::SERVFAIL);
+    }
+
+    #[test]
+    fn tcp_clients_are_served_too() {
+        let (upstream, _) = upstream([192, 0, 2, 9]);
+        let forwarder = start(vec![upstream]);
+
+        let mut stream = TcpStream::connect(forwarder.address()).unwrap();
+        let query = Message::query(77, "example.com", RecordType::A);
+        dns::write_framed(&mut stream, &query.encode().unwrap()).unwrap();
+        let reply = Message::decode(&dns::read_framed(&mut stream).unwrap()).unwrap();
+
+        assert_eq!(reply.id, 77);
+        assert_eq!(reply.answers[0].data.to_string(), "192.0.2.9");
+    }
+}
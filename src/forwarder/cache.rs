@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::dns::{Message, Rcode, RecordData, RecordType};
+
+/// Upper bound on how long anything is kept, whatever the TTL says.
+const MAX_TTL: u32 = 86_400;
+
+/// Answers by lowercased name and record type.
+pub type Key = (String, RecordType);
+
+struct Entry {
+    message: Message,
+    stored: Instant,
+    expires: Instant,
+    /// Tells apart entries that expire at the same instant.
+    serial: u64,
+}
+
+/// Upstream responses kept for as long as their TTLs allow (RFC 1035), and
+/// NXDOMAIN/NODATA responses for as long as their SOA allows (RFC 2308).
+pub struct Cache {
+    entries: HashMap<Key, Entry>,
+    /// Every entry's key, soonest to expire first, so a full cache finds what
+    /// to drop without looking at the rest.
+    by_expiry: BTreeMap<(Instant, u64), Key>,
+    next_serial: u64,
+    capacity: usize,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_expiry: BTreeMap::new(),
+            next_serial: 0,
+            capacity,
+        }
+    }
+
+    pub fn key(name: &str, qtype: RecordType) -> Key {
+        (name.trim_end_matches('.').to_ascii_lowercase(), qtype)
+    }
+
+    /// The cached response with every TTL lowered by the time it spent in
+    /// the cache.
+    pub fn get(&self, key: &Key, now: Instant) -> Option<Message> {
+        let entry = self.entries.get(key)?;
+        if entry.expires <= now {
+            return None;
+        }
+        let age = now.duration_since(entry.stored).as_secs() as u32;
+        let mut message = entry.message.clone();
+        for record in message
+            .answers
+            .iter_mut()
+            .chain(&mut message.authority)
+            .chain(&mut message.additional)
+        {
+            record.ttl = record.ttl.saturating_sub(age);
+        }
+        Some(message)
+    }
+
+    /// Keeps `message` if it may be cached at all. Returns whether it was.
+    pub fn insert(&mut self, key: Key, message: &Message, now: Instant) -> bool {
+        let Some(ttl) = cache_ttl(message) else {
+            return false;
+        };
+        if let Some(old) = self.entries.remove(&key) {
+            self.by_expiry.remove(&(old.expires, old.serial));
+        }
+        while let Some(entry) = self.by_expiry.first_entry() {
+            if entry.key().0 > now && self.entries.len() < self.capacity {
+                break;
+            }
+            self.entries.remove(&entry.remove());
+        }
+
+        let expires = now + Duration::from_secs(u64::from(ttl));
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        self.by_expiry.insert((expires, serial), key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                message: message.clone(),
+                stored: now,
+                expires,
+                serial,
+            },
+        );
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.by_expiry.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// How long `message` may be reused: the lowest answer TTL for positive
+/// answers, the SOA's negative TTL for NXDOMAIN and NODATA. Failures and
+/// negative answers without an SOA are not cached.
+fn cache_ttl(message: &Message) -> Option<u32> {
+    let rcode = message.rcode();
+    let ttl = if rcode == Rcode::NOERROR && !message.answers.is_empty() {
+        message.answers.iter().map(|record| record.ttl).min()?
+    } else if rcode == Rcode::NOERROR || rcode == Rcode::NXDOMAIN {
+        message
+            .authority
+            .iter()
+            .find_map(|record| match record.data {
+                RecordData::Soa { minimum, .. } => Some(record.ttl.min(minimum)),
+                _ => None,
+            })?
+    } else {
+        return None;
+    };
+    (ttl > 0).then_some(ttl.min(MAX_TTL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Record;
+    use std::net::Ipv4Addr;
+
+    fn answer(ttl: u32) -> Message {
+        let mut message = Message::reply_to(
+            &Message::query(1, "example.com", RecordType::A),
+            Rcode::NOERROR,
+        );
+        message.answers.push(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::A,
+            ttl,
+            data: RecordData::A(Ipv4Addr::new(93, 184, 216, 34)),
+        });
+        message
+    }
+
+    fn negative(rcode: Rcode, soa: Option<(u32, u32)>) -> Message {
+        let mut message =
+            Message::reply_to(&Message::query(1, "missing.example", RecordType::A), rcode);
+        if let Some((ttl, minimum)) = soa {
+            message.authority.push(Record {
+                name: "example".to_string(),
+                rtype: RecordType::Soa,
+                ttl,
+                data: RecordData::Soa {
+                    mname: "ns.example".to_string(),
+                    rname: "hostmaster.example".to_string(),
+                    serial: 1,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1_209_600,
+                    minimum,
+                },
+            });
+        }
+        message
+    }
+
+    #[test]
+    fn answers_expire_with_their_ttl_and_age_while_cached() {
+        let mut cache = Cache::new(10);
+        let key = Cache::key("Example.COM.", RecordType::A);
+        let now = Instant::now();
+
+        assert!(cache.insert(key.clone(), &answer(300), now));
+
+        let later = cache.get(&key, now + Duration::from_secs(120)).unwrap();
+        assert_eq!(later.answers[0].ttl, 180);
+        assert!(cache
+            .get(&Cache::key("example.com", RecordType::A), now)
+            .is_some());
+        assert!(cache.get(&key, now + Duration::from_secs(300)).is_none());
+    }
+
+    #[test]
+    fn negative_answers_use_the_soa_ttl() {
+        let mut cache = Cache::new(10);
+        let key = Cache::key("missing.example", RecordType::A);
+        let now = Instant::now();
+
+        cache.insert(
+            key.clone(),
+            &negative(Rcode::NXDOMAIN, Some((3600, 60))),
+            now,
+        );
+
+        let cached = cache.get(&key, now + Duration::from_secs(59)).unwrap();
+        assert_eq!(cached.rcode(), Rcode::NXDOMAIN);
+        assert!(cache.get(&key, now + Duration::from_secs(60)).is_none());
+
+        // NODATA is cached the same way.
+        assert!(cache.insert(key, &negative(Rcode::NOERROR, Some((30, 900))), now));
+    }
+
+    #[test]
+    fn failures_and_unbounded_negatives_are_not_cached() {
+        let mut cache = Cache::new(10);
+        let key = Cache::key("missing.example", RecordType::A);
+        let now = Instant::now();
+
+        assert!(!cache.insert(key.clone(), &negative(Rcode::SERVFAIL, None), now));
+        assert!(!cache.insert(key.clone(), &negative(Rcode::NXDOMAIN, None), now));
+        assert!(!cache.insert(key.clone(), &answer(0), now));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn a_full_cache_drops_what_expires_first() {
+        let mut cache = Cache::new(2);
+        let now = Instant::now();
+        let short = Cache::key("short.example", RecordType::A);
+        let long = Cache::key("long.example", RecordType::A);
+        let new = Cache::key("new.example", RecordType::A);
+
+        cache.insert(short.clone(), &answer(10), now);
+        cache.insert(long.clone(), &answer(1000), now);
+        cache.insert(new.clone(), &answer(100), now);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&short, now).is_none());
+        assert!(cache.get(&long, now).is_some());
+        assert!(cache.get(&new, now).is_some());
+    }
+
+    #[test]
+    fn a_replaced_answer_expires_on_its_new_ttl() {
+        let mut cache = Cache::new(2);
+        let now = Instant::now();
+        let renewed = Cache::key("renewed.example", RecordType::A);
+        let other = Cache::key("other.example", RecordType::A);
+        let new = Cache::key("new.example", RecordType::A);
+
+        cache.insert(renewed.clone(), &answer(10), now);
+        cache.insert(other.clone(), &answer(100), now);
+        cache.insert(renewed.clone(), &answer(1000), now);
+        cache.insert(new.clone(), &answer(500), now);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&renewed, now).is_some());
+        assert!(cache.get(&other, now).is_none());
+        assert!(cache.get(&new, now).is_some());
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::{mpsc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Events a watcher can fall behind by before newer ones are dropped for it.
+const BACKLOG: usize = 5_000;
+
+/// Where the answer to a query came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Upstream,
+    Cache,
+    Blocked,
+    /// Malformed queries and names no upstream answered.
+    Forwarder,
+}
+
+impl Source {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Source::Upstream => "upstream",
+            Source::Cache => "cache",
+            Source::Blocked => "blocked",
+            Source::Forwarder => "forwarder",
+        }
+    }
+}
+
+/// One answered query, as the query log shows and exports it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryEvent {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub client: String,
+    pub name: String,
+    pub qtype: String,
+    pub upstream: Option<String>,
+    pub rcode: String,
+    pub latency_ms: f64,
+    pub source: Source,
+}
+
+impl QueryEvent {
+    pub fn now_ms() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    /// `HH:MM:SS.mmm` in UTC.
+    pub fn utc_time(&self) -> String {
+        let ms = self.timestamp_ms % 86_400_000;
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        )
+    }
+
+    /// Whether every word of `filter` appears in some column, ignoring case.
+    pub fn matches(&self, filter: &str) -> bool {
+        if filter.trim().is_empty() {
+            return true;
+        }
+        let row = format!(
+            "{} {} {} {} {} {} {}",
+            self.utc_time(),
+            self.client,
+            self.name,
+            self.qtype,
+            self.upstream.as_deref().unwrap_or_default(),
+            self.rcode,
+            self.source.label()
+        )
+        .to_lowercase();
+        filter
+            .split_whitespace()
+            .all(|word| row.contains(&word.to_lowercase()))
+    }
+}
+
+/// One JSON object per line.
+pub fn to_jsonl<'a>(events: impl IntoIterator<Item = &'a QueryEvent>) -> Result<String, String> {
+    let mut jsonl = String::new();
+    for event in events {
+        let line =
+            serde_json::to_string(event).map_err(|e| format!("Failed to serialize: {}", e))?;
+        jsonl.push_str(&line);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+/// Hands every answered query to whoever is watching. Events are only built
+/// while someone is, and a watcher that falls behind misses the newest.
+#[derive(Default)]
+pub struct QueryLog(Mutex<Vec<mpsc::SyncSender<QueryEvent>>>);
+
+impl QueryLog {
+    pub fn subscribe(&self) -> mpsc::Receiver<QueryEvent> {
+        let (sender, receiver) = mpsc::sync_channel(BACKLOG);
+        self.0.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: impl FnOnce() -> QueryEvent) {
+        let mut watchers = self.0.lock().unwrap();
+        if watchers.is_empty() {
+            return;
+        }
+        let event = event();
+        watchers.retain(|watcher| match watcher.try_send(event.clone()) {
+            Ok(()) | Err(mpsc::TrySendError::Full(_)) => true,
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, source: Source) -> QueryEvent {
+        QueryEvent {
+            // 2024-03-01 13:05:09.042 UTC
+            timestamp_ms: 1_709_298_309_042,
+            client: "127.0.0.1:50000".to_string(),
+            name: name.to_string(),
+            qtype: "AAAA".to_string(),
+            upstream: (source == Source::Upstream).then(|| "9.9.9.9".to_string()),
+            rcode: "NOERROR".to_string(),
+            latency_ms: 12.5,
+            source,
+        }
+    }
+
+    #[test]
+    fn filters_match_every_word_in_any_column() {
+        let forwarded = event("www.Example.com", Source::Upstream);
+        let blocked = event("ads.example.net", Source::Blocked);
+
+        assert_eq!(forwarded.utc_time(), "13:05:09.042");
+        assert!(forwarded.matches(""));
+        assert!(forwarded.matches("example.COM 9.9.9.9"));
+        assert!(forwarded.matches("aaaa 13:05"));
+        assert!(!forwarded.matches("example.com blocked"));
+        assert!(blocked.matches("example blocked"));
+    }
+
+    #[test]
+    fn exports_one_json_object_per_line() {
+        let events = [
+            event("example.com", Source::Upstream),
+            event("example.com", Source::Cache),
+        ];
+
+        let jsonl = to_jsonl(&events).unwrap();
+
+        let lines: Vec<serde_json::Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["upstream"], "9.9.9.9");
+        assert_eq!(lines[0]["timestamp_ms"], 1_709_298_309_042u64);
+        assert_eq!(lines[1]["source"], "cache");
+        assert!(lines[1]["upstream"].is_null());
+    }
+
+    #[test]
+    fn watchers_that_went_away_are_dropped() {
+        let log = QueryLog::default();
+        log.publish(|| unreachable!("nobody is watching"));
+
+        let watching = log.subscribe();
+        drop(log.subscribe());
+        log.publish(|| event("example.com", Source::Cache));
+
+        assert_eq!(watching.try_recv().unwrap().name, "example.com");
+        assert_eq!(log.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn watchers_that_fall_behind_miss_the_newest() {
+        let log = QueryLog::default();
+        let watching = log.subscribe();
+
+        for _ in 0..BACKLOG {
+            log.publish(|| event("example.com", Source::Cache));
+        }
+        log.publish(|| event("late.example", Source::Cache));
+
+        assert_eq!(watching.try_iter().count(), BACKLOG);
+        assert_eq!(log.0.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use regex::{Regex, RegexBuilder};
+
+use super::upstreams::Group;
+use crate::dns::Upstream;
+use crate::domain::{DnsProvider, MatchKind, RoutingRule};
+
+enum Matcher {
+    Suffix(String),
+    Exact(String),
+    Pattern(Regex),
+}
+
+impl Matcher {
+    fn new(kind: MatchKind, pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err("Enter a pattern".to_string());
+        }
+        let name = normalize(pattern);
+        let regex = match kind {
+            MatchKind::Suffix => return Ok(Matcher::Suffix(name.trim_start_matches('.').into())),
+            MatchKind::Exact => return Ok(Matcher::Exact(name)),
+            MatchKind::Regex => pattern.to_string(),
+            MatchKind::Wildcard => {
+                let mut regex = "^".to_string();
+                for c in name.chars() {
+                    match c {
+                        '*' => regex.push_str(".*"),
+                        '?' => regex.push('.'),
+                        c => regex.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                regex.push('$');
+                regex
+            }
+        };
+        RegexBuilder::new(&regex)
+            .case_insensitive(true)
+            .build()
+            .map(Matcher::Pattern)
+            .map_err(|e| format!("'{}' is not a valid pattern: {}", pattern, e))
+    }
+
+    /// `name` is already normalized.
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Matcher::Suffix(suffix) => {
+                name == suffix
+                    || name
+                        .strip_suffix(suffix.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }
+            Matcher::Exact(exact) => name == exact,
+            Matcher::Pattern(regex) => regex.is_match(name),
+        }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks a rule's pattern before it is saved.
+pub fn validate(kind: MatchKind, pattern: &str) -> Result<(), String> {
+    Matcher::new(kind, pattern).map(|_| ())
+}
+
+struct Route {
+    matcher: Matcher,
+    group: Arc<Group>,
+}
+
+/// Routing rules resolved against the providers they name, ready for the
+/// forwarder to look names up in.
+#[derive(Default)]
+pub struct Routes(Vec<Route>);
+
+impl Routes {
+    pub fn compile(rules: &[RoutingRule], providers: &[DnsProvider]) -> Result<Self, String> {
+        let mut routes = Self::default();
+        for rule in rules {
+            let provider = providers
+                .iter()
+                .find(|provider| provider.display_name().eq_ignore_ascii_case(&rule.upstream))
+                .ok_or_else(|| format!("No DNS provider is called '{}'", rule.upstream))?;
+            let upstreams = Upstream::from_servers(provider.servers());
+            if upstreams.is_empty() {
+                return Err(format!("'{}' has no DNS servers", rule.upstream));
+            }
+            routes.push(
+                rule.kind,
+                &rule.pattern,
+                Group::new(upstreams, rule.strategy),
+            )?;
+        }
+        Ok(routes)
+    }
+
+    /// Adds a rule after the existing ones.
+    pub fn push(&mut self, kind: MatchKind, pattern: &str, group: Group) -> Result<(), String> {
+        self.0.push(Route {
+            matcher: Matcher::new(kind, pattern)?,
+            group: Arc::new(group),
+        });
+        Ok(())
+    }
+
+    /// The group of the first rule matching `name`, if any does.
+    pub fn group_for(&self, name: &str) -> Option<Arc<Group>> {
+        let name = normalize(name);
+        self.0
+            .iter()
+            .find(|route| route.matcher.matches(&name))
+            .map(|route| route.group.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{SavedDnsEntry, ServerList, UpstreamStrategy};
+
+    fn rule(kind: MatchKind, pattern: &str, upstream: &str) -> RoutingRule {
+        RoutingRule {
+            kind,
+            pattern: pattern.to_string(),
+            upstream: upstream.to_string(),
+            strategy: UpstreamStrategy::Failover,
+        }
+    }
+
+    fn first_server(routes: &Routes, name: &str) -> Option<String> {
+        routes
+            .group_for(name)
+            .map(|group| group.upstreams()[0].to_string())
+    }
+
+    #[test]
+    fn each_kind_of_rule_matches_what_it_says() {
+        let office = DnsProvider::saved(&SavedDnsEntry {
+            name: "Office".to_string(),
+            servers: ServerList::new(["10.1.0.53"]),
+            doh_url: None,
+            tls_name: None,
+        });
+        let providers = vec![DnsProvider::electro(), DnsProvider::quad9(), office];
+        let routes = Routes::compile(
+            &[
+                rule(MatchKind::Exact, "intranet.example.", "office"),
+                rule(MatchKind::Wildcard, "*.corp.internal", "Office"),
+                rule(MatchKind::Suffix, "ir", "Electro"),
+                rule(MatchKind::Regex, r"^ads?\d+\.", "Quad9"),
+            ],
+            &providers,
+        )
+        .unwrap();
+
+        assert_eq!(
+            first_server(&routes, "Intranet.Example"),
+            Some("10.1.0.53".into())
+        );
+        assert_eq!(first_server(&routes, "www.intranet.example"), None);
+        assert_eq!(
+            first_server(&routes, "git.dev.corp.internal."),
+            Some("10.1.0.53".into())
+        );
+        assert_eq!(first_server(&routes, "corp.internal"), None);
+        let electro = DnsProvider::electro().servers()[0].clone();
+        assert_eq!(first_server(&routes, "digikala.ir"), Some(electro.clone()));
+        assert_eq!(first_server(&routes, "ir"), Some(electro));
+        assert_eq!(first_server(&routes, "example.air"), None);
+        let quad9 = DnsProvider::quad9().servers()[0].clone();
+        assert_eq!(first_server(&routes, "AD7.example.com"), Some(quad9));
+        assert_eq!(first_server(&routes, "bad7.example.com"), None);
+    }
+
+    #[test]
+    fn rules_naming_unknown_providers_or_bad_patterns_are_refused() {
+        let providers = DnsProvider::built_in();
+
+        let unknown = Routes::compile(&[rule(MatchKind::Suffix, "ir", "Home")], &providers);
+        assert_eq!(
+            unknown.err(),
+            Some("No DNS provider is called 'Home'".to_string())
+        );
+        assert!(validate(MatchKind::Regex, "(unclosed").is_err());
+        assert!(validate(MatchKind::Suffix, "  ").is_err());
+        assert!(validate(MatchKind::Wildcard, "*.ir").is_ok());
+    }
+}
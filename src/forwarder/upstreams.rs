@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dns::{Message, Rcode, RecordType, Upstream};
+use crate::domain::UpstreamStrategy;
+
+/// Failures in a row after which an upstream is left out.
+const EJECT_AFTER: u32 = 3;
+/// How long an ejected upstream is left out before it gets another try.
+const EJECTION: Duration = Duration::from_secs(30);
+/// Race attempts one group keeps in flight at once. Past it, queries fail
+/// over instead, so a flood of queries can't pile up threads.
+const RACE_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// For one upstream while there are others left to try.
+    pub attempt: Duration,
+    /// For the last upstream tried, and for a race as a whole.
+    pub total: Duration,
+}
+
+/// How one upstream has been doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Score {
+    pub answered: u64,
+    pub failed: u64,
+    failures_in_a_row: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Score {
+    pub fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Scores of every upstream the forwarder has sent queries to.
+#[derive(Default)]
+pub struct Health(Mutex<HashMap<Upstream, Score>>);
+
+impl Health {
+    pub fn record(&self, upstream: &Upstream, answered: bool, now: Instant) {
+        let mut scores = self.0.lock().unwrap();
+        let score = scores.entry(upstream.clone()).or_default();
+        if answered {
+            score.answered += 1;
+            score.failures_in_a_row = 0;
+            score.ejected_until = None;
+            return;
+        }
+        score.failed += 1;
+        score.failures_in_a_row += 1;
+        if score.failures_in_a_row >= EJECT_AFTER {
+            if !score.is_ejected(now) {
+                log::warn!(
+                    "Leaving {} out for {}s after {} failures in a row",
+                    upstream,
+                    EJECTION.as_secs(),
+                    score.failures_in_a_row
+                );
+            }
+            score.ejected_until = Some(now + EJECTION);
+        }
+    }
+
+    /// `upstreams` without the ejected ones, or all of them when every one
+    /// is ejected, since trying is better than failing outright.
+    fn usable(&self, upstreams: &[Upstream], now: Instant) -> Vec<Upstream> {
+        let scores = self.0.lock().unwrap();
+        let usable: Vec<Upstream> = upstreams
+            .iter()
+            .filter(|upstream| !scores.get(*upstream).is_some_and(|s| s.is_ejected(now)))
+            .cloned()
+            .collect();
+        if usable.is_empty() {
+            upstreams.to_vec()
+        } else {
+            usable
+        }
+    }
+
+    pub fn scores(&self) -> Vec<(Upstream, Score)> {
+        let mut scores: Vec<(Upstream, Score)> = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(upstream, score)| (upstream.clone(), *score))
+            .collect();
+        scores.sort_by(|a, b| a.0.cmp(&b.0));
+        scores
+    }
+}
+
+/// One provider's servers and how queries are spread over them.
+pub struct Group {
+    upstreams: Vec<Upstream>,
+    strategy: UpstreamStrategy,
+    next: AtomicUsize,
+    racing: Arc<AtomicUsize>,
+}
+
+impl Group {
+    pub fn new(upstreams: Vec<Upstream>, strategy: UpstreamStrategy) -> Self {
+        Self {
+            upstreams,
+            strategy,
+            next: AtomicUsize::new(0),
+            racing: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    pub fn strategy(&self) -> UpstreamStrategy {
+        self.strategy
+    }
+
+    /// The first good answer from the group and the upstream it came from.
+    /// A SERVFAIL or REFUSED is only returned when nothing better came back.
+    pub fn resolve(
+        &self,
+        name: &str,
+        qtype: RecordType,
+        health: &Arc<Health>,
+        timeouts: Timeouts,
+    ) -> Option<(Upstream, Message)> {
+        let mut upstreams = health.usable(&self.upstreams, Instant::now());
+        if upstreams.is_empty() {
+            return None;
+        }
+        match self.strategy {
+            UpstreamStrategy::Failover => failover(&upstreams, name, qtype, health, timeouts),
+            UpstreamStrategy::RoundRobin => {
+                let first = self.next.fetch_add(1, Ordering::Relaxed) % upstreams.len();
+                upstreams.rotate_left(first);
+                failover(&upstreams, name, qtype, health, timeouts)
+            }
+            UpstreamStrategy::Race => {
+                let racers = upstreams.len();
+                if self.racing.fetch_add(racers, Ordering::SeqCst) + racers > RACE_LIMIT {
+                    self.racing.fetch_sub(racers, Ordering::SeqCst);
+                    return failover(&upstreams, name, qtype, health, timeouts);
+                }
+                race(upstreams, name, qtype, health, timeouts.total, &self.racing)
+            }
+        }
+    }
+}
+
+fn failover(
+    upstreams: &[Upstream],
+    name: &str,
+    qtype: RecordType,
+    health: &Health,
+    timeouts: Timeouts,
+) -> Option<(Upstream, Message)> {
+    let mut fallback = None;
+    for (position, upstream) in upstreams.iter().enumerate() {
+        let timeout = if position + 1 < upstreams.len() {
+            timeouts.attempt
+        } else {
+            timeouts.total
+        };
+        match attempt(upstream, name, qtype, timeout, health) {
+            Some(message) if is_good(&message) => return Some((upstream.clone(), message)),
+            Some(message) => fallback = Some((upstream.clone(), message)),
+            None => {}
+        }
+    }
+    fallback
+}
+
+fn race(
+    upstreams: Vec<Upstream>,
+    name: &str,
+    qtype: RecordType,
+    health: &Arc<Health>,
+    timeout: Duration,
+    racing: &Arc<AtomicUsize>,
+) -> Option<(Upstream, Message)> {
+    let (sender, receiver) = mpsc::channel();
+    for upstream in upstreams {
+        let sender = sender.clone();
+        let health = health.clone();
+        let racing = racing.clone();
+        let name = name.to_string();
+        // The losers keep running so their health is still recorded. Each
+        // gives up after `timeout`, and `racing` counts them until then.
+        thread::spawn(move || {
+            let answer = attempt(&upstream, &name, qtype, timeout, &health);
+            racing.fetch_sub(1, Ordering::SeqCst);
+            let _ = sender.send(answer.map(|message| (upstream, message)));
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + timeout;
+    let mut fallback = None;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(left) {
+            Ok(Some(answer)) if is_good(&answer.1) => return Some(answer),
+            Ok(Some(answer)) => fallback = Some(answer),
+            Ok(None) => {}
+            Err(_) => return fallback,
+        }
+    }
+}
+
+fn attempt(
+    upstream: &Upstream,
+    name: &str,
+    qtype: RecordType,
+    timeout: Duration,
+    health: &Health,
+) -> Option<Message> {
+    match upstream.resolve(name, qtype, timeout) {
+        Ok(response) => {
+            health.record(upstream, is_good(&response.message), Instant::now());
+            Some(response.message)
+        }
+        Err(e) => {
+            health.record(upstream, false, Instant::now());
+            log::warn!("Forwarding {} to {} failed: {}", name, upstream, e);
+            None
+        }
+    }
+}
+
+/// SERVFAIL and REFUSED say more about the server than about the name, so
+/// another server is worth asking.
+fn is_good(message: &Message) -> bool {
+    !matches!(message.rcode(), Rcode::SERVFAIL | Rcode::REFUSED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forwarder::tests::{silent, upstream};
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        attempt: Duration::from_millis(200),
+        total: Duration::from_secs(2),
+    };
+
+    fn answer(group: &Group, health: &Arc<Health>) -> String {
+        let (_, message) = group
+            .resolve("example.com", RecordType::A, health, TIMEOUTS)
+            .unwrap();
+        message.answers[0].data.to_string()
+    }
+
+    #[test]
+    fn failover_moves_on_after_the_short_timeout() {
+        let (dead, _socket) = silent();
+        let (alive, _) = upstream([192, 0, 2, 1]);
+        let group = Group::new(vec![dead.clone(), alive], UpstreamStrategy::Failover);
+        let health = Arc::new(Health::default());
+
+        let started = Instant::now();
+        assert_eq!(answer(&group, &health), "192.0.2.1");
+
+        assert!(started.elapsed() < TIMEOUTS.total / 2);
+        let scores = health.scores();
+        let (_, dead_score) = scores.iter().find(|(u, _)| *u == dead).unwrap();
+        assert_eq!(dead_score.failed, 1);
+    }
+
+    #[test]
+    fn a_race_takes_the_first_answer() {
+        let (dead, _socket) = silent();
+        let (alive, _) = upstream([192, 0, 2, 2]);
+        let group = Group::new(vec![dead, alive], UpstreamStrategy::Race);
+        let health = Arc::new(Health::default());
+
+        let started = Instant::now();
+        assert_eq!(answer(&group, &health), "192.0.2.2");
+
+        assert!(started.elapsed() < TIMEOUTS.attempt);
+    }
+
+    #[test]
+    fn races_past_the_limit_fail_over_instead() {
+        let (dead, _socket) = silent();
+        let (alive, _) = upstream([192, 0, 2, 2]);
+        let group = Group::new(vec![dead, alive], UpstreamStrategy::Race);
+        let health = Arc::new(Health::default());
+        group.racing.store(RACE_LIMIT - 1, Ordering::SeqCst);
+
+        let started = Instant::now();
+        assert_eq!(answer(&group, &health), "192.0.2.2");
+
+        assert!(started.elapsed() >= TIMEOUTS.attempt);
+        assert_eq!(group.racing.load(Ordering::SeqCst), RACE_LIMIT - 1);
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        let (first, first_count) = upstream([192, 0, 2, 1]);
+        let (second, second_count) = upstream([192, 0, 2, 2]);
+        let group = Group::new(vec![first, second], UpstreamStrategy::RoundRobin);
+        let health = Arc::new(Health::default());
+
+        let answers: Vec<String> = (0..4).map(|_| answer(&group, &health)).collect();
+
+        assert_eq!(
+            answers,
+            ["192.0.2.1", "192.0.2.2", "192.0.2.1", "192.0.2.2"]
+        );
+        assert_eq!(first_count.load(Ordering::SeqCst), 2);
+        assert_eq!(second_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn upstreams_that_keep_failing_are_left_out_for_a_while() {
+        let (dead, _socket) = silent();
+        let (alive, _) = upstream([192, 0, 2, 1]);
+        let both = [dead.clone(), alive];
+        let health = Health::default();
+        let now = Instant::now();
+
+        for _ in 0..EJECT_AFTER - 1 {
+            health.record(&dead, false, now);
+        }
+        assert_eq!(health.usable(&both, now), both);
+
+        health.record(&dead, false, now);
+        assert_eq!(health.usable(&both, now), both[1..]);
+        assert_eq!(health.usable(&both[..1], now), both[..1]);
+        assert_eq!(health.usable(&both, now + EJECTION), both);
+
+        // One more failure after the break puts it straight back out.
+        health.record(&dead, false, now + EJECTION);
+        assert_eq!(health.usable(&both, now + EJECTION), both[1..]);
+        health.record(&dead, true, now + EJECTION);
+        assert_eq!(health.usable(&both, now + EJECTION), both);
+    }
+}
@@ -0,0 +1,121 @@
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{self, Rcode, RecordType, DNS_PORT};
+
+/// Checks that every domain in `domains` resolves through at least one of
+/// `servers` within `timeout`. Domains are checked in parallel; the servers
+/// for one domain are tried in order.
+pub fn check(servers: &[String], domains: &[String], timeout: Duration) -> Result<(), String> {
+    let servers: Vec<SocketAddr> = servers
+        .iter()
+        .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .collect();
+    check_addresses(&servers, domains, timeout)
+}
+
+fn check_addresses(
+    servers: &[SocketAddr],
+    domains: &[String],
+    timeout: Duration,
+) -> Result<(), String> {
+    let failed: Vec<&str> = thread::scope(|scope| {
+        let handles: Vec<_> = domains
+            .iter()
+            .map(|domain| {
+                scope.spawn(move || {
+                    let ok = servers
+                        .iter()
+                        .any(|server| resolves(*server, domain, timeout).unwrap_or(false));
+                    (domain.as_str(), ok)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .filter(|(_, ok)| !ok)
+            .map(|(domain, _)| domain)
+            .collect()
+    });
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "The new DNS servers could not resolve {}",
+            failed.join(", ")
+        ))
+    }
+}
+
+/// Sends one A query for `domain` to `server` and reports whether it came
+/// back with at least one answer.
+fn resolves(server: SocketAddr, domain: &str, timeout: Duration) -> Result<bool, String> {
+    let response = dns::query(server, domain, RecordType::A, timeout)?;
+    Ok(response.message.rcode() == Rcode::NOERROR && !response.message.answers.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing;
+    use crate::dns::RecordData;
+    use std::net::{Ipv4Addr, UdpSocket};
+
+    /// Answers every query on a local socket: `answer` controls whether the
+    /// reply carries one record or none.
+    fn stand_in(answer: bool) -> SocketAddr {
+        testing::resolver(move |query| {
+            let ip = RecordData::A(Ipv4Addr::new(93, 184, 216, 34));
+            vec![testing::answer(query, 60, answer.then_some(ip))]
+        })
+    }
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn healthy_server_passes() {
+        let server = stand_in(true);
+        assert_eq!(
+            check_addresses(
+                &[server],
+                &domains(&["example.com", "wikipedia.org"]),
+                Duration::from_secs(2)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_next_server() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = stand_in(true);
+        assert_eq!(
+            check_addresses(
+                &[silent.local_addr().unwrap(), server],
+                &domains(&["example.com"]),
+                Duration::from_millis(200)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn empty_or_missing_replies_fail() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let empty = stand_in(false);
+        assert_eq!(
+            check_addresses(
+                &[silent.local_addr().unwrap(), empty],
+                &domains(&["example.com"]),
+                Duration::from_millis(200)
+            ),
+            Err("The new DNS servers could not resolve example.com".to_string())
+        );
+    }
+}
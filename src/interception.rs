@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{self, QueryError, RecordType, DNS_PORT};
+use crate::domain::{DnsProvider, OperationResult};
+
+/// Names answered with the address of the resolver that asked the
+/// authoritative server, whichever address the query was sent to.
+const WHOAMI: [(&str, RecordType); 2] = [
+    ("whoami.akamai.net", RecordType::A),
+    ("o-o.myaddr.l.google.com", RecordType::Txt),
+];
+
+/// An address in TEST-NET-1, which is never routed. Anything answering
+/// there is something on the path pretending to be a resolver.
+pub const BOGUS_RESOLVER: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)), DNS_PORT);
+
+/// A server to check and the provider it is supposed to belong to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Server {
+    pub provider: String,
+    pub address: SocketAddr,
+}
+
+impl Server {
+    /// The configured servers labelled with the provider they belong to,
+    /// followed by the first IPv4 server of every other built-in provider
+    /// so there is always something to compare against.
+    pub fn to_check(configured: &[String]) -> Vec<Server> {
+        let providers = DnsProvider::built_in();
+        let mut servers: Vec<Server> = Vec::new();
+        for server in configured {
+            let Ok(ip) = server.trim().parse::<IpAddr>() else {
+                continue;
+            };
+            let provider = providers
+                .iter()
+                .find(|p| p.servers().iter().any(|s| s.parse() == Ok(ip)))
+                .map(|p| p.display_name())
+                .unwrap_or_else(|| "Configured".to_string());
+            servers.push(Server {
+                provider,
+                address: SocketAddr::new(ip, DNS_PORT),
+            });
+        }
+        for provider in providers {
+            let name = provider.display_name();
+            if servers.iter().any(|s| s.provider == name) {
+                continue;
+            }
+            let first = provider.servers().iter().find_map(|s| s.parse().ok());
+            if let Some(ip @ IpAddr::V4(_)) = first {
+                servers.push(Server {
+                    provider: name,
+                    address: SocketAddr::new(ip, DNS_PORT),
+                });
+            }
+        }
+        servers
+    }
+}
+
+impl std::fmt::Display for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.provider, self.address.ip())
+    }
+}
+
+/// What one server answered.
+#[derive(Debug, Clone, PartialEq)]
+struct Fingerprint {
+    /// The whoami answers, i.e. where the resolver really lives.
+    egress: Vec<String>,
+    /// The answers for the probe domain with their remaining TTLs, which
+    /// only match between resolvers that share a cache.
+    cached: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    Clean,
+    Suspicious,
+    Intercepted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub verdict: Verdict,
+    pub findings: Vec<String>,
+}
+
+impl From<Report> for OperationResult {
+    fn from(report: Report) -> Self {
+        let headline = match report.verdict {
+            Verdict::Clean => "No sign of DNS interception",
+            Verdict::Suspicious => "DNS may be intercepted",
+            Verdict::Intercepted => "DNS is being intercepted",
+        };
+        let text = std::iter::once(headline.to_string())
+            .chain(report.findings)
+            .collect::<Vec<_>>()
+            .join("\n");
+        match report.verdict {
+            Verdict::Clean => OperationResult::Success(text),
+            Verdict::Suspicious => OperationResult::Warning(text),
+            Verdict::Intercepted => OperationResult::Error(text),
+        }
+    }
+}
+
+/// Asks `bogus` and every server who they are and what they have cached
+/// for `probe_domain`, all in parallel, and looks for answers that should
+/// not be there.
+pub fn check(
+    servers: &[Server],
+    bogus: SocketAddr,
+    probe_domain: &str,
+    timeout: Duration,
+) -> Report {
+    let (bogus_answer, fingerprints) = thread::scope(|scope| {
+        let bogus = scope.spawn(move || {
+            let (name, qtype) = WHOAMI[0];
+            dns::query(bogus, name, qtype, timeout)
+        });
+        let handles: Vec<_> = servers
+            .iter()
+            .map(|server| scope.spawn(move || fingerprint(server.address, probe_domain, timeout)))
+            .collect();
+        let fingerprints: Vec<Result<Fingerprint, QueryError>> = handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(QueryError::Failed("check panicked".to_string())))
+            })
+            .collect();
+        (bogus.join().ok().and_then(Result::ok), fingerprints)
+    });
+
+    let mut verdict = Verdict::Clean;
+    let mut findings = Vec::new();
+
+    if let Some(response) = bogus_answer {
+        verdict = Verdict::Intercepted;
+        let egress: Vec<String> = response
+            .message
+            .answers
+            .iter()
+            .map(|record| record.data.to_string())
+            .collect();
+        findings.push(format!(
+            "{} is not a resolver but answered {}; port 53 traffic is being redirected",
+            bogus,
+            response.message.rcode()
+        ));
+        for (server, fingerprint) in servers.iter().zip(&fingerprints) {
+            let Ok(fingerprint) = fingerprint else {
+                continue;
+            };
+            if egress.iter().any(|e| fingerprint.egress.contains(e)) {
+                findings.push(format!("{} is answered by the interceptor", server));
+            }
+        }
+    }
+
+    let answered: Vec<(&Server, &Fingerprint)> = servers
+        .iter()
+        .zip(&fingerprints)
+        .filter_map(|(server, fingerprint)| Some((server, fingerprint.as_ref().ok()?)))
+        .collect();
+
+    let mut by_egress: BTreeMap<&[String], Vec<&Server>> = BTreeMap::new();
+    for (server, fingerprint) in &answered {
+        if !fingerprint.egress.is_empty() {
+            by_egress
+                .entry(&fingerprint.egress)
+                .or_default()
+                .push(server);
+        }
+    }
+    for (egress, group) in &by_egress {
+        if spans_providers(group) {
+            verdict = verdict.max(Verdict::Intercepted);
+            findings.push(format!(
+                "{} are all answered by the same resolver ({})",
+                list(group),
+                egress.join(", ")
+            ));
+        }
+    }
+
+    let mut by_cache: BTreeMap<&[(String, u32)], Vec<&Server>> = BTreeMap::new();
+    for (server, fingerprint) in &answered {
+        if !fingerprint.cached.is_empty() {
+            by_cache
+                .entry(&fingerprint.cached)
+                .or_default()
+                .push(server);
+        }
+    }
+    for group in by_cache.values() {
+        let egress: Vec<&[String]> = answered
+            .iter()
+            .filter(|(server, _)| group.contains(server))
+            .map(|(_, fingerprint)| fingerprint.egress.as_slice())
+            .collect();
+        // Groups already reported above need no second finding.
+        if spans_providers(group) && egress.windows(2).any(|pair| pair[0] != pair[1]) {
+            verdict = verdict.max(Verdict::Suspicious);
+            findings.push(format!(
+                "{} returned identical answers and TTLs for {}, as if they shared a cache",
+                list(group),
+                probe_domain
+            ));
+        }
+    }
+
+    for (server, fingerprint) in servers.iter().zip(&fingerprints) {
+        if let Err(e) = fingerprint {
+            findings.push(format!("{} could not be checked: {}", server, e));
+        }
+    }
+    if verdict == Verdict::Clean {
+        findings.insert(
+            0,
+            format!(
+                "{} servers answered through {} different resolvers",
+                answered.len(),
+                by_egress.len()
+            ),
+        );
+    }
+
+    Report { verdict, findings }
+}
+
+fn fingerprint(
+    server: SocketAddr,
+    probe_domain: &str,
+    timeout: Duration,
+) -> Result<Fingerprint, QueryError> {
+    let mut egress = Vec::new();
+    for (name, qtype) in WHOAMI {
+        let response = dns::query(server, name, qtype, timeout)?;
+        egress.extend(response.message.answers.iter().map(|r| r.data.to_string()));
+    }
+    egress.sort();
+
+    let response = dns::query(server, probe_domain, RecordType::A, timeout)?;
+    let mut cached: Vec<(String, u32)> = response
+        .message
+        .answers
+        .iter()
+        .map(|record| (record.data.to_string(), record.ttl))
+        .collect();
+    cached.sort();
+
+    Ok(Fingerprint { egress, cached })
+}
+
+fn spans_providers(group: &[&Server]) -> bool {
+    group
+        .iter()
+        .any(|server| server.provider != group[0].provider)
+}
+
+fn list(group: &[&Server]) -> String {
+    group
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{testing, RecordData};
+    use std::net::UdpSocket;
+
+    const TIMEOUT: Duration = Duration::from_millis(300);
+
+    /// Local resolver whose whoami answers are `egress` and whose answer for
+    /// every other name is 93.184.216.34 with `ttl` left.
+    fn fake_resolver(egress: [u8; 4], ttl: u32) -> SocketAddr {
+        let egress = Ipv4Addr::from(egress);
+        testing::resolver(move |query| {
+            let question = &query.questions[0];
+            let whoami = question.name.contains("whoami") || question.name.contains("myaddr");
+            let reply = match (whoami, question.qtype) {
+                (true, RecordType::Txt) => {
+                    testing::answer(query, 60, [RecordData::Txt(vec![egress.to_string()])])
+                }
+                (true, _) => testing::answer(query, 20, [RecordData::A(egress)]),
+                (false, _) => {
+                    testing::answer(query, ttl, [RecordData::A(Ipv4Addr::new(93, 184, 216, 34))])
+                }
+            };
+            vec![reply]
+        })
+    }
+
+    /// A local address that swallows queries, like an unrouted one.
+    fn silent() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let _socket = socket;
+            thread::sleep(Duration::from_secs(5));
+        });
+        address
+    }
+
+    fn server(provider: &str, address: SocketAddr) -> Server {
+        Server {
+            provider: provider.to_string(),
+            address,
+        }
+    }
+
+    #[test]
+    fn separate_resolvers_are_clean() {
+        let servers = [
+            server("Shekan", fake_resolver([198, 51, 100, 1], 120)),
+            server("Shekan", fake_resolver([198, 51, 100, 1], 120)),
+            server("Quad9", fake_resolver([198, 51, 100, 2], 45)),
+        ];
+
+        let report = check(&servers, silent(), "example.com", TIMEOUT);
+
+        assert_eq!(report.verdict, Verdict::Clean);
+        assert_eq!(
+            report.findings,
+            ["3 servers answered through 2 different resolvers"]
+        );
+    }
+
+    #[test]
+    fn an_answer_from_the_bogus_address_means_interception() {
+        let interceptor = fake_resolver([203, 0, 113, 9], 300);
+        let servers = [
+            server("Shekan", fake_resolver([203, 0, 113, 9], 300)),
+            server("Quad9", fake_resolver([198, 51, 100, 2], 45)),
+        ];
+
+        let report = check(&servers, interceptor, "example.com", TIMEOUT);
+
+        assert_eq!(report.verdict, Verdict::Intercepted);
+        assert_eq!(
+            report.findings[0],
+            format!(
+                "{} is not a resolver but answered NOERROR; port 53 traffic is being redirected",
+                interceptor
+            )
+        );
+        assert_eq!(
+            report.findings[1],
+            "Shekan (127.0.0.1) is answered by the interceptor"
+        );
+    }
+
+    #[test]
+    fn different_providers_behind_one_resolver_are_intercepted() {
+        let servers = [
+            server("Shekan", fake_resolver([203, 0, 113, 9], 300)),
+            server("Quad9", fake_resolver([203, 0, 113, 9], 300)),
+        ];
+
+        let report = check(&servers, silent(), "example.com", TIMEOUT);
+
+        assert_eq!(report.verdict, Verdict::Intercepted);
+        assert_eq!(
+            report.findings,
+            [
+                "Shekan (127.0.0.1), Quad9 (127.0.0.1) are all answered by the same resolver \
+              (\"203.0.113.9\", 203.0.113.9)"
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_ttls_from_different_resolvers_are_suspicious() {
+        let servers = [
+            server("Shekan", fake_resolver([198, 51, 100, 1], 217)),
+            server("Quad9", fake_resolver([198, 51, 100, 2], 217)),
+            server("Radar", silent()),
+        ];
+
+        let report = check(&servers, silent(), "example.com", TIMEOUT);
+
+        assert_eq!(report.verdict, Verdict::Suspicious);
+        assert_eq!(
+            report.findings[0],
+            "Shekan (127.0.0.1), Quad9 (127.0.0.1) returned identical answers and TTLs for \
+             example.com, as if they shared a cache"
+        );
+        assert!(report.findings[1].starts_with("Radar (127.0.0.1) could not be checked"));
+        assert!(matches!(
+            OperationResult::from(report),
+            OperationResult::Warning(text) if text.starts_with("DNS may be intercepted\n")
+        ));
+    }
+
+    #[test]
+    fn configured_servers_are_labelled_and_compared_with_other_providers() {
+        let servers = Server::to_check(&["9.9.9.9".to_string(), "10.0.0.1".to_string()]);
+
+        assert_eq!(servers[0], server("Quad9", "9.9.9.9:53".parse().unwrap()));
+        assert_eq!(
+            servers[1],
+            server("Configured", "10.0.0.1:53".parse().unwrap())
+        );
+        assert!(servers.iter().all(|s| s.address.is_ipv4()));
+        assert_eq!(servers.iter().filter(|s| s.provider == "Quad9").count(), 1);
+        assert!(servers.iter().any(|s| s.provider == "Shekan"));
+    }
+}
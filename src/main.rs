@@ -1,7 +1,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod app;
+mod auto_select;
+mod benchmark;
+mod compare;
+mod dns;
 mod domain;
+mod forwarder;
+mod health;
+mod interception;
+mod reachability;
 mod storage;
 mod system;
 mod textures;
@@ -13,11 +21,12 @@ use eframe::egui;
 
 fn main() -> eframe::Result {
     env_logger::init();
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_decorations(false)
-            .with_inner_size([250.0, 520.0])
-            .with_min_inner_size([250.0, 520.0])
+            .with_inner_size([250.0, 620.0])
+            .with_min_inner_size([250.0, 620.0])
             .with_transparent(true),
         centered: true,
         ..Default::default()
@@ -28,7 +37,7 @@ fn main() -> eframe::Result {
         options,
         Box::new(|cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Ok(Box::new(MyApp::new()))
+            Ok(Box::new(MyApp::new(dry_run)))
         }),
     )
 }
@@ -0,0 +1,464 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use rustls::ClientConfig;
+
+use crate::compare::is_sinkhole;
+use crate::dns::{self, QueryError, Rcode, RecordData, RecordType, DNS_PORT};
+use crate::domain::DnsProvider;
+
+const HTTPS_PORT: u16 = 443;
+
+/// CNAMEs followed before giving up on a name.
+const MAX_CNAME_HOPS: usize = 8;
+
+/// Redirect targets that mean the national filter's block page.
+const BLOCK_PAGES: [&str; 2] = ["peyvandha.ir", "10.10.34."];
+
+/// How far one host got through one provider.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reachability {
+    /// The provider gave no address for the host.
+    Unresolved(String),
+    /// The address is a filtering sinkhole, so nothing was sent to it.
+    Sinkhole(IpAddr),
+    /// Nothing accepted a connection on port 443.
+    NoConnect {
+        ip: IpAddr,
+        error: String,
+    },
+    /// The connection opened but TLS or HTTP failed on it.
+    NoHttps {
+        ip: IpAddr,
+        error: String,
+    },
+    /// Whoever answered on port 443 could not prove it is the host, which
+    /// is what an intercepting middlebox looks like.
+    BadCertificate {
+        ip: IpAddr,
+        error: String,
+    },
+    /// The service itself refused the region with 403 or 451.
+    Blocked {
+        ip: IpAddr,
+        status: u16,
+    },
+    /// The request was redirected to a block page.
+    BlockPage {
+        ip: IpAddr,
+        location: String,
+    },
+    Reachable {
+        ip: IpAddr,
+        status: u16,
+    },
+}
+
+impl Reachability {
+    /// Short text for a grid cell.
+    pub fn label(&self) -> String {
+        match self {
+            Reachability::Unresolved(_) => "✖ DNS".to_string(),
+            Reachability::Sinkhole(_) => "⛔ sinkhole".to_string(),
+            Reachability::NoConnect { .. } => "✖ TCP".to_string(),
+            Reachability::NoHttps { .. } => "✖ TLS".to_string(),
+            Reachability::BadCertificate { .. } => "⛔ certificate".to_string(),
+            Reachability::Blocked { status, .. } => format!("⛔ {}", status),
+            Reachability::BlockPage { .. } => "⛔ block page".to_string(),
+            Reachability::Reachable { status, .. } => format!("✔ {}", status),
+        }
+    }
+
+    /// Every step that was taken, for the cell's tooltip.
+    pub fn details(&self) -> String {
+        match self {
+            Reachability::Unresolved(error) => format!("Did not resolve: {}", error),
+            Reachability::Sinkhole(ip) => format!("Resolved to the sinkhole {}", ip),
+            Reachability::NoConnect { ip, error } => {
+                format!("Resolved to {}\nTCP 443 failed: {}", ip, error)
+            }
+            Reachability::NoHttps { ip, error } => {
+                format!("Resolved to {}\nTCP 443 OK\nHEAD failed: {}", ip, error)
+            }
+            Reachability::BadCertificate { ip, error } => format!(
+                "Resolved to {}\nTCP 443 OK\n{}\nLikely intercepted",
+                ip, error
+            ),
+            Reachability::Blocked { ip, status } => {
+                format!("Resolved to {}\nTCP 443 OK\nHEAD answered {}", ip, status)
+            }
+            Reachability::BlockPage { ip, location } => format!(
+                "Resolved to {}\nTCP 443 OK\nHEAD redirected to {}",
+                ip, location
+            ),
+            Reachability::Reachable { ip, status } => {
+                format!("Resolved to {}\nTCP 443 OK\nHEAD answered {}", ip, status)
+            }
+        }
+    }
+
+    /// Filtered or geo-blocked, as opposed to merely failing.
+    pub fn is_blocked(&self) -> bool {
+        matches!(
+            self,
+            Reachability::Sinkhole(_)
+                | Reachability::BadCertificate { .. }
+                | Reachability::Blocked { .. }
+                | Reachability::BlockPage { .. }
+        )
+    }
+}
+
+/// One cell of the grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub provider: String,
+    pub host: String,
+    pub result: Reachability,
+}
+
+/// Checks every host through every provider in parallel and sends each
+/// cell as soon as it is known.
+pub fn run(
+    providers: Vec<DnsProvider>,
+    hosts: &[String],
+    timeout: Duration,
+    results: mpsc::Sender<Cell>,
+) {
+    thread::scope(|scope| {
+        for provider in &providers {
+            let servers: Vec<SocketAddr> = provider
+                .servers()
+                .iter()
+                .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+                .collect();
+            for host in hosts {
+                let results = results.clone();
+                let servers = servers.clone();
+                let provider = provider.display_name();
+                scope.spawn(move || {
+                    let result = check(&servers, host, timeout, dns::default_tls());
+                    let _ = results.send(Cell {
+                        provider,
+                        host: host.clone(),
+                        result,
+                    });
+                });
+            }
+        }
+    });
+}
+
+fn check(
+    servers: &[SocketAddr],
+    host: &str,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Reachability {
+    let ip = match resolve(servers, host, timeout) {
+        Ok(ip) => ip,
+        Err(e) => return Reachability::Unresolved(e),
+    };
+    if is_sinkhole(ip) {
+        return Reachability::Sinkhole(ip);
+    }
+    reach(ip, HTTPS_PORT, host, timeout, tls)
+}
+
+/// The first IPv4 address the first answering server gives for `host`.
+fn resolve(servers: &[SocketAddr], host: &str, timeout: Duration) -> Result<IpAddr, String> {
+    let mut last_error = "no servers configured".to_string();
+    for server in servers {
+        match resolve_with(*server, host, timeout) {
+            Ok(answer) => return answer,
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+/// Asks one server for `host`, following CNAMEs it answered without the
+/// address. The outer error means the server itself did not answer.
+fn resolve_with(
+    server: SocketAddr,
+    host: &str,
+    timeout: Duration,
+) -> Result<Result<IpAddr, String>, QueryError> {
+    let mut name = host.to_string();
+    for _ in 0..MAX_CNAME_HOPS {
+        let message = dns::query(server, &name, RecordType::A, timeout)?.message;
+        if message.rcode() != Rcode::NOERROR {
+            return Ok(Err(message.rcode().to_string()));
+        }
+        let address = message.answers.iter().find_map(|record| match record.data {
+            RecordData::A(ip) => Some(IpAddr::V4(ip)),
+            _ => None,
+        });
+        if let Some(address) = address {
+            return Ok(Ok(address));
+        }
+
+        // The chain may go on in the same answer before it stops.
+        let mut target: Option<String> = None;
+        for _ in 0..message.answers.len() {
+            let current = target.as_deref().unwrap_or(&name);
+            let next = message
+                .answers
+                .iter()
+                .find_map(|record| match &record.data {
+                    RecordData::Cname(next) if record.name.eq_ignore_ascii_case(current) => {
+                        Some(next.clone())
+                    }
+                    _ => None,
+                });
+            match next {
+                Some(next) => target = Some(next),
+                None => break,
+            }
+        }
+        match target {
+            Some(target) => name = target,
+            None => return Ok(Err("no address".to_string())),
+        }
+    }
+    Ok(Err(format!("too many CNAMEs after {}", host)))
+}
+
+/// Connects to `ip` and asks for `host`'s front page.
+fn reach(
+    ip: IpAddr,
+    port: u16,
+    host: &str,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+) -> Reachability {
+    let socket = match TcpStream::connect_timeout(&SocketAddr::new(ip, port), timeout) {
+        Ok(socket) => socket,
+        Err(e) => {
+            return Reachability::NoConnect {
+                ip,
+                error: e.to_string(),
+            }
+        }
+    };
+    let response = match dns::head(socket, host, timeout, tls) {
+        Ok(response) => response,
+        Err(QueryError::Certificate(error)) => return Reachability::BadCertificate { ip, error },
+        Err(e) => {
+            return Reachability::NoHttps {
+                ip,
+                error: e.to_string(),
+            }
+        }
+    };
+
+    let location = response
+        .header("Location")
+        .filter(|location| BLOCK_PAGES.iter().any(|page| location.contains(page)));
+    match (location, response.status) {
+        (Some(location), _) => Reachability::BlockPage {
+            ip,
+            location: location.to_string(),
+        },
+        (None, status @ (403 | 451)) => Reachability::Blocked { ip, status },
+        (None, status) => Reachability::Reachable { ip, status },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing::{self, certificate};
+    use crate::dns::{Message, Record};
+    use rustls::{ServerConnection, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Local resolver answering every A query with `ip`, or with `rcode`
+    /// alone when it is not NOERROR.
+    fn fake_resolver(rcode: Rcode, ip: [u8; 4]) -> SocketAddr {
+        testing::resolver(move |query| {
+            let reply = if rcode == Rcode::NOERROR {
+                testing::answer(query, 60, [RecordData::A(ip.into())])
+            } else {
+                Message::reply_to(query, rcode)
+            };
+            vec![reply]
+        })
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        Record {
+            name: name.to_string(),
+            rtype: RecordType::Cname,
+            ttl: 60,
+            data: RecordData::Cname(target.to_string()),
+        }
+    }
+
+    /// Local HTTPS site for "localhost" answering every request with
+    /// `status` and the extra header lines in `headers`.
+    fn site(status: u16, headers: &'static str) -> (u16, Arc<ClientConfig>) {
+        let (server_config, client_config) = certificate();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let connection = ServerConnection::new(server_config.clone()).unwrap();
+                let mut tls = StreamOwned::new(connection, stream);
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    if tls.read_exact(&mut byte).is_err() {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                assert!(head.starts_with(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n"));
+                // A HEAD answer announces a body it does not send.
+                let reply = format!(
+                    "HTTP/1.1 {} Test\r\n{}Content-Length: 5120\r\n\r\n",
+                    status, headers
+                );
+                let _ = tls.write_all(reply.as_bytes());
+                let _ = tls.flush();
+            }
+        });
+        (port, client_config)
+    }
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn head_status_and_redirects_are_classified() {
+        let (port, tls) = site(200, "");
+        assert_eq!(
+            reach(LOCAL, port, "localhost", TIMEOUT, tls),
+            Reachability::Reachable {
+                ip: LOCAL,
+                status: 200
+            }
+        );
+
+        let (port, tls) = site(451, "");
+        let result = reach(LOCAL, port, "localhost", TIMEOUT, tls);
+        assert_eq!(
+            result,
+            Reachability::Blocked {
+                ip: LOCAL,
+                status: 451
+            }
+        );
+        assert!(result.is_blocked());
+
+        let (port, tls) = site(302, "Location: http://10.10.34.35/\r\n");
+        assert_eq!(
+            reach(LOCAL, port, "localhost", TIMEOUT, tls),
+            Reachability::BlockPage {
+                ip: LOCAL,
+                location: "http://10.10.34.35/".to_string()
+            }
+        );
+
+        let (port, tls) = site(301, "Location: https://www.localhost/\r\n");
+        assert_eq!(
+            reach(LOCAL, port, "localhost", TIMEOUT, tls).label(),
+            "✔ 301"
+        );
+    }
+
+    #[test]
+    fn connection_and_tls_failures_are_told_apart() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let (_, tls) = certificate();
+        assert!(matches!(
+            reach(LOCAL, port, "localhost", TIMEOUT, tls),
+            Reachability::NoConnect { .. }
+        ));
+
+        let hangs_up = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = hangs_up.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in hangs_up.incoming() {
+                drop(stream);
+            }
+        });
+        let (_, tls) = certificate();
+        assert!(matches!(
+            reach(LOCAL, port, "localhost", TIMEOUT, tls),
+            Reachability::NoHttps { .. }
+        ));
+    }
+
+    #[test]
+    fn certificates_for_another_host_look_like_interception() {
+        let (port, tls) = site(200, "");
+        let result = reach(LOCAL, port, "registry.example", TIMEOUT, tls);
+
+        assert!(
+            matches!(&result, Reachability::BadCertificate { error, .. } if error.contains("registry.example")),
+            "{:?}",
+            result
+        );
+        assert!(result.is_blocked());
+        assert_eq!(result.label(), "⛔ certificate");
+    }
+
+    #[test]
+    fn cnames_answered_without_an_address_are_followed() {
+        let server = testing::resolver(|query| {
+            let name = query.questions[0].name.clone();
+            let reply = match name.as_str() {
+                "www.registry.example" => {
+                    let mut reply = Message::reply_to(query, Rcode::NOERROR);
+                    reply
+                        .answers
+                        .push(cname("www.registry.example", "cdn.example"));
+                    reply.answers.push(cname("cdn.example", "edge.cdn.example"));
+                    reply
+                }
+                "edge.cdn.example" => {
+                    testing::answer(query, 60, [RecordData::A(Ipv4Addr::new(198, 51, 100, 7))])
+                }
+                _ => Message::reply_to(query, Rcode::NOERROR),
+            };
+            vec![reply]
+        });
+
+        assert_eq!(
+            resolve(&[server], "www.registry.example", TIMEOUT),
+            Ok(IpAddr::from([198, 51, 100, 7]))
+        );
+        assert_eq!(
+            resolve(&[server], "empty.example", TIMEOUT),
+            Err("no address".to_string())
+        );
+    }
+
+    #[test]
+    fn unresolved_and_sinkholed_hosts_stop_before_connecting() {
+        let (_, tls) = certificate();
+
+        let missing = fake_resolver(Rcode::NXDOMAIN, [0; 4]);
+        assert_eq!(
+            check(&[missing], "registry.example", TIMEOUT, tls.clone()),
+            Reachability::Unresolved("NXDOMAIN".to_string())
+        );
+
+        let filtered = fake_resolver(Rcode::NOERROR, [10, 10, 34, 36]);
+        let result = check(&[filtered], "registry.example", TIMEOUT, tls);
+        assert_eq!(
+            result,
+            Reachability::Sinkhole(IpAddr::from([10, 10, 34, 36]))
+        );
+        assert_eq!(result.label(), "⛔ sinkhole");
+    }
+}
@@ -1,34 +1,32 @@
 use std::fs;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::domain::SavedDnsEntry;
+use crate::domain::{AppSettings, RoutingRule, RulesFile, SavedDnsEntry};
 
-fn get_storage_path() -> PathBuf {
+fn get_storage_path(file_name: &str) -> PathBuf {
     let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
     path.push("dnsight");
     fs::create_dir_all(&path).ok();
-    path.push("saved_dns.json");
+    path.push(file_name);
     path
 }
 
 pub fn load_saved_dns() -> Vec<SavedDnsEntry> {
-    let path = get_storage_path();
+    let path = get_storage_path("saved_dns.json");
 
     if !path.exists() {
         return Vec::new();
     }
 
     match fs::read_to_string(&path) {
-        Ok(content) => match serde_json::from_str::<Vec<SavedDnsEntry>>(&content) {
-            Ok(entries) => entries,
-            Err(_) => Vec::new(),
-        },
+        Ok(content) => serde_json::from_str::<Vec<SavedDnsEntry>>(&content).unwrap_or_default(),
         Err(_) => Vec::new(),
     }
 }
 
 pub fn save_dns_entries(entries: &[SavedDnsEntry]) -> Result<(), String> {
-    let path = get_storage_path();
+    let path = get_storage_path("saved_dns.json");
 
     let json =
         serde_json::to_string_pretty(entries).map_err(|e| format!("Failed to serialize: {}", e))?;
@@ -49,3 +47,50 @@ pub fn delete_saved_dns(name: &str) -> Result<(), String> {
     entries.retain(|e| e.name != name);
     save_dns_entries(&entries)
 }
+
+pub fn load_settings() -> AppSettings {
+    fs::read_to_string(get_storage_path("settings.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_settings(settings: &AppSettings) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize: {}", e))?;
+
+    fs::write(get_storage_path("settings.json"), json)
+        .map_err(|e| format!("Failed to write file: {}", e))
+}
+
+/// No file means no rules. A file from a newer version is an error.
+pub fn load_rules() -> Result<Vec<RoutingRule>, String> {
+    let path = get_storage_path("rules.json");
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    RulesFile::parse(&content)
+}
+
+pub fn save_rules(rules: &[RoutingRule]) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&RulesFile::new(rules.to_vec()))
+        .map_err(|e| format!("Failed to serialize: {}", e))?;
+
+    fs::write(get_storage_path("rules.json"), json)
+        .map_err(|e| format!("Failed to write file: {}", e))
+}
+
+/// Writes an exported query log next to the settings and returns its path.
+pub fn save_query_log(jsonl: &str) -> Result<PathBuf, String> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let path = get_storage_path(&format!("queries-{}.jsonl", seconds));
+
+    fs::write(&path, jsonl).map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(path)
+}
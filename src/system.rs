@@ -1,115 +1,244 @@
-use std::os::windows::process::CommandExt;
-use std::process::{Command, Stdio};
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::sync::Arc;
 
-use regex::Regex;
+use crate::domain::{AdapterInfo, DnsSnapshot, DnsState, OperationResult};
 
-use crate::domain::OperationResult;
+mod command;
+mod netsh;
+mod network_manager;
+mod resolv_conf;
+mod resolved;
+mod transaction;
 
-const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+pub use command::{CommandRunner, DryRunRunner, Plan, SystemCommandRunner};
+pub use netsh::NetshBackend;
+pub use network_manager::NetworkManagerBackend;
+pub use resolv_conf::ResolvConfBackend;
+pub use resolved::ResolvedBackend;
+pub use transaction::{apply_with_rollback, restore_all};
 
-fn run_netsh(args: &[&str]) -> std::process::Output {
-    Command::new("netsh")
-        .args(args)
-        .creation_flags(CREATE_NO_WINDOW)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to run netsh")
-        .wait_with_output()
-        .expect("Failed to wait for netsh")
-}
+const RESOLVED_STUB_ADDRESS: &str = "127.0.0.53";
+
+/// Platform-specific way of inspecting and changing the DNS configuration
+/// of a network adapter.
+pub trait DnsBackend: Send + Sync {
+    /// Short identifier used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Returns the adapter (or link) that currently carries the connection.
+    fn active_adapter(&self) -> Option<String>;
+
+    /// Lists the adapters DNS can be set on, with their current servers.
+    fn adapters(&self) -> Vec<AdapterInfo> {
+        self.active_adapter()
+            .map(|name| {
+                vec![AdapterInfo {
+                    dns: self.current_dns(&name),
+                    index: interface_index(&name).unwrap_or_default(),
+                    r#virtual: is_virtual_interface(&name),
+                    name,
+                    connected: true,
+                    ..Default::default()
+                }]
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the DNS servers currently configured on `adapter`.
+    fn current_dns(&self, adapter: &str) -> Vec<String>;
 
-pub fn get_active_adapter() -> Option<String> {
-    let output = run_netsh(&["interface", "show", "interface"]);
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    /// Describes where the DNS servers of `adapter` come from. Backends that
+    /// can tell DHCP servers apart override this; by default every server
+    /// counts as static.
+    fn dns_state(&self, adapter: &str) -> DnsState {
+        let dns = self.current_dns(adapter);
+        if dns.is_empty() {
+            DnsState::None
+        } else {
+            DnsState::Static(dns)
+        }
+    }
+
+    /// Points `adapter` at `servers`, in order. IPv4 and IPv6 addresses may
+    /// be mixed; each backend applies them to the matching protocol.
+    fn set_dns(&self, adapter: &str, servers: &[String]) -> OperationResult;
+
+    /// Returns `adapter` to automatically assigned DNS servers.
+    fn reset_dns(&self, adapter: &str) -> OperationResult;
+
+    /// Records what `restore` needs to put `adapter` back the way it is now.
+    fn snapshot(&self, adapter: &str) -> DnsSnapshot {
+        DnsSnapshot::new(adapter, self.dns_state(adapter))
+    }
 
-    for line in stdout.lines() {
-        if line.contains("Connected") && line.contains("Dedicated") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            return parts.last().map(|s| s.to_string());
+    /// Puts an adapter back to `snapshot`. Static servers are applied again;
+    /// DHCP and an empty configuration both go back to automatic. Backends
+    /// that configure IPv4 and IPv6 separately restore each on its own.
+    fn restore(&self, snapshot: &DnsSnapshot) -> OperationResult {
+        match snapshot.static_servers() {
+            Some(servers) => self.set_dns(&snapshot.adapter, &servers),
+            None => self.reset_dns(&snapshot.adapter),
         }
     }
-    None
 }
 
-pub fn get_current_dns(adapter: &str) -> Vec<String> {
-    let output = run_netsh(&[
-        "interface",
-        "ip",
-        "show",
-        "dns",
-        &format!("name={}", adapter),
-    ]);
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    let re = Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}\b").unwrap();
-    re.find_iter(&stdout)
-        .map(|m| m.as_str().to_string())
-        .collect()
+/// Cheaply clonable handle to the backend the app talks to, so it can be
+/// moved into worker threads.
+#[derive(Clone)]
+pub struct SharedBackend(Arc<dyn DnsBackend>);
+
+impl SharedBackend {
+    pub fn new(backend: impl DnsBackend + 'static) -> Self {
+        Self(Arc::new(backend))
+    }
+}
+
+/// Inert placeholder; use [`default_backend`] to pick a real one.
+impl Default for SharedBackend {
+    fn default() -> Self {
+        Self::new(UnsupportedBackend)
+    }
+}
+
+impl Deref for SharedBackend {
+    type Target = dyn DnsBackend;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
 }
 
-pub fn set_dns_with_result(interface: &str, primary: &str, secondary: &str) -> OperationResult {
-    let output1 = run_netsh(&[
-        "interface",
-        "ipv4",
-        "set",
-        "dns",
-        &format!("name={}", interface),
-        "static",
-        primary,
-    ]);
-
-    if !output1.status.success() {
-        return OperationResult::Error(format!(
-            "Error setting primary DNS {}: {}",
-            primary,
-            String::from_utf8_lossy(&output1.stderr)
-        ));
+/// Backend used when nothing better is available for the current platform.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnsupportedBackend;
+
+impl DnsBackend for UnsupportedBackend {
+    fn name(&self) -> &'static str {
+        "unsupported"
+    }
+
+    fn active_adapter(&self) -> Option<String> {
+        None
     }
 
-    let output2 = run_netsh(&[
-        "interface",
-        "ipv4",
-        "add",
-        "dns",
-        &format!("name={}", interface),
-        secondary,
-        "index=2",
-    ]);
-
-    if !output2.status.success() {
-        return OperationResult::Error(format!(
-            "Error setting secondary DNS {}: {}",
-            secondary,
-            String::from_utf8_lossy(&output2.stderr)
-        ));
+    fn current_dns(&self, _adapter: &str) -> Vec<String> {
+        Vec::new()
     }
 
-    OperationResult::Success(format!(
-        "DNS servers {} and {} set successfully for '{}'",
-        primary, secondary, interface
-    ))
+    fn set_dns(&self, _adapter: &str, _servers: &[String]) -> OperationResult {
+        OperationResult::Error("Changing DNS is not supported on this platform".to_string())
+    }
+
+    fn reset_dns(&self, _adapter: &str) -> OperationResult {
+        OperationResult::Error("Changing DNS is not supported on this platform".to_string())
+    }
+}
+
+/// Picks the backend that matches the platform the app is running on.
+pub fn default_backend() -> SharedBackend {
+    platform_backend(None)
+}
+
+/// Same backend as `default_backend`, but every change it would make is
+/// recorded in `plan` instead of being carried out.
+pub fn dry_run_backend(plan: Plan) -> SharedBackend {
+    platform_backend(Some(plan))
 }
 
-pub fn clear_dns_with_result(interface: &str) -> OperationResult {
-    let output = run_netsh(&[
-        "interface",
-        "ipv4",
-        "set",
-        "dns",
-        &format!("name={}", interface),
-        "source=dhcp",
-    ]);
-    if output.status.success() {
-        OperationResult::Success(format!(
-            "DNS reset to DHCP successfully for '{}'",
-            interface
-        ))
+fn platform_backend(plan: Option<Plan>) -> SharedBackend {
+    let runner: Arc<dyn CommandRunner> = match &plan {
+        Some(plan) => Arc::new(DryRunRunner::new(SystemCommandRunner, plan.clone())),
+        None => Arc::new(SystemCommandRunner),
+    };
+
+    if cfg!(windows) {
+        SharedBackend::new(NetshBackend::with_runner(runner))
+    } else if cfg!(target_os = "linux") {
+        if uses_network_manager() {
+            SharedBackend::new(NetworkManagerBackend::with_runner(runner))
+        } else if uses_systemd_resolved() {
+            SharedBackend::new(ResolvedBackend::with_runner(runner))
+        } else {
+            let backend = ResolvConfBackend::default();
+            match plan {
+                Some(plan) => SharedBackend::new(backend.dry_run(plan)),
+                None => SharedBackend::new(backend),
+            }
+        }
     } else {
-        OperationResult::Error(format!(
-            "Error resetting DNS for '{}': {}",
-            interface,
-            String::from_utf8_lossy(&output.stderr)
-        ))
+        SharedBackend::new(UnsupportedBackend)
     }
 }
+
+/// Returns the interface that carries the IPv4 default route, read from
+/// `/proc/net/route`.
+pub(crate) fn default_route_interface() -> Option<String> {
+    std::fs::read_to_string("/proc/net/route")
+        .ok()
+        .and_then(|table| parse_default_route(&table))
+}
+
+/// Splits `servers` into IPv4 and IPv6 addresses, keeping their order.
+/// Anything that is not an IPv6 address is treated as IPv4.
+pub(crate) fn split_by_family(servers: &[String]) -> (Vec<&str>, Vec<&str>) {
+    servers
+        .iter()
+        .map(|server| server.trim())
+        .filter(|server| !server.is_empty())
+        .partition(|server| !matches!(server.parse::<IpAddr>(), Ok(IpAddr::V6(_))))
+}
+
+/// Linux interface names used for loopback, containers, bridges and VPNs.
+pub(crate) fn is_virtual_interface(name: &str) -> bool {
+    const PREFIXES: [&str; 11] = [
+        "docker",
+        "veth",
+        "br-",
+        "virbr",
+        "vnet",
+        "tun",
+        "tap",
+        "wg",
+        "tailscale",
+        "zt",
+        "ppp",
+    ];
+    name == "lo" || PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Looks up the kernel index of a Linux network interface.
+pub(crate) fn interface_index(name: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", name))
+        .ok()
+        .and_then(|index| index.trim().parse().ok())
+}
+
+fn parse_default_route(table: &str) -> Option<String> {
+    table.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let interface = fields.next()?;
+        let destination = fields.next()?;
+        (destination == "00000000").then(|| interface.to_string())
+    })
+}
+
+/// systemd-resolved owns DNS when resolv.conf points at its stub listener
+/// and `resolvectl` is there to talk to it.
+fn uses_systemd_resolved() -> bool {
+    let stub_in_use = std::fs::read_to_string("/etc/resolv.conf")
+        .map(|content| content.contains(RESOLVED_STUB_ADDRESS))
+        .unwrap_or(false);
+    stub_in_use
+        && SystemCommandRunner
+            .run("resolvectl", &["--version"])
+            .is_ok_and(|output| output.success)
+}
+
+/// NetworkManager takes precedence when it is running, since it would
+/// overwrite changes made to resolv.conf or systemd-resolved directly.
+fn uses_network_manager() -> bool {
+    SystemCommandRunner
+        .run("nmcli", &["-t", "-f", "RUNNING", "general"])
+        .is_ok_and(|output| output.success && output.stdout.trim() == "running")
+}
@@ -0,0 +1,199 @@
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Captured result of running an external program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs external programs on behalf of a backend. Swapping the runner lets
+/// the parsing code be exercised against recorded output.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String>;
+
+    /// Runs a command that changes the system configuration. Backends use
+    /// this instead of `run` for anything that is not a read, so a dry run
+    /// can record it instead.
+    fn run_change(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        self.run(program, args)
+    }
+}
+
+impl<R: CommandRunner + ?Sized> CommandRunner for Arc<R> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        (**self).run(program, args)
+    }
+
+    fn run_change(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        (**self).run_change(program, args)
+    }
+}
+
+/// The steps a dry run would have taken, in order. Clones share the list.
+#[derive(Debug, Clone, Default)]
+pub struct Plan(Arc<Mutex<Vec<String>>>);
+
+impl Plan {
+    pub fn record(&self, step: String) {
+        log::info!("[dry run] {}", step);
+        self.0.lock().unwrap().push(step);
+    }
+
+    /// Returns the steps recorded so far and starts a new plan.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Runner that performs reads for real but only records changes in a `Plan`,
+/// reporting them as successful.
+pub struct DryRunRunner {
+    inner: Arc<dyn CommandRunner>,
+    plan: Plan,
+}
+
+impl DryRunRunner {
+    pub fn new(inner: impl CommandRunner + 'static, plan: Plan) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            plan,
+        }
+    }
+}
+
+impl CommandRunner for DryRunRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        self.inner.run(program, args)
+    }
+
+    fn run_change(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        self.plan.record(command_line(program, args));
+        Ok(CommandOutput {
+            success: true,
+            ..Default::default()
+        })
+    }
+}
+
+/// Joins a command for display, quoting arguments that contain spaces.
+fn command_line(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(|arg| {
+            if arg.contains(' ') {
+                format!("\"{}\"", arg)
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runner that spawns the real program without flashing a console window.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemCommandRunner;
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        let mut command = Command::new(program);
+        command.args(args);
+        #[cfg(windows)]
+        command.creation_flags(CREATE_NO_WINDOW);
+
+        let output = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// Test runner that answers from canned output and records every call as a
+/// single space-joined command line.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct FakeRunner {
+    responses: Arc<Mutex<Vec<(String, CommandOutput)>>>,
+    calls: Arc<Mutex<Vec<String>>>,
+    unavailable: bool,
+}
+
+#[cfg(test)]
+impl FakeRunner {
+    /// A runner for which every program is missing.
+    pub fn unavailable() -> Self {
+        Self {
+            unavailable: true,
+            ..Default::default()
+        }
+    }
+
+    /// Answers commands starting with `prefix` with a successful `stdout`.
+    pub fn respond(self, prefix: &str, stdout: &str) -> Self {
+        self.responses.lock().unwrap().push((
+            prefix.to_string(),
+            CommandOutput {
+                success: true,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            },
+        ));
+        self
+    }
+
+    /// Makes commands starting with `prefix` exit unsuccessfully with `stderr`.
+    pub fn fail(self, prefix: &str, stderr: &str) -> Self {
+        self.responses.lock().unwrap().push((
+            prefix.to_string(),
+            CommandOutput {
+                success: false,
+                stdout: String::new(),
+                stderr: stderr.to_string(),
+            },
+        ));
+        self
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for FakeRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        let line = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.calls.lock().unwrap().push(line.clone());
+
+        if self.unavailable {
+            return Err(format!("Failed to run {}: not found", program));
+        }
+        let responses = self.responses.lock().unwrap();
+        Ok(responses
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .map(|(_, response)| response.clone())
+            .unwrap_or(CommandOutput {
+                success: true,
+                ..Default::default()
+            }))
+    }
+}
@@ -0,0 +1,964 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::domain::{AdapterInfo, DnsSnapshot, DnsState, OperationResult};
+use crate::system::command::{CommandOutput, CommandRunner, SystemCommandRunner};
+use crate::system::{split_by_family, DnsBackend};
+
+const NETSH: &str = "netsh";
+const POWERSHELL: &str = "powershell";
+
+/// Lists adapters as JSON. Property names and `Status` values are not
+/// translated, unlike everything netsh prints. `@()` keeps a lone adapter
+/// inside an array, and UTF-8 output keeps non-Latin adapter names intact.
+const GET_NET_ADAPTER_SCRIPT: &str = "[Console]::OutputEncoding = [Text.Encoding]::UTF8; \
+ConvertTo-Json -Compress -InputObject @(Get-NetAdapter | \
+ForEach-Object { [pscustomobject]@{ \
+Name = $_.Name; InterfaceIndex = $_.ifIndex; InterfaceDescription = $_.InterfaceDescription; \
+Status = [string]$_.Status; MediaType = [string]$_.MediaType; Virtual = [bool]$_.Virtual; \
+DefaultRoute = [bool](Get-NetRoute -InterfaceIndex $_.ifIndex -DestinationPrefix '0.0.0.0/0' \
+-ErrorAction SilentlyContinue) } })";
+
+const DEFAULT_ROUTE_PREFIX: &str = "0.0.0.0/0";
+const LOOPBACK_PREFIX: &str = "Loopback Pseudo-Interface";
+/// Names and descriptions of tunnel, VPN and virtual switch adapters that
+/// Windows does not flag as virtual.
+const VIRTUAL_MARKERS: [&str; 7] = [
+    "vEthernet",
+    "TAP-Windows",
+    "Wintun",
+    "WireGuard",
+    "Virtual",
+    "VPN",
+    LOOPBACK_PREFIX,
+];
+
+/// One adapter as reported by `Get-NetAdapter`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetAdapter {
+    pub name: String,
+    pub interface_index: u32,
+    #[serde(default)]
+    pub interface_description: String,
+    pub status: String,
+    #[serde(default)]
+    pub media_type: String,
+    #[serde(default)]
+    pub r#virtual: bool,
+    #[serde(default)]
+    pub default_route: bool,
+}
+
+impl NetAdapter {
+    fn is_up(&self) -> bool {
+        self.status == "Up"
+    }
+
+    fn is_virtual(&self) -> bool {
+        self.r#virtual || looks_virtual(&self.name) || looks_virtual(&self.interface_description)
+    }
+}
+
+fn looks_virtual(text: &str) -> bool {
+    VIRTUAL_MARKERS.iter().any(|marker| text.contains(marker))
+}
+
+/// Windows backend that drives `netsh interface` commands.
+#[derive(Clone)]
+pub struct NetshBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl NetshBackend {
+    pub fn new() -> Self {
+        Self::with_runner(SystemCommandRunner)
+    }
+
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: Arc::new(runner),
+        }
+    }
+
+    fn netsh(&self, args: &[&str]) -> Result<CommandOutput, String> {
+        self.runner.run(NETSH, args)
+    }
+
+    fn netsh_change(&self, args: &[&str]) -> Result<CommandOutput, String> {
+        self.runner.run_change(NETSH, args)
+    }
+
+    fn net_adapters(&self) -> Result<Vec<NetAdapter>, String> {
+        let output = self.runner.run(
+            POWERSHELL,
+            &[
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                GET_NET_ADAPTER_SCRIPT,
+            ],
+        )?;
+        if !output.success {
+            return Err(failure_message(&output));
+        }
+        parse_net_adapters(&output.stdout)
+    }
+
+    /// Fallback for systems without the NetAdapter PowerShell module.
+    ///
+    /// The state column is translated, so an interface counts as connected
+    /// when its state reads the same as that of the default-route interface.
+    fn netsh_adapters(&self) -> Result<Vec<AdapterInfo>, String> {
+        let routes = self.netsh(&["interface", "ipv4", "show", "route"])?;
+        let default_index = parse_default_route_index(&routes.stdout);
+        let interfaces = self.netsh(&["interface", "ipv4", "show", "interfaces"])?;
+        let interfaces = parse_interfaces(&interfaces.stdout);
+
+        let connected_state = interfaces
+            .iter()
+            .find(|interface| Some(interface.index) == default_index)
+            .map(|interface| interface.state.clone());
+
+        let mut adapters: Vec<AdapterInfo> = interfaces
+            .into_iter()
+            .map(|interface| AdapterInfo {
+                index: interface.index,
+                connected: Some(&interface.state) == connected_state.as_ref(),
+                r#virtual: looks_virtual(&interface.name),
+                status: interface.state,
+                name: interface.name,
+                ..Default::default()
+            })
+            .collect();
+        // Keep the default-route interface first so it wins as the active one.
+        adapters.sort_by_key(|adapter| Some(adapter.index) != default_index);
+        Ok(adapters)
+    }
+
+    /// Points one protocol of `interface` at `servers`: `set` replaces the
+    /// list with the first one and `add index=N` appends the rest. An empty
+    /// list leaves that protocol without servers instead of falling back to
+    /// whatever the router hands out.
+    fn apply_family(&self, family: &str, interface: &str, servers: &[&str]) -> Result<(), String> {
+        let name = format!("name={}", interface);
+        if servers.is_empty() {
+            let output =
+                self.netsh_change(&["interface", family, "set", "dns", &name, "static", "none"])?;
+            return if output.success {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Error clearing {} DNS: {}",
+                    family,
+                    failure_message(&output)
+                ))
+            };
+        }
+
+        for (position, server) in servers.iter().enumerate() {
+            let index = format!("index={}", position + 1);
+            let output = if position == 0 {
+                self.netsh_change(&["interface", family, "set", "dns", &name, "static", server])?
+            } else {
+                self.netsh_change(&["interface", family, "add", "dns", &name, server, &index])?
+            };
+
+            if !output.success {
+                let role = match position {
+                    0 => "primary",
+                    1 => "secondary",
+                    _ => "additional",
+                };
+                return Err(format!(
+                    "Error setting {} DNS {}: {}",
+                    role,
+                    server,
+                    failure_message(&output)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands one protocol of `interface` back to DHCP.
+    fn reset_family(&self, family: &str, interface: &str) -> Result<(), String> {
+        let output = self.netsh_change(&[
+            "interface",
+            family,
+            "set",
+            "dns",
+            &format!("name={}", interface),
+            "source=dhcp",
+        ])?;
+        if output.success {
+            Ok(())
+        } else {
+            Err(format!(
+                "Error resetting DNS for '{}': {}",
+                interface,
+                failure_message(&output)
+            ))
+        }
+    }
+
+    /// `family` is `ip` for IPv4 or `ipv6`.
+    fn show_dns(&self, family: &str, adapter: &str) -> String {
+        match self.netsh(&[
+            "interface",
+            family,
+            "show",
+            "dns",
+            &format!("name={}", adapter),
+        ]) {
+            Ok(output) => output.stdout,
+            Err(e) => {
+                log::warn!("{}", e);
+                String::new()
+            }
+        }
+    }
+}
+
+impl Default for NetshBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// netsh reports failures on stdout about as often as on stderr.
+fn failure_message(output: &CommandOutput) -> String {
+    if output.stderr.trim().is_empty() {
+        output.stdout.trim().to_string()
+    } else {
+        output.stderr.trim().to_string()
+    }
+}
+
+/// Parses the JSON printed by [`GET_NET_ADAPTER_SCRIPT`].
+pub fn parse_net_adapters(json: &str) -> Result<Vec<NetAdapter>, String> {
+    let json = json.trim();
+    if json.starts_with('{') {
+        // Older PowerShell unwraps single-element arrays despite `@()`.
+        serde_json::from_str::<NetAdapter>(json).map(|adapter| vec![adapter])
+    } else {
+        serde_json::from_str::<Vec<NetAdapter>>(json)
+    }
+    .map_err(|e| format!("Failed to parse Get-NetAdapter output: {}", e))
+}
+
+/// Prefers the adapter that holds the default route, then any physical
+/// adapter that is up.
+pub fn pick_net_adapter(adapters: &[NetAdapter]) -> Option<String> {
+    adapters
+        .iter()
+        .find(|adapter| adapter.is_up() && adapter.default_route)
+        .or_else(|| {
+            adapters
+                .iter()
+                .find(|adapter| adapter.is_up() && !adapter.is_virtual())
+        })
+        .map(|adapter| adapter.name.clone())
+}
+
+/// Splits a netsh table into rows of cells.
+///
+/// Headers and values are translated, but every table has a separator line
+/// made of dash runs, one per column, which gives the column positions. The
+/// last column runs to the end of the line, so names may contain spaces.
+pub fn parse_table(stdout: &str) -> Vec<Vec<String>> {
+    let mut lines = stdout.lines();
+    let Some(separator) = lines.find(|line| {
+        let line = line.trim();
+        !line.is_empty() && line.chars().all(|c| c == '-' || c == ' ')
+    }) else {
+        return Vec::new();
+    };
+
+    let mut starts = Vec::new();
+    let mut previous = ' ';
+    for (i, c) in separator.chars().enumerate() {
+        if c == '-' && previous == ' ' {
+            starts.push(i);
+        }
+        previous = c;
+    }
+
+    lines
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            starts
+                .iter()
+                .enumerate()
+                .map(|(column, &start)| {
+                    let end = starts.get(column + 1).copied().unwrap_or(chars.len());
+                    chars
+                        .get(start.min(chars.len())..end.min(chars.len()))
+                        .map(|cell| cell.iter().collect::<String>().trim().to_string())
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Finds the interface index of the cheapest IPv4 default route in
+/// `netsh interface ipv4 show route`. The columns around the prefix are the
+/// metric and the index in every language.
+pub fn parse_default_route_index(stdout: &str) -> Option<u32> {
+    parse_table(stdout)
+        .iter()
+        .filter_map(|row| {
+            let prefix = row.iter().position(|cell| cell == DEFAULT_ROUTE_PREFIX)?;
+            let metric = row.get(prefix.checked_sub(1)?)?.parse::<u32>().ok()?;
+            let index = row.get(prefix + 1)?.parse::<u32>().ok()?;
+            Some((metric, index))
+        })
+        .min()
+        .map(|(_, index)| index)
+}
+
+/// One row of `netsh interface ipv4 show interfaces`. `state` is in the
+/// display language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetshInterface {
+    pub index: u32,
+    pub state: String,
+    pub name: String,
+}
+
+/// Reads `netsh interface ipv4 show interfaces`, leaving out the loopback
+/// pseudo-interface.
+pub fn parse_interfaces(stdout: &str) -> Vec<NetshInterface> {
+    parse_table(stdout)
+        .into_iter()
+        .filter_map(|row| {
+            let index = row.first()?.parse::<u32>().ok()?;
+            let name = row.last()?.clone();
+            let state = row.get(row.len().checked_sub(2)?)?.clone();
+            (!name.is_empty() && !name.starts_with(LOOPBACK_PREFIX)).then_some(NetshInterface {
+                index,
+                state,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Collects every IP address in `netsh interface ip show dns` output.
+///
+/// Works on any display language since only the addresses are looked at;
+/// IPv6 zone suffixes such as `%1` are dropped.
+pub fn parse_dns_servers(stdout: &str) -> Vec<String> {
+    stdout
+        .split_whitespace()
+        .filter_map(|token| {
+            let address = token.split('%').next().unwrap_or(token);
+            address.parse::<IpAddr>().ok().map(|ip| ip.to_string())
+        })
+        .collect()
+}
+
+/// Tells DHCP-assigned servers apart from static ones. The label in front of
+/// the first address is translated, but mentions "DHCP" in every language.
+pub fn parse_dns_state(stdout: &str) -> DnsState {
+    let servers = parse_dns_servers(stdout);
+    if servers.is_empty() {
+        return DnsState::None;
+    }
+
+    let first = &servers[0];
+    let from_dhcp = stdout
+        .lines()
+        .find(|line| parse_dns_servers(line).first() == Some(first))
+        .is_some_and(|line| line.contains("DHCP"));
+
+    if from_dhcp {
+        DnsState::Dhcp
+    } else {
+        DnsState::Static(servers)
+    }
+}
+
+/// Like [`parse_dns_state`], but a protocol left to DHCP that got no servers
+/// counts as DHCP, so restoring it does not pin it to none.
+pub fn parse_family_state(stdout: &str) -> DnsState {
+    match parse_dns_state(stdout) {
+        DnsState::None if stdout.contains("DHCP") => DnsState::Dhcp,
+        state => state,
+    }
+}
+
+impl DnsBackend for NetshBackend {
+    fn name(&self) -> &'static str {
+        "netsh"
+    }
+
+    fn active_adapter(&self) -> Option<String> {
+        match self.net_adapters() {
+            Ok(adapters) => return pick_net_adapter(&adapters),
+            Err(e) => log::warn!("Get-NetAdapter unavailable, falling back to netsh: {}", e),
+        }
+        match self.netsh_adapters() {
+            Ok(adapters) => adapters
+                .into_iter()
+                .find(|adapter| adapter.connected)
+                .map(|adapter| adapter.name),
+            Err(e) => {
+                log::warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn adapters(&self) -> Vec<AdapterInfo> {
+        let adapters = match self.net_adapters() {
+            Ok(adapters) => Ok(adapters
+                .into_iter()
+                .map(|adapter| AdapterInfo {
+                    index: adapter.interface_index,
+                    connected: adapter.is_up(),
+                    r#virtual: adapter.is_virtual(),
+                    kind: if adapter.media_type.is_empty() {
+                        adapter.interface_description
+                    } else {
+                        adapter.media_type
+                    },
+                    status: adapter.status,
+                    name: adapter.name,
+                    dns: Vec::new(),
+                })
+                .collect()),
+            Err(e) => {
+                log::warn!("Get-NetAdapter unavailable, falling back to netsh: {}", e);
+                self.netsh_adapters()
+            }
+        };
+
+        let mut adapters = adapters.unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            Vec::new()
+        });
+        for adapter in &mut adapters {
+            adapter.dns = self.current_dns(&adapter.name);
+        }
+        adapters
+    }
+
+    fn current_dns(&self, adapter: &str) -> Vec<String> {
+        let mut servers = parse_dns_servers(&self.show_dns("ip", adapter));
+        servers.extend(parse_dns_servers(&self.show_dns("ipv6", adapter)));
+        servers
+    }
+
+    fn dns_state(&self, adapter: &str) -> DnsState {
+        let ipv4 = parse_dns_state(&self.show_dns("ip", adapter));
+        let ipv6 = parse_dns_state(&self.show_dns("ipv6", adapter));
+        match (ipv4, ipv6) {
+            (DnsState::Static(mut ipv4), DnsState::Static(ipv6)) => {
+                ipv4.extend(ipv6);
+                DnsState::Static(ipv4)
+            }
+            (DnsState::Static(servers), _) | (_, DnsState::Static(servers)) => {
+                DnsState::Static(servers)
+            }
+            (DnsState::Dhcp, _) | (_, DnsState::Dhcp) => DnsState::Dhcp,
+            _ => DnsState::None,
+        }
+    }
+
+    fn set_dns(&self, interface: &str, servers: &[String]) -> OperationResult {
+        let (ipv4, ipv6) = split_by_family(servers);
+
+        if let Err(e) = self.apply_family("ipv4", interface, &ipv4) {
+            return OperationResult::Error(e);
+        }
+        if let Err(e) = self.apply_family("ipv6", interface, &ipv6) {
+            if ipv6.is_empty() {
+                // Adapters with IPv6 unbound have nothing to leak through.
+                log::warn!("Could not clear IPv6 DNS for '{}': {}", interface, e);
+            } else {
+                return OperationResult::Error(e);
+            }
+        }
+
+        OperationResult::Success(format!(
+            "DNS servers {} set successfully for '{}'",
+            servers.join(", "),
+            interface
+        ))
+    }
+
+    fn reset_dns(&self, interface: &str) -> OperationResult {
+        for family in ["ipv4", "ipv6"] {
+            if let Err(e) = self.reset_family(family, interface) {
+                return OperationResult::Error(e);
+            }
+        }
+
+        OperationResult::Success(format!(
+            "DNS reset to DHCP successfully for '{}'",
+            interface
+        ))
+    }
+
+    fn snapshot(&self, adapter: &str) -> DnsSnapshot {
+        DnsSnapshot {
+            adapter: adapter.to_string(),
+            ipv4: parse_family_state(&self.show_dns("ip", adapter)),
+            ipv6: parse_family_state(&self.show_dns("ipv6", adapter)),
+        }
+    }
+
+    fn restore(&self, snapshot: &DnsSnapshot) -> OperationResult {
+        let interface = &snapshot.adapter;
+        for (family, state) in [("ipv4", &snapshot.ipv4), ("ipv6", &snapshot.ipv6)] {
+            let result = match state {
+                DnsState::Static(servers) => {
+                    let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+                    self.apply_family(family, interface, &servers)
+                }
+                DnsState::Dhcp => self.reset_family(family, interface),
+                DnsState::None => self.apply_family(family, interface, &[]),
+            };
+            match result {
+                Ok(()) => {}
+                // Adapters with IPv6 unbound had nothing to put back.
+                Err(e) if family == "ipv6" && !matches!(state, DnsState::Static(_)) => {
+                    log::warn!("Could not restore IPv6 DNS for '{}': {}", interface, e);
+                }
+                Err(e) => return OperationResult::Error(e),
+            }
+        }
+
+        OperationResult::Success(format!(
+            "Previous DNS settings restored for '{}'",
+            interface
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::command::{DryRunRunner, FakeRunner, Plan};
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/netsh/",
+                $name
+            ))
+        };
+    }
+
+    #[test]
+    fn net_adapter_prefers_default_route() {
+        let adapters = parse_net_adapters(fixture!("get_net_adapter_multi.json")).unwrap();
+        assert_eq!(adapters.len(), 4);
+        assert_eq!(pick_net_adapter(&adapters), Some("Wi-Fi 2".to_string()));
+    }
+
+    #[test]
+    fn net_adapter_without_default_route_skips_virtual() {
+        let mut adapters = parse_net_adapters(fixture!("get_net_adapter_multi.json")).unwrap();
+        adapters.iter_mut().for_each(|a| a.default_route = false);
+        adapters.retain(|a| a.name != "Wi-Fi 2");
+        adapters[0].status = "Up".to_string();
+        assert_eq!(pick_net_adapter(&adapters), Some("Ethernet 3".to_string()));
+    }
+
+    #[test]
+    fn net_adapter_single_object() {
+        let adapters = parse_net_adapters(fixture!("get_net_adapter_single.json")).unwrap();
+        assert_eq!(pick_net_adapter(&adapters), Some("اترنت".to_string()));
+    }
+
+    #[test]
+    fn table_keeps_names_with_spaces() {
+        let names: Vec<(u32, String)> = parse_interfaces(fixture!("ipv4_show_interfaces_en.txt"))
+            .into_iter()
+            .map(|interface| (interface.index, interface.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (12, "Wi-Fi 2".to_string()),
+                (15, "Ethernet 3".to_string()),
+                (27, "vEthernet (Default Switch)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_route_english() {
+        assert_eq!(
+            parse_default_route_index(fixture!("ipv4_show_route_en.txt")),
+            Some(12)
+        );
+    }
+
+    #[test]
+    fn default_route_german_picks_lowest_metric() {
+        assert_eq!(
+            parse_default_route_index(fixture!("ipv4_show_route_de.txt")),
+            Some(9)
+        );
+        assert!(parse_interfaces(fixture!("ipv4_show_interfaces_de.txt"))
+            .iter()
+            .any(|interface| interface.index == 9 && interface.name == "Ethernet 2"));
+    }
+
+    #[test]
+    fn netsh_fallback_persian() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .fail("powershell", "The term 'Get-NetAdapter' is not recognized")
+                .respond(
+                    "netsh interface ipv4 show route",
+                    fixture!("ipv4_show_route_fa.txt"),
+                )
+                .respond(
+                    "netsh interface ipv4 show interfaces",
+                    fixture!("ipv4_show_interfaces_fa.txt"),
+                ),
+        );
+
+        assert_eq!(backend.active_adapter(), Some("اترنت".to_string()));
+    }
+
+    #[test]
+    fn dns_from_dhcp() {
+        let stdout = fixture!("show_dns_dhcp_en.txt");
+        assert_eq!(parse_dns_servers(stdout), ["192.168.1.1", "192.168.1.2"]);
+        assert_eq!(parse_dns_state(stdout), DnsState::Dhcp);
+    }
+
+    #[test]
+    fn dns_static() {
+        let stdout = fixture!("show_dns_static_en.txt");
+        assert_eq!(
+            parse_dns_state(stdout),
+            DnsState::Static(vec![
+                "78.157.42.100".to_string(),
+                "78.157.42.101".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn dns_none() {
+        let stdout = fixture!("show_dns_none_en.txt");
+        assert!(parse_dns_servers(stdout).is_empty());
+        assert_eq!(parse_dns_state(stdout), DnsState::None);
+        assert_eq!(parse_family_state(stdout), DnsState::Dhcp);
+        assert_eq!(
+            parse_family_state(fixture!("show_dns_static_none_en.txt")),
+            DnsState::None
+        );
+    }
+
+    #[test]
+    fn dns_localized() {
+        assert_eq!(
+            parse_dns_state(fixture!("show_dns_dhcp_de.txt")),
+            DnsState::Dhcp
+        );
+        assert_eq!(
+            parse_dns_state(fixture!("show_dns_static_de.txt")),
+            DnsState::Static(vec!["9.9.9.9".to_string(), "149.112.112.112".to_string()])
+        );
+    }
+
+    #[test]
+    fn dns_ipv6() {
+        let dhcp = fixture!("show_dns_ipv6_dhcp_en.txt");
+        assert_eq!(
+            parse_dns_servers(dhcp),
+            ["fec0:0:0:ffff::1", "fec0:0:0:ffff::2", "fec0:0:0:ffff::3"]
+        );
+        assert_eq!(parse_dns_state(dhcp), DnsState::Dhcp);
+        assert_eq!(
+            parse_dns_state(fixture!("show_dns_ipv6_static_en.txt")),
+            DnsState::Static(vec!["2620:fe::fe".to_string(), "2620:fe::9".to_string()])
+        );
+    }
+
+    #[test]
+    fn set_dns_runs_set_then_add() {
+        let runner = FakeRunner::default();
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let result = backend.set_dns(
+            "Wi-Fi 2",
+            &["9.9.9.9".to_string(), "149.112.112.112".to_string()],
+        );
+
+        assert!(matches!(result, OperationResult::Success(_)));
+        assert_eq!(
+            runner.calls(),
+            [
+                "netsh interface ipv4 set dns name=Wi-Fi 2 static 9.9.9.9",
+                "netsh interface ipv4 add dns name=Wi-Fi 2 149.112.112.112 index=2",
+                "netsh interface ipv6 set dns name=Wi-Fi 2 static none",
+            ]
+        );
+    }
+
+    #[test]
+    fn set_dns_appends_every_server_in_order() {
+        let runner = FakeRunner::default();
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let servers = ["1.1.1.1", "8.8.8.8", "9.9.9.9"].map(String::from);
+        backend.set_dns("Ethernet", &servers);
+
+        assert_eq!(
+            &runner.calls()[..3],
+            [
+                "netsh interface ipv4 set dns name=Ethernet static 1.1.1.1",
+                "netsh interface ipv4 add dns name=Ethernet 8.8.8.8 index=2",
+                "netsh interface ipv4 add dns name=Ethernet 9.9.9.9 index=3",
+            ]
+        );
+    }
+
+    #[test]
+    fn set_dns_applies_ipv6_separately() {
+        let runner = FakeRunner::default();
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let servers = ["9.9.9.9", "2620:fe::fe", "149.112.112.112", "2620:fe::9"].map(String::from);
+        let result = backend.set_dns("Ethernet", &servers);
+
+        assert!(matches!(result, OperationResult::Success(_)));
+        assert_eq!(
+            runner.calls(),
+            [
+                "netsh interface ipv4 set dns name=Ethernet static 9.9.9.9",
+                "netsh interface ipv4 add dns name=Ethernet 149.112.112.112 index=2",
+                "netsh interface ipv6 set dns name=Ethernet static 2620:fe::fe",
+                "netsh interface ipv6 add dns name=Ethernet 2620:fe::9 index=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn clearing_ipv6_without_ipv6_stack_is_not_fatal() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default().fail("netsh interface ipv6", "Element not found."),
+        );
+
+        assert!(matches!(
+            backend.set_dns("Ethernet", &["9.9.9.9".to_string()]),
+            OperationResult::Success(_)
+        ));
+        assert_eq!(
+            backend.set_dns("Ethernet", &["2620:fe::fe".to_string()]),
+            OperationResult::Error(
+                "Error setting primary DNS 2620:fe::fe: Element not found.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn dns_state_merges_static_ipv6() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .respond(
+                    "netsh interface ip show dns",
+                    fixture!("show_dns_static_en.txt"),
+                )
+                .respond(
+                    "netsh interface ipv6 show dns",
+                    fixture!("show_dns_ipv6_static_en.txt"),
+                ),
+        );
+
+        assert_eq!(
+            backend.dns_state("Wi-Fi"),
+            DnsState::Static(
+                [
+                    "78.157.42.100",
+                    "78.157.42.101",
+                    "2620:fe::fe",
+                    "2620:fe::9"
+                ]
+                .map(String::from)
+                .to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn dns_state_ignores_default_ipv6_dhcp_servers() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .respond(
+                    "netsh interface ip show dns",
+                    fixture!("show_dns_static_en.txt"),
+                )
+                .respond(
+                    "netsh interface ipv6 show dns",
+                    fixture!("show_dns_ipv6_dhcp_en.txt"),
+                ),
+        );
+
+        assert_eq!(
+            backend.dns_state("Wi-Fi"),
+            DnsState::Static(vec![
+                "78.157.42.100".to_string(),
+                "78.157.42.101".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn adapters_from_get_net_adapter() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .respond("powershell", fixture!("get_net_adapter_multi.json"))
+                .respond(
+                    "netsh interface ip show dns name=Wi-Fi 2",
+                    fixture!("show_dns_static_en.txt"),
+                ),
+        );
+
+        let adapters = backend.adapters();
+        let summary: Vec<(u32, &str, &str, bool)> = adapters
+            .iter()
+            .map(|a| (a.index, a.name.as_str(), a.status.as_str(), a.connected))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (12, "Wi-Fi 2", "Up", true),
+                (15, "Ethernet 3", "Disconnected", false),
+                (27, "vEthernet (Default Switch)", "Up", true),
+                (31, "OpenVPN TAP-Windows6", "Disconnected", false),
+            ]
+        );
+        assert_eq!(adapters[0].kind, "Native 802.11");
+        assert_eq!(adapters[0].dns, ["78.157.42.100", "78.157.42.101"]);
+        // The Hyper-V switch is up but virtual, and the TAP adapter is both.
+        let virtual_flags: Vec<bool> = adapters.iter().map(|a| a.r#virtual).collect();
+        assert_eq!(virtual_flags, [false, false, true, true]);
+        let all: Vec<&str> = adapters
+            .iter()
+            .filter(|a| a.in_all())
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(all, ["Wi-Fi 2"]);
+    }
+
+    #[test]
+    fn netsh_fallback_adapters_use_default_route_state() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .fail("powershell", "Get-NetAdapter is not recognized")
+                .respond(
+                    "netsh interface ipv4 show route",
+                    fixture!("ipv4_show_route_de.txt"),
+                )
+                .respond(
+                    "netsh interface ipv4 show interfaces",
+                    fixture!("ipv4_show_interfaces_de.txt"),
+                ),
+        );
+
+        let adapters: Vec<(String, bool)> = backend
+            .adapters()
+            .into_iter()
+            .map(|a| (a.name, a.connected))
+            .collect();
+        assert_eq!(
+            adapters,
+            [
+                ("Ethernet 2".to_string(), true),
+                ("WLAN".to_string(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn backend_reads_through_runner() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .respond("powershell", fixture!("get_net_adapter_multi.json"))
+                .respond(
+                    "netsh interface ip show dns name=Wi-Fi 2",
+                    fixture!("show_dns_static_en.txt"),
+                ),
+        );
+
+        let adapter = backend.active_adapter().unwrap();
+        assert_eq!(adapter, "Wi-Fi 2");
+        assert_eq!(
+            backend.current_dns(&adapter),
+            ["78.157.42.100", "78.157.42.101"]
+        );
+    }
+
+    #[test]
+    fn failed_secondary_reports_netsh_message() {
+        let backend = NetshBackend::with_runner(
+            FakeRunner::default()
+                .fail("netsh interface ipv4 add dns", "The object already exists."),
+        );
+
+        assert_eq!(
+            backend.set_dns("Ethernet", &["9.9.9.9".to_string(), "9.9.9.9".to_string()]),
+            OperationResult::Error(
+                "Error setting secondary DNS 9.9.9.9: The object already exists.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn dry_run_records_changes_without_running_them() {
+        let runner = FakeRunner::default().respond(
+            "netsh interface ip show dns",
+            fixture!("show_dns_static_en.txt"),
+        );
+        let plan = Plan::default();
+        let backend = NetshBackend::with_runner(DryRunRunner::new(runner.clone(), plan.clone()));
+
+        assert!(matches!(
+            backend.set_dns("Wi-Fi 2", &["9.9.9.9".to_string()]),
+            OperationResult::Success(_)
+        ));
+        assert!(runner.calls().is_empty());
+        assert_eq!(
+            plan.take(),
+            [
+                "netsh interface ipv4 set dns \"name=Wi-Fi 2\" static 9.9.9.9",
+                "netsh interface ipv6 set dns \"name=Wi-Fi 2\" static none",
+            ]
+        );
+
+        assert_eq!(
+            backend.current_dns("Wi-Fi 2"),
+            ["78.157.42.100", "78.157.42.101"]
+        );
+        assert!(plan.take().is_empty());
+    }
+
+    #[test]
+    fn missing_netsh_is_an_error() {
+        let backend = NetshBackend::with_runner(FakeRunner::unavailable());
+
+        assert_eq!(backend.active_adapter(), None);
+        assert!(matches!(
+            backend.set_dns("Ethernet", &["9.9.9.9".to_string()]),
+            OperationResult::Error(_)
+        ));
+        assert!(matches!(
+            backend.reset_dns("Ethernet"),
+            OperationResult::Error(_)
+        ));
+    }
+}
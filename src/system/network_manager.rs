@@ -0,0 +1,567 @@
+use std::sync::Arc;
+
+use crate::domain::{AdapterInfo, DnsSnapshot, DnsState, OperationResult};
+use crate::system::command::{CommandOutput, CommandRunner, SystemCommandRunner};
+use crate::system::{
+    default_route_interface, interface_index, is_virtual_interface, split_by_family, DnsBackend,
+};
+
+const NMCLI: &str = "nmcli";
+
+/// Connection types that never carry the user's internet traffic.
+const IGNORED_TYPES: [&str; 3] = ["loopback", "bridge", "tun"];
+
+/// Linux backend for desktops running NetworkManager. DNS is stored on the
+/// active connection profile, so it survives reconnects and reboots.
+#[derive(Clone)]
+pub struct NetworkManagerBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+/// One row of `nmcli connection show --active`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActiveConnection {
+    pub name: String,
+    pub uuid: String,
+    pub kind: String,
+    pub device: String,
+}
+
+impl NetworkManagerBackend {
+    pub fn new() -> Self {
+        Self::with_runner(SystemCommandRunner)
+    }
+
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: Arc::new(runner),
+        }
+    }
+
+    fn nmcli(&self, args: &[&str]) -> Result<String, String> {
+        Self::stdout(self.runner.run(NMCLI, args)?)
+    }
+
+    fn nmcli_change(&self, args: &[&str]) -> Result<String, String> {
+        Self::stdout(self.runner.run_change(NMCLI, args)?)
+    }
+
+    fn stdout(output: CommandOutput) -> Result<String, String> {
+        if output.success {
+            Ok(output.stdout)
+        } else {
+            Err(output.stderr.trim().to_string())
+        }
+    }
+
+    fn active_connections(&self) -> Vec<ActiveConnection> {
+        match self.nmcli(&[
+            "-t",
+            "-f",
+            "NAME,UUID,TYPE,DEVICE",
+            "connection",
+            "show",
+            "--active",
+        ]) {
+            Ok(stdout) => parse_active_connections(&stdout),
+            Err(e) => {
+                log::warn!("nmcli connection show failed: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn connection(&self, name: &str) -> Option<ActiveConnection> {
+        self.active_connections()
+            .into_iter()
+            .find(|connection| connection.name == name)
+    }
+
+    fn runtime_dns(&self, device: &str) -> Vec<String> {
+        self.nmcli(&["-t", "-f", "IP4.DNS,IP6.DNS", "device", "show", device])
+            .map(|stdout| {
+                parse_fields(&stdout)
+                    .into_iter()
+                    .filter(|(key, _)| key.starts_with("IP4.DNS") || key.starts_with("IP6.DNS"))
+                    .map(|(_, value)| value)
+                    .filter(|value| !value.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn ignores_auto_dns(&self, connection: &ActiveConnection) -> bool {
+        self.nmcli(&[
+            "-t",
+            "-f",
+            "ipv4.ignore-auto-dns",
+            "connection",
+            "show",
+            "uuid",
+            &connection.uuid,
+        ])
+        .map(|stdout| {
+            parse_fields(&stdout)
+                .iter()
+                .any(|(key, value)| key == "ipv4.ignore-auto-dns" && value == "yes")
+        })
+        .unwrap_or(false)
+    }
+
+    /// The DNS properties of both protocols on the `connection` profile.
+    fn profile_dns(&self, connection: &ActiveConnection) -> String {
+        self.nmcli(&[
+            "-t",
+            "-f",
+            "ipv4.dns,ipv4.ignore-auto-dns,ipv6.dns,ipv6.ignore-auto-dns",
+            "connection",
+            "show",
+            "uuid",
+            &connection.uuid,
+        ])
+        .unwrap_or_else(|e| {
+            log::warn!("nmcli connection show failed: {}", e);
+            String::new()
+        })
+    }
+
+    /// Writes the DNS properties of `connection` and reapplies it to its device.
+    fn apply(&self, connection: &ActiveConnection, properties: &[&str]) -> Result<(), String> {
+        let mut args = vec!["connection", "modify", "uuid", connection.uuid.as_str()];
+        args.extend_from_slice(properties);
+        self.nmcli_change(&args)?;
+
+        // `reapply` keeps the link up; older NetworkManager versions need a full `up`.
+        self.nmcli_change(&["device", "reapply", &connection.device])
+            .or_else(|_| self.nmcli_change(&["connection", "up", "uuid", &connection.uuid]))
+            .map(|_| ())
+    }
+}
+
+impl Default for NetworkManagerBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits one line of `nmcli -t` output, where `:` separates fields and
+/// literal colons and backslashes are escaped with a backslash.
+pub fn split_terse(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            ':' => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Parses `nmcli -t -f NAME,UUID,TYPE,DEVICE connection show --active`.
+pub fn parse_active_connections(stdout: &str) -> Vec<ActiveConnection> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = split_terse(line).into_iter();
+            Some(ActiveConnection {
+                name: fields.next()?,
+                uuid: fields.next()?,
+                kind: fields.next()?,
+                device: fields.next()?,
+            })
+        })
+        .filter(|connection| !connection.device.is_empty())
+        .collect()
+}
+
+/// Parses `nmcli -t -f ...` property listings into `(key, value)` pairs.
+fn parse_fields(stdout: &str) -> Vec<(String, String)> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let value = split_terse(value).join(":");
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Reads what one protocol (`ipv4` or `ipv6`) of a profile asks for: its own
+/// servers when automatic ones are ignored, otherwise whatever DHCP hands
+/// out.
+pub fn parse_profile_state(stdout: &str, family: &str) -> DnsState {
+    let fields = parse_fields(stdout);
+    let value = |property: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| *key == format!("{}.{}", family, property))
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    };
+    if value("ignore-auto-dns") != "yes" {
+        return DnsState::Dhcp;
+    }
+    let servers: Vec<String> = value("dns")
+        .split(',')
+        .map(|server| server.trim().to_string())
+        .filter(|server| !server.is_empty())
+        .collect();
+    if servers.is_empty() {
+        DnsState::None
+    } else {
+        DnsState::Static(servers)
+    }
+}
+
+/// Picks the connection that carries the default route, or else the first
+/// active connection that is not a loopback, bridge or tunnel.
+pub fn pick_active_connection(
+    connections: &[ActiveConnection],
+    default_route_device: Option<&str>,
+) -> Option<ActiveConnection> {
+    default_route_device
+        .and_then(|device| connections.iter().find(|c| c.device == device))
+        .or_else(|| {
+            connections
+                .iter()
+                .find(|c| !IGNORED_TYPES.contains(&c.kind.as_str()))
+        })
+        .cloned()
+}
+
+impl DnsBackend for NetworkManagerBackend {
+    fn name(&self) -> &'static str {
+        "NetworkManager"
+    }
+
+    fn active_adapter(&self) -> Option<String> {
+        let default_device = default_route_interface();
+        pick_active_connection(&self.active_connections(), default_device.as_deref())
+            .map(|connection| connection.name)
+    }
+
+    fn adapters(&self) -> Vec<AdapterInfo> {
+        self.active_connections()
+            .into_iter()
+            .filter(|c| !IGNORED_TYPES.contains(&c.kind.as_str()))
+            .map(|connection| AdapterInfo {
+                index: interface_index(&connection.device).unwrap_or_default(),
+                status: format!("active on {}", connection.device),
+                connected: true,
+                r#virtual: matches!(connection.kind.as_str(), "vpn" | "wireguard")
+                    || is_virtual_interface(&connection.device),
+                dns: self.runtime_dns(&connection.device),
+                kind: connection.kind,
+                name: connection.name,
+            })
+            .collect()
+    }
+
+    fn current_dns(&self, adapter: &str) -> Vec<String> {
+        self.connection(adapter)
+            .map(|connection| self.runtime_dns(&connection.device))
+            .unwrap_or_default()
+    }
+
+    fn dns_state(&self, adapter: &str) -> DnsState {
+        let Some(connection) = self.connection(adapter) else {
+            return DnsState::None;
+        };
+        let servers = self.runtime_dns(&connection.device);
+        if servers.is_empty() {
+            DnsState::None
+        } else if self.ignores_auto_dns(&connection) {
+            DnsState::Static(servers)
+        } else {
+            DnsState::Dhcp
+        }
+    }
+
+    fn set_dns(&self, adapter: &str, servers: &[String]) -> OperationResult {
+        let Some(connection) = self.connection(adapter) else {
+            return OperationResult::Error(format!("Connection '{}' is not active", adapter));
+        };
+
+        let (ipv4, ipv6) = split_by_family(servers);
+        let ipv4_dns = ipv4.join(",");
+        let ipv6_dns = ipv6.join(",");
+        let properties = [
+            "ipv4.dns",
+            ipv4_dns.as_str(),
+            "ipv4.ignore-auto-dns",
+            "yes",
+            "ipv6.dns",
+            ipv6_dns.as_str(),
+            "ipv6.ignore-auto-dns",
+            "yes",
+        ];
+
+        match self.apply(&connection, &properties) {
+            Ok(()) => OperationResult::Success(format!(
+                "DNS servers {} set successfully for '{}'",
+                servers.join(", "),
+                adapter
+            )),
+            Err(e) => OperationResult::Error(format!("Error setting DNS for '{}': {}", adapter, e)),
+        }
+    }
+
+    fn reset_dns(&self, adapter: &str) -> OperationResult {
+        let Some(connection) = self.connection(adapter) else {
+            return OperationResult::Error(format!("Connection '{}' is not active", adapter));
+        };
+
+        let properties = [
+            "ipv4.dns",
+            "",
+            "ipv4.ignore-auto-dns",
+            "no",
+            "ipv6.dns",
+            "",
+            "ipv6.ignore-auto-dns",
+            "no",
+        ];
+
+        match self.apply(&connection, &properties) {
+            Ok(()) => OperationResult::Success(format!(
+                "DNS reset to automatic successfully for '{}'",
+                adapter
+            )),
+            Err(e) => {
+                OperationResult::Error(format!("Error resetting DNS for '{}': {}", adapter, e))
+            }
+        }
+    }
+
+    fn snapshot(&self, adapter: &str) -> DnsSnapshot {
+        let Some(connection) = self.connection(adapter) else {
+            return DnsSnapshot::new(adapter, DnsState::None);
+        };
+        let profile = self.profile_dns(&connection);
+        DnsSnapshot {
+            adapter: adapter.to_string(),
+            ipv4: parse_profile_state(&profile, "ipv4"),
+            ipv6: parse_profile_state(&profile, "ipv6"),
+        }
+    }
+
+    fn restore(&self, snapshot: &DnsSnapshot) -> OperationResult {
+        let Some(connection) = self.connection(&snapshot.adapter) else {
+            return OperationResult::Error(format!(
+                "Connection '{}' is not active",
+                snapshot.adapter
+            ));
+        };
+
+        let mut properties = Vec::new();
+        for (family, state) in [("ipv4", &snapshot.ipv4), ("ipv6", &snapshot.ipv6)] {
+            let (servers, ignore_auto_dns) = match state {
+                DnsState::Static(servers) => (servers.join(","), "yes"),
+                DnsState::None => (String::new(), "yes"),
+                DnsState::Dhcp => (String::new(), "no"),
+            };
+            properties.push((format!("{}.dns", family), servers));
+            properties.push((
+                format!("{}.ignore-auto-dns", family),
+                ignore_auto_dns.to_string(),
+            ));
+        }
+        let properties: Vec<&str> = properties
+            .iter()
+            .flat_map(|(key, value)| [key.as_str(), value.as_str()])
+            .collect();
+
+        match self.apply(&connection, &properties) {
+            Ok(()) => OperationResult::Success(format!(
+                "Previous DNS settings restored for '{}'",
+                snapshot.adapter
+            )),
+            Err(e) => OperationResult::Error(format!(
+                "Error restoring DNS for '{}': {}",
+                snapshot.adapter, e
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::command::FakeRunner;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/nmcli/",
+                $name
+            ))
+        };
+    }
+
+    const ACTIVE: &str = "nmcli -t -f NAME,UUID,TYPE,DEVICE connection show --active";
+    const WIRED_UUID: &str = "8a7f5b3c-1d2e-4f60-9a8b-7c6d5e4f3a21";
+
+    fn backend(runner: FakeRunner) -> NetworkManagerBackend {
+        NetworkManagerBackend::with_runner(
+            runner
+                .respond(ACTIVE, fixture!("connection_show_active.txt"))
+                .respond("nmcli -t -f IP4.DNS", fixture!("device_show_dns.txt")),
+        )
+    }
+
+    #[test]
+    fn terse_fields_unescape_colons_and_backslashes() {
+        assert_eq!(
+            split_terse(r"Cafe\: Guest:uuid:a\\b:"),
+            ["Cafe: Guest", "uuid", r"a\b", ""]
+        );
+    }
+
+    #[test]
+    fn active_connections_skip_rows_without_a_device() {
+        let connections = parse_active_connections(fixture!("connection_show_active.txt"));
+
+        let names: Vec<&str> = connections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["Cafe: Guest", "Wired connection 1", "lo", "docker0", "tun0"]
+        );
+        assert_eq!(
+            connections[1],
+            ActiveConnection {
+                name: "Wired connection 1".to_string(),
+                uuid: WIRED_UUID.to_string(),
+                kind: "802-3-ethernet".to_string(),
+                device: "enp0s31f6".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn active_connection_follows_the_default_route() {
+        let connections = parse_active_connections(fixture!("connection_show_active.txt"));
+
+        let picked = |device| pick_active_connection(&connections, device).map(|c| c.name);
+        assert_eq!(picked(Some("enp0s31f6")), Some("Wired connection 1".into()));
+        // Without a usable route, the first real connection wins.
+        assert_eq!(picked(Some("eth9")), Some("Cafe: Guest".into()));
+        assert_eq!(picked(None), Some("Cafe: Guest".into()));
+
+        let virtual_only: Vec<ActiveConnection> = connections
+            .into_iter()
+            .filter(|c| IGNORED_TYPES.contains(&c.kind.as_str()))
+            .collect();
+        assert_eq!(pick_active_connection(&virtual_only, None), None);
+    }
+
+    #[test]
+    fn property_values_keep_their_colons() {
+        assert_eq!(
+            parse_fields("IP6.DNS[1]:fd00\\:\\:1\nIP6.DNS[2]:2001:db8::53\nipv4.dns:\n"),
+            [
+                ("IP6.DNS[1]".to_string(), "fd00::1".to_string()),
+                ("IP6.DNS[2]".to_string(), "2001:db8::53".to_string()),
+                ("ipv4.dns".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn dns_state_depends_on_ignore_auto_dns() {
+        let servers = vec![
+            "192.168.1.1".to_string(),
+            "192.168.1.2".to_string(),
+            "fd00::1".to_string(),
+        ];
+
+        let automatic = backend(FakeRunner::default().respond(
+            "nmcli -t -f ipv4.ignore-auto-dns",
+            "ipv4.ignore-auto-dns:no\n",
+        ));
+        assert_eq!(automatic.current_dns("Wired connection 1"), servers);
+        assert_eq!(automatic.dns_state("Wired connection 1"), DnsState::Dhcp);
+
+        let manual = backend(FakeRunner::default().respond(
+            "nmcli -t -f ipv4.ignore-auto-dns",
+            "ipv4.ignore-auto-dns:yes\n",
+        ));
+        assert_eq!(
+            manual.dns_state("Wired connection 1"),
+            DnsState::Static(servers)
+        );
+        assert_eq!(manual.dns_state("Office VPN"), DnsState::None);
+    }
+
+    #[test]
+    fn snapshots_restore_each_protocol_as_it_was() {
+        let runner = FakeRunner::default().respond(
+            "nmcli -t -f ipv4.dns,",
+            fixture!("connection_show_profile_dns.txt"),
+        );
+        let backend = backend(runner.clone());
+
+        let snapshot = backend.snapshot("Wired connection 1");
+        assert_eq!(
+            snapshot,
+            DnsSnapshot {
+                adapter: "Wired connection 1".to_string(),
+                ipv4: DnsState::Static(vec!["9.9.9.9".to_string(), "149.112.112.112".to_string()]),
+                ipv6: DnsState::Dhcp,
+            }
+        );
+        assert_eq!(
+            parse_profile_state("ipv6.ignore-auto-dns:yes\nipv6.dns:\n", "ipv6"),
+            DnsState::None
+        );
+
+        let result = backend.restore(&snapshot);
+
+        assert!(matches!(result, OperationResult::Success(_)));
+        assert!(runner.calls().contains(&format!(
+            "nmcli connection modify uuid {} ipv4.dns 9.9.9.9,149.112.112.112 \
+             ipv4.ignore-auto-dns yes ipv6.dns  ipv6.ignore-auto-dns no",
+            WIRED_UUID
+        )));
+    }
+
+    #[test]
+    fn setting_dns_modifies_the_profile_and_falls_back_to_up() {
+        let runner = FakeRunner::default().fail("nmcli device reapply", "Error: not supported");
+        let backend = backend(runner.clone());
+
+        let result = backend.set_dns(
+            "Wired connection 1",
+            &[
+                "9.9.9.9".to_string(),
+                "2620:fe::fe".to_string(),
+                "149.112.112.112".to_string(),
+            ],
+        );
+
+        assert!(matches!(result, OperationResult::Success(_)));
+        let changes: Vec<String> = runner
+            .calls()
+            .into_iter()
+            .filter(|call| !call.starts_with(ACTIVE))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                format!(
+                    "nmcli connection modify uuid {} ipv4.dns 9.9.9.9,149.112.112.112 \
+                     ipv4.ignore-auto-dns yes ipv6.dns 2620:fe::fe ipv6.ignore-auto-dns yes",
+                    WIRED_UUID
+                ),
+                "nmcli device reapply enp0s31f6".to_string(),
+                format!("nmcli connection up uuid {}", WIRED_UUID),
+            ]
+        );
+    }
+}
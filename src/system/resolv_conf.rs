@@ -0,0 +1,469 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::domain::{DnsSnapshot, OperationResult};
+use crate::system::{default_route_interface, DnsBackend, Plan};
+
+const DEFAULT_PATH: &str = "/etc/resolv.conf";
+const MANAGED_HEADER: &str = "# Generated by DNSIGHT";
+const BACKUP_MARKER: &str = ".dnsight-";
+const BACKUP_EXTENSION: &str = ".bak";
+
+/// Keywords whose lines are carried over when the nameservers are rewritten.
+const PRESERVED_KEYWORDS: [&str; 4] = ["search", "domain", "options", "sortlist"];
+
+/// Linux backend that rewrites `resolv.conf` directly.
+///
+/// The first change takes a timestamped copy of the original file next to it,
+/// and resetting restores that copy instead of guessing what DHCP would hand out.
+#[derive(Debug, Clone)]
+pub struct ResolvConfBackend {
+    path: PathBuf,
+    plan: Option<Plan>,
+}
+
+impl ResolvConfBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            plan: None,
+        }
+    }
+
+    /// Records file changes in `plan` instead of making them.
+    pub fn dry_run(mut self, plan: Plan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    fn read(&self) -> Result<String, String> {
+        fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))
+    }
+
+    /// Backups are named `<file>.dnsight-<unix seconds>.bak`, with `-<n>`
+    /// after the seconds when several are taken in the same second. Oldest
+    /// first.
+    fn backups(&self) -> Vec<PathBuf> {
+        let (Some(dir), Some(file_name)) = (self.dir(), self.path.file_name()) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}{}", file_name.to_string_lossy(), BACKUP_MARKER);
+
+        let mut backups: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .map(|name| name.to_string_lossy())
+                            .is_some_and(|name| {
+                                name.starts_with(&prefix) && name.ends_with(BACKUP_EXTENSION)
+                            })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        backups.sort_by_key(|path| backup_age(path));
+        backups
+    }
+
+    fn dir(&self) -> Option<&Path> {
+        match self.path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => Some(Path::new(".")),
+            other => other,
+        }
+    }
+
+    /// A backup name not used yet, so an earlier backup is never
+    /// overwritten.
+    fn backup_path(&self) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut counter = 0;
+        loop {
+            let suffix = match counter {
+                0 => timestamp.to_string(),
+                n => format!("{}-{}", timestamp, n),
+            };
+            let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!("{}{}{}", BACKUP_MARKER, suffix, BACKUP_EXTENSION));
+            let path = self.path.with_file_name(name);
+            if !path.exists() {
+                return path;
+            }
+            counter += 1;
+        }
+    }
+
+    /// Copies the current file aside unless it is already one we generated,
+    /// in which case the newest backup still holds what was there before us.
+    fn ensure_backup(&self, current: &str) -> Result<(), String> {
+        if is_managed(current) && !self.backups().is_empty() {
+            return Ok(());
+        }
+        let backup = self.backup_path();
+        if let Some(plan) = &self.plan {
+            plan.record(format!(
+                "copy {} to {}",
+                self.path.display(),
+                backup.display()
+            ));
+            return Ok(());
+        }
+        fs::write(&backup, current)
+            .map_err(|e| format!("Failed to back up {}: {}", self.path.display(), e))
+    }
+
+    fn write(&self, content: &str) -> Result<(), String> {
+        if let Some(plan) = &self.plan {
+            plan.record(format!(
+                "write {}:\n{}",
+                self.path.display(),
+                content.trim_end()
+            ));
+            return Ok(());
+        }
+
+        // Write through a symlinked resolv.conf instead of replacing the link.
+        let target = fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        let mut tmp_name = target.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".dnsight-tmp");
+        let tmp = target.with_file_name(tmp_name);
+
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, &target))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp);
+                format!("Failed to write {}: {}", self.path.display(), e)
+            })
+    }
+}
+
+impl Default for ResolvConfBackend {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATH)
+    }
+}
+
+/// The seconds and same-second counter in a backup's name.
+fn backup_age(path: &Path) -> (u64, u64) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stamp = name
+        .rsplit_once(BACKUP_MARKER)
+        .and_then(|(_, rest)| rest.strip_suffix(BACKUP_EXTENSION))
+        .unwrap_or_default();
+    let (seconds, counter) = stamp.split_once('-').unwrap_or((stamp, "0"));
+    (
+        seconds.parse().unwrap_or_default(),
+        counter.parse().unwrap_or_default(),
+    )
+}
+
+fn is_managed(content: &str) -> bool {
+    content.lines().next() == Some(MANAGED_HEADER)
+}
+
+/// Returns the `nameserver` entries of a resolv.conf file, in order.
+fn parse_nameservers(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("nameserver"), Some(server)) => Some(server.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Builds a resolv.conf that uses `servers` and keeps the `search`, `domain`,
+/// `options` and `sortlist` lines of `existing`.
+fn render_resolv_conf(existing: &str, servers: &[String]) -> String {
+    let mut content = format!("{}\n", MANAGED_HEADER);
+    for server in servers {
+        content.push_str(&format!("nameserver {}\n", server));
+    }
+    for line in existing.lines() {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        if PRESERVED_KEYWORDS.contains(&keyword) {
+            content.push_str(line.trim_end());
+            content.push('\n');
+        }
+    }
+    content
+}
+
+impl DnsBackend for ResolvConfBackend {
+    fn name(&self) -> &'static str {
+        "resolv.conf"
+    }
+
+    fn active_adapter(&self) -> Option<String> {
+        default_route_interface()
+    }
+
+    fn current_dns(&self, _adapter: &str) -> Vec<String> {
+        self.read()
+            .map(|content| parse_nameservers(&content))
+            .unwrap_or_default()
+    }
+
+    fn set_dns(&self, _adapter: &str, servers: &[String]) -> OperationResult {
+        let current = match self.read() {
+            Ok(content) => content,
+            Err(e) => return OperationResult::Error(e),
+        };
+        if let Err(e) = self.ensure_backup(&current) {
+            return OperationResult::Error(e);
+        }
+
+        match self.write(&render_resolv_conf(&current, servers)) {
+            Ok(()) => OperationResult::Success(format!(
+                "DNS servers {} set successfully in {}",
+                servers.join(", "),
+                self.path.display()
+            )),
+            Err(e) => OperationResult::Error(e),
+        }
+    }
+
+    fn reset_dns(&self, _adapter: &str) -> OperationResult {
+        let backups = self.backups();
+        let Some(original) = backups.last() else {
+            return OperationResult::Warning(format!(
+                "No DNSIGHT backup of {} to restore",
+                self.path.display()
+            ));
+        };
+
+        let content = match fs::read_to_string(original) {
+            Ok(content) => content,
+            Err(e) => {
+                return OperationResult::Error(format!(
+                    "Failed to read backup {}: {}",
+                    original.display(),
+                    e
+                ))
+            }
+        };
+        if let Err(e) = self.write(&content) {
+            return OperationResult::Error(e);
+        }
+        for backup in &backups {
+            match &self.plan {
+                Some(plan) => plan.record(format!("remove {}", backup.display())),
+                None => {
+                    let _ = fs::remove_file(backup);
+                }
+            }
+        }
+
+        OperationResult::Success(format!(
+            "Original {} restored successfully",
+            self.path.display()
+        ))
+    }
+
+    /// A snapshot of the file from before our first change is the backup
+    /// itself, so that is put back instead of rewriting the file.
+    fn restore(&self, snapshot: &DnsSnapshot) -> OperationResult {
+        let Some(servers) = snapshot.static_servers() else {
+            return self.reset_dns(&snapshot.adapter);
+        };
+        let original = self
+            .backups()
+            .last()
+            .and_then(|backup| fs::read_to_string(backup).ok());
+        let is_original = original.is_some_and(|original| {
+            let mut original = parse_nameservers(&original);
+            let mut servers = servers.clone();
+            original.sort();
+            servers.sort();
+            original == servers
+        });
+
+        if is_original {
+            self.reset_dns(&snapshot.adapter)
+        } else {
+            self.set_dns(&snapshot.adapter, &servers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "dnsight-resolv-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const ORIGINAL: &str = "\
+# from DHCP
+nameserver 192.168.1.1
+search corp.example
+options edns0 trust-ad
+sortlist 10.0.0.0/255.0.0.0
+";
+
+    fn servers(servers: &[&str]) -> Vec<String> {
+        servers.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn setting_dns_backs_up_the_original_and_keeps_other_lines() {
+        let dir = TempDir::new();
+        let path = dir.0.join("resolv.conf");
+        fs::write(&path, ORIGINAL).unwrap();
+        let backend = ResolvConfBackend::new(&path);
+
+        let result = backend.set_dns("eth0", &servers(&["9.9.9.9", "2620:fe::fe"]));
+
+        assert!(
+            matches!(result, OperationResult::Success(_)),
+            "{:?}",
+            result
+        );
+        let backups = backend.backups();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), ORIGINAL);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "\
+# Generated by DNSIGHT
+nameserver 9.9.9.9
+nameserver 2620:fe::fe
+search corp.example
+options edns0 trust-ad
+sortlist 10.0.0.0/255.0.0.0
+"
+        );
+        assert_eq!(backend.current_dns("eth0"), ["9.9.9.9", "2620:fe::fe"]);
+
+        // A second change keeps the backup of the original.
+        backend.set_dns("eth0", &servers(&["1.1.1.1"]));
+        assert_eq!(backend.backups(), backups);
+    }
+
+    #[test]
+    fn resetting_puts_the_original_back_and_removes_the_backups() {
+        let dir = TempDir::new();
+        let path = dir.0.join("resolv.conf");
+        fs::write(&path, ORIGINAL).unwrap();
+        let backend = ResolvConfBackend::new(&path);
+        backend.set_dns("eth0", &servers(&["9.9.9.9"]));
+
+        let result = backend.reset_dns("eth0");
+
+        assert!(
+            matches!(result, OperationResult::Success(_)),
+            "{:?}",
+            result
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), ORIGINAL);
+        assert!(backend.backups().is_empty());
+        assert!(matches!(
+            backend.reset_dns("eth0"),
+            OperationResult::Warning(_)
+        ));
+    }
+
+    #[test]
+    fn undoing_the_first_change_puts_the_backup_back() {
+        let dir = TempDir::new();
+        let path = dir.0.join("resolv.conf");
+        fs::write(&path, ORIGINAL).unwrap();
+        let backend = ResolvConfBackend::new(&path);
+
+        let before = backend.snapshot("eth0");
+        backend.set_dns("eth0", &servers(&["9.9.9.9"]));
+        let between = backend.snapshot("eth0");
+        backend.set_dns("eth0", &servers(&["1.1.1.1"]));
+
+        backend.restore(&between);
+        assert!(is_managed(&fs::read_to_string(&path).unwrap()));
+        assert_eq!(backend.current_dns("eth0"), ["9.9.9.9"]);
+        assert_eq!(backend.backups().len(), 1);
+
+        let result = backend.restore(&before);
+        assert!(
+            matches!(result, OperationResult::Success(_)),
+            "{:?}",
+            result
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), ORIGINAL);
+        assert!(backend.backups().is_empty());
+    }
+
+    #[test]
+    fn backups_taken_in_the_same_second_are_all_kept() {
+        let dir = TempDir::new();
+        let path = dir.0.join("resolv.conf");
+        fs::write(&path, ORIGINAL).unwrap();
+        let backend = ResolvConfBackend::new(&path);
+
+        backend.ensure_backup(ORIGINAL).unwrap();
+        backend.ensure_backup("nameserver 10.0.0.1\n").unwrap();
+        backend.ensure_backup("nameserver 10.0.0.2\n").unwrap();
+
+        let contents: Vec<String> = backend
+            .backups()
+            .iter()
+            .map(|backup| fs::read_to_string(backup).unwrap())
+            .collect();
+        assert_eq!(
+            contents,
+            [ORIGINAL, "nameserver 10.0.0.1\n", "nameserver 10.0.0.2\n"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlinked_resolv_conf_stays_a_link() {
+        let dir = TempDir::new();
+        let target = dir.0.join("stub-resolv.conf");
+        let link = dir.0.join("resolv.conf");
+        fs::write(&target, ORIGINAL).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let backend = ResolvConfBackend::new(&link);
+
+        backend.set_dns("eth0", &servers(&["9.9.9.9"]));
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert!(fs::read_to_string(&target)
+            .unwrap()
+            .contains("nameserver 9.9.9.9"));
+
+        backend.reset_dns("eth0");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), ORIGINAL);
+    }
+}
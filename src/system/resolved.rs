@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use crate::domain::{AdapterInfo, DnsState, OperationResult};
+use crate::system::command::{CommandRunner, SystemCommandRunner};
+use crate::system::{default_route_interface, is_virtual_interface, DnsBackend};
+
+const RESOLVECTL: &str = "resolvectl";
+/// Routing domain `set_dns` adds so every name goes through the link. DHCP
+/// never hands it out, so a link that has it was configured by hand.
+const ROUTE_ALL: &str = "~.";
+
+/// Linux backend for hosts where systemd-resolved owns DNS. Servers are set
+/// per link with `resolvectl`, so other links keep their own configuration.
+#[derive(Clone)]
+pub struct ResolvedBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+/// One `Link N (name)` section of `resolvectl status`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResolvedLink {
+    pub index: u32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub default_route: bool,
+    pub current_server: Option<String>,
+    pub servers: Vec<String>,
+    pub domains: Vec<String>,
+}
+
+impl ResolvedLink {
+    fn has_dns_scope(&self) -> bool {
+        self.scopes.iter().any(|scope| scope == "DNS")
+    }
+
+    fn routes_all(&self) -> bool {
+        self.domains.iter().any(|domain| domain == ROUTE_ALL)
+    }
+}
+
+impl ResolvedBackend {
+    pub fn new() -> Self {
+        Self::with_runner(SystemCommandRunner)
+    }
+
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: Arc::new(runner),
+        }
+    }
+
+    fn links(&self) -> Vec<ResolvedLink> {
+        match self.runner.run(RESOLVECTL, &["status", "--no-pager"]) {
+            Ok(output) if output.success => parse_status(&output.stdout),
+            Ok(output) => {
+                log::warn!("resolvectl status failed: {}", output.stderr.trim());
+                Vec::new()
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn link(&self, name: &str) -> Option<ResolvedLink> {
+        self.links().into_iter().find(|link| link.name == name)
+    }
+
+    fn resolvectl(&self, args: &[&str]) -> Result<(), String> {
+        let output = self.runner.run_change(RESOLVECTL, args)?;
+        if output.success {
+            Ok(())
+        } else {
+            Err(output.stderr.trim().to_string())
+        }
+    }
+}
+
+impl Default for ResolvedBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses the human-readable output of `resolvectl status`.
+///
+/// Values that do not fit on one line continue on following lines without a
+/// `Key:` prefix, so those are appended to the key seen last.
+pub fn parse_status(output: &str) -> Vec<ResolvedLink> {
+    let mut links = Vec::new();
+    let mut current: Option<ResolvedLink> = None;
+    let mut last_key = String::new();
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("Link ") {
+            links.extend(current.take());
+            current = parse_link_header(header);
+            last_key.clear();
+            continue;
+        }
+        if line.trim() == "Global" {
+            links.extend(current.take());
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(link) = current.as_mut() else {
+            continue;
+        };
+
+        let (key, value) = match line.split_once(": ") {
+            Some((key, value)) => {
+                last_key = key.trim().to_string();
+                (last_key.as_str(), value.trim())
+            }
+            None => (last_key.as_str(), line.trim()),
+        };
+
+        match key {
+            "Current Scopes" => link.scopes = split_values(value),
+            "Protocols" => {
+                link.default_route |= value.split_whitespace().any(|p| p == "+DefaultRoute");
+            }
+            "DefaultRoute setting" => link.default_route |= value == "yes",
+            "Current DNS Server" => link.current_server = Some(value.to_string()),
+            "DNS Servers" => link.servers.extend(split_values(value)),
+            "DNS Domain" => link.domains.extend(split_values(value)),
+            _ => {}
+        }
+    }
+    links.extend(current);
+    links
+}
+
+fn parse_link_header(header: &str) -> Option<ResolvedLink> {
+    let (index, rest) = header.split_once(' ')?;
+    let name = rest.trim().strip_prefix('(')?.strip_suffix(')')?;
+    Some(ResolvedLink {
+        index: index.parse().ok()?,
+        name: name.to_string(),
+        ..Default::default()
+    })
+}
+
+fn split_values(value: &str) -> Vec<String> {
+    value
+        .split_whitespace()
+        .filter(|v| *v != "none")
+        .map(str::to_string)
+        .collect()
+}
+
+impl DnsBackend for ResolvedBackend {
+    fn name(&self) -> &'static str {
+        "systemd-resolved"
+    }
+
+    fn active_adapter(&self) -> Option<String> {
+        let links = self.links();
+        links
+            .iter()
+            .find(|link| link.default_route && link.has_dns_scope())
+            .map(|link| link.name.clone())
+            .or_else(|| {
+                let name = default_route_interface()?;
+                links.iter().any(|link| link.name == name).then_some(name)
+            })
+    }
+
+    fn adapters(&self) -> Vec<AdapterInfo> {
+        self.links()
+            .into_iter()
+            .map(|link| AdapterInfo {
+                index: link.index,
+                connected: link.has_dns_scope(),
+                status: if link.scopes.is_empty() {
+                    "no scopes".to_string()
+                } else {
+                    link.scopes.join(" ")
+                },
+                kind: "link".to_string(),
+                r#virtual: is_virtual_interface(&link.name),
+                dns: link.servers,
+                name: link.name,
+            })
+            .collect()
+    }
+
+    fn current_dns(&self, adapter: &str) -> Vec<String> {
+        self.link(adapter)
+            .map(|link| link.servers)
+            .unwrap_or_default()
+    }
+
+    /// Servers set with `set_dns` are static. Anything else came from the
+    /// network configuration, which `revert` goes back to, so it counts as
+    /// DHCP.
+    fn dns_state(&self, adapter: &str) -> DnsState {
+        match self.link(adapter) {
+            Some(link) if link.servers.is_empty() => DnsState::None,
+            Some(link) if link.routes_all() => DnsState::Static(link.servers),
+            Some(_) => DnsState::Dhcp,
+            None => DnsState::None,
+        }
+    }
+
+    fn set_dns(&self, adapter: &str, servers: &[String]) -> OperationResult {
+        let mut domains = self
+            .link(adapter)
+            .map(|link| link.domains)
+            .unwrap_or_default();
+        domains.retain(|domain| domain != ROUTE_ALL);
+        domains.push(ROUTE_ALL.to_string());
+
+        let mut args = vec!["dns", adapter];
+        args.extend(servers.iter().map(String::as_str));
+        if let Err(e) = self.resolvectl(&args) {
+            return OperationResult::Error(format!(
+                "Error setting DNS for link '{}': {}",
+                adapter, e
+            ));
+        }
+
+        // Route every domain through this link so other links' servers are
+        // not consulted, keeping the search domains the link already has.
+        let mut args = vec!["domain", adapter];
+        args.extend(domains.iter().map(String::as_str));
+        if let Err(e) = self.resolvectl(&args) {
+            let _ = self.resolvectl(&["revert", adapter]);
+            return OperationResult::Error(format!(
+                "Error setting routing domain for link '{}': {}",
+                adapter, e
+            ));
+        }
+
+        OperationResult::Success(format!(
+            "DNS servers {} set successfully for '{}'",
+            servers.join(", "),
+            adapter
+        ))
+    }
+
+    fn reset_dns(&self, adapter: &str) -> OperationResult {
+        match self.resolvectl(&["revert", adapter]) {
+            Ok(()) => OperationResult::Success(format!(
+                "DNS reverted to network defaults for '{}'",
+                adapter
+            )),
+            Err(e) => {
+                OperationResult::Error(format!("Error reverting DNS for '{}': {}", adapter, e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::command::FakeRunner;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/resolvectl/",
+                $name
+            ))
+        };
+    }
+
+    fn with_status(status: &str) -> (ResolvedBackend, FakeRunner) {
+        let runner = FakeRunner::default().respond("resolvectl status", status);
+        (ResolvedBackend::with_runner(runner.clone()), runner)
+    }
+
+    #[test]
+    fn status_lists_every_link_with_continued_values() {
+        let links = parse_status(fixture!("status_249.txt"));
+
+        let names: Vec<&str> = links.iter().map(|link| link.name.as_str()).collect();
+        assert_eq!(names, ["lo", "enp0s3", "docker0", "tun0"]);
+        assert_eq!(
+            links[1],
+            ResolvedLink {
+                index: 2,
+                name: "enp0s3".to_string(),
+                scopes: vec!["DNS".to_string()],
+                default_route: true,
+                current_server: Some("192.168.1.1".to_string()),
+                servers: vec!["192.168.1.1".to_string(), "fe80::1%enp0s3".to_string()],
+                domains: vec!["home.lan".to_string()],
+            }
+        );
+        assert!(links[0].scopes.is_empty());
+        assert!(!links[3].default_route);
+        assert_eq!(links[3].servers, ["10.8.0.1", "10.8.0.2"]);
+        assert_eq!(links[3].domains, ["corp.example", "~internal.example"]);
+    }
+
+    #[test]
+    fn older_status_format_is_read() {
+        let links = parse_status(fixture!("status_245.txt"));
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].name, "eth0");
+        assert!(links[0].default_route);
+        assert_eq!(links[0].servers, ["10.0.2.3"]);
+    }
+
+    #[test]
+    fn active_link_has_the_default_route() {
+        let (backend, _) = with_status(fixture!("status_249.txt"));
+        assert_eq!(backend.active_adapter(), Some("enp0s3".to_string()));
+
+        let all: Vec<String> = backend
+            .adapters()
+            .into_iter()
+            .filter(|adapter| adapter.in_all())
+            .map(|adapter| adapter.name)
+            .collect();
+        assert_eq!(all, ["enp0s3"]);
+    }
+
+    #[test]
+    fn only_servers_set_by_hand_are_static() {
+        let (backend, _) = with_status(fixture!("status_249.txt"));
+        assert_eq!(backend.dns_state("enp0s3"), DnsState::Dhcp);
+        assert_eq!(backend.dns_state("lo"), DnsState::None);
+
+        let (backend, _) = with_status(fixture!("status_249_overridden.txt"));
+        assert_eq!(
+            backend.dns_state("enp0s3"),
+            DnsState::Static(vec!["9.9.9.9".to_string(), "149.112.112.112".to_string()])
+        );
+    }
+
+    #[test]
+    fn setting_dns_keeps_the_search_domains() {
+        let servers = ["9.9.9.9".to_string(), "2620:fe::fe".to_string()];
+
+        let (backend, runner) = with_status(fixture!("status_249.txt"));
+        backend.set_dns("enp0s3", &servers);
+        let changes: Vec<String> = runner
+            .calls()
+            .into_iter()
+            .filter(|call| !call.starts_with("resolvectl status"))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "resolvectl dns enp0s3 9.9.9.9 2620:fe::fe",
+                "resolvectl domain enp0s3 home.lan ~.",
+            ]
+        );
+
+        // Setting again does not add a second routing domain.
+        let (backend, runner) = with_status(fixture!("status_249_overridden.txt"));
+        backend.set_dns("enp0s3", &servers);
+        assert!(runner
+            .calls()
+            .contains(&"resolvectl domain enp0s3 home.lan ~.".to_string()));
+    }
+
+    #[test]
+    fn a_failed_routing_domain_reverts_the_link() {
+        let runner = FakeRunner::default()
+            .respond("resolvectl status", fixture!("status_249.txt"))
+            .fail("resolvectl domain", "Failed to set domain configuration");
+        let backend = ResolvedBackend::with_runner(runner.clone());
+
+        let result = backend.set_dns("enp0s3", &["9.9.9.9".to_string()]);
+
+        assert!(matches!(result, OperationResult::Error(_)));
+        assert_eq!(runner.calls().last().unwrap(), "resolvectl revert enp0s3");
+    }
+}
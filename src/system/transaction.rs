@@ -0,0 +1,210 @@
+use crate::domain::{DnsSnapshot, OperationResult};
+use crate::system::DnsBackend;
+
+/// Runs `change` on every adapter in `adapters`, snapshotting each one first.
+///
+/// If any adapter fails, every adapter touched so far is put back and the
+/// error says whether that worked, so nothing is left half-configured. On
+/// success the snapshots are returned so the change can be undone later.
+pub fn apply_with_rollback(
+    backend: &dyn DnsBackend,
+    adapters: &[String],
+    mut change: impl FnMut(&str) -> OperationResult,
+) -> (OperationResult, Vec<DnsSnapshot>) {
+    if adapters.is_empty() {
+        return (
+            OperationResult::Error("No Internet Connection Found".to_string()),
+            Vec::new(),
+        );
+    }
+
+    let mut snapshots = Vec::new();
+    let mut results = Vec::new();
+    for adapter in adapters {
+        snapshots.push(backend.snapshot(adapter));
+
+        match change(adapter) {
+            OperationResult::Error(message) => {
+                let message = if adapters.len() == 1 {
+                    message
+                } else {
+                    format!("{}: {}", adapter, message)
+                };
+                let rollback = match restore_all(backend, &snapshots) {
+                    OperationResult::Error(e) => format!("Rolling back also failed: {}", e),
+                    _ => "Previous DNS settings were restored".to_string(),
+                };
+                return (
+                    OperationResult::Error(format!("{}\n{}", message, rollback)),
+                    Vec::new(),
+                );
+            }
+            result => results.push((adapter.clone(), result)),
+        }
+    }
+
+    (OperationResult::combine(results), snapshots)
+}
+
+/// Restores `snapshots` newest first and merges the outcomes.
+pub fn restore_all(backend: &dyn DnsBackend, snapshots: &[DnsSnapshot]) -> OperationResult {
+    OperationResult::combine(
+        snapshots
+            .iter()
+            .rev()
+            .map(|snapshot| (snapshot.adapter.clone(), backend.restore(snapshot)))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::DnsState;
+    use crate::system::command::FakeRunner;
+    use crate::system::NetshBackend;
+
+    const STATIC_DNS: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/netsh/show_dns_static_en.txt"
+    ));
+    const DHCP_DNS: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/netsh/show_dns_dhcp_en.txt"
+    ));
+    const DHCP_DNS_V6: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/netsh/show_dns_ipv6_dhcp_en.txt"
+    ));
+
+    fn servers(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn failed_add_restores_each_protocol_as_it_was() {
+        let runner = FakeRunner::default()
+            .respond("netsh interface ip show dns", STATIC_DNS)
+            .respond("netsh interface ipv6 show dns", DHCP_DNS_V6)
+            .fail(
+                "netsh interface ipv4 add dns name=Ethernet 149.112.112.112",
+                "The object already exists.",
+            );
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let (result, snapshots) = apply_with_rollback(&backend, &servers(&["Ethernet"]), |a| {
+            backend.set_dns(a, &servers(&["9.9.9.9", "149.112.112.112"]))
+        });
+
+        assert_eq!(
+            result,
+            OperationResult::Error(
+                "Error setting secondary DNS 149.112.112.112: The object already exists.\n\
+                 Previous DNS settings were restored"
+                    .to_string()
+            )
+        );
+        assert!(snapshots.is_empty());
+        let calls = runner.calls();
+        assert_eq!(
+            calls[calls.len() - 3..],
+            [
+                "netsh interface ipv4 set dns name=Ethernet static 78.157.42.100",
+                "netsh interface ipv4 add dns name=Ethernet 78.157.42.101 index=2",
+                "netsh interface ipv6 set dns name=Ethernet source=dhcp",
+            ]
+        );
+    }
+
+    #[test]
+    fn protocols_without_servers_are_cleared_again() {
+        let runner = FakeRunner::default()
+            .respond("netsh interface ip show dns", STATIC_DNS)
+            .respond(
+                "netsh interface ipv6 show dns",
+                include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/fixtures/netsh/show_dns_static_none_en.txt"
+                )),
+            );
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let (_, snapshots) = apply_with_rollback(&backend, &servers(&["Ethernet"]), |a| {
+            backend.set_dns(a, &servers(&["2620:fe::fe"]))
+        });
+        assert_eq!(snapshots[0].ipv6, DnsState::None);
+        restore_all(&backend, &snapshots);
+
+        let calls = runner.calls();
+        assert_eq!(
+            calls[calls.len() - 3..],
+            [
+                "netsh interface ipv4 set dns name=Ethernet static 78.157.42.100",
+                "netsh interface ipv4 add dns name=Ethernet 78.157.42.101 index=2",
+                "netsh interface ipv6 set dns name=Ethernet static none",
+            ]
+        );
+    }
+
+    #[test]
+    fn failure_on_second_adapter_rolls_back_both() {
+        let runner = FakeRunner::default()
+            .respond("netsh interface ip show dns", DHCP_DNS)
+            .respond("netsh interface ipv6 show dns", DHCP_DNS_V6)
+            .fail(
+                "netsh interface ipv4 set dns name=Wi-Fi static",
+                "The interface is disabled.",
+            );
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let (result, _) = apply_with_rollback(&backend, &servers(&["Ethernet", "Wi-Fi"]), |a| {
+            backend.set_dns(a, &servers(&["1.1.1.1"]))
+        });
+
+        assert!(matches!(result, OperationResult::Error(ref e) if e.starts_with("Wi-Fi: ")));
+        let resets: Vec<String> = runner
+            .calls()
+            .into_iter()
+            .filter(|call| call.contains("source=dhcp"))
+            .collect();
+        assert_eq!(
+            resets,
+            [
+                "netsh interface ipv4 set dns name=Wi-Fi source=dhcp",
+                "netsh interface ipv6 set dns name=Wi-Fi source=dhcp",
+                "netsh interface ipv4 set dns name=Ethernet source=dhcp",
+                "netsh interface ipv6 set dns name=Ethernet source=dhcp",
+            ]
+        );
+    }
+
+    #[test]
+    fn success_returns_snapshots_for_undo() {
+        let runner = FakeRunner::default()
+            .respond("netsh interface ip show dns", DHCP_DNS)
+            .respond("netsh interface ipv6 show dns", DHCP_DNS_V6);
+        let backend = NetshBackend::with_runner(runner.clone());
+
+        let (result, snapshots) = apply_with_rollback(&backend, &servers(&["Ethernet"]), |a| {
+            backend.set_dns(a, &servers(&["1.1.1.1"]))
+        });
+
+        assert!(matches!(result, OperationResult::Success(_)));
+        assert_eq!(
+            snapshots,
+            [DnsSnapshot {
+                adapter: "Ethernet".to_string(),
+                ipv4: DnsState::Dhcp,
+                ipv6: DnsState::Dhcp,
+            }]
+        );
+        assert!(matches!(
+            restore_all(&backend, &snapshots),
+            OperationResult::Success(_)
+        ));
+        assert_eq!(
+            runner.calls().last().unwrap(),
+            "netsh interface ipv6 set dns name=Ethernet source=dhcp"
+        );
+    }
+}
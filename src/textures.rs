@@ -1,5 +1,4 @@
 use eframe::egui::{self, ColorImage, TextureHandle};
-use image;
 
 fn get_exe_dir() -> Option<std::path::PathBuf> {
     std::env::current_exe()
@@ -1,6 +1,14 @@
 use eframe::egui::{self, TextureHandle, Vec2};
 
-use crate::domain::{AppState, DnsProvider, DnsState};
+use crate::benchmark::{ServerStats, SortColumn};
+use crate::compare::{self, Outcome, ProviderAnswer};
+use crate::domain::{
+    AdapterInfo, AdapterTarget, AppState, AutoSelectSettings, BenchmarkSettings, BlockResponse,
+    BlockingSettings, CacheMode, DnsProvider, DnsState, DohMethod, IpFamily, MatchKind,
+    ReachabilitySettings, RoutingRule, ServerList, UpstreamStrategy,
+};
+use crate::forwarder::{QueryEvent, Source};
+use crate::reachability::{Cell, Reachability};
 
 pub mod ui_constants {
     pub const SPACING_SMALL: f32 = 10.0;
@@ -13,6 +21,8 @@ pub mod ui_constants {
     pub const BUTTON_SPACING: f32 = 3.0;
 
     pub const TITLE_BAR_HEIGHT: f32 = 30.0;
+
+    pub const SERVER_LIST_HEIGHT: f32 = 75.0;
 }
 
 pub mod ui_colors {
@@ -46,47 +56,77 @@ pub fn configure_theme(ctx: &egui::Context) {
     }
 }
 
-pub fn render_ip_input(ui: &mut egui::Ui, ip: &mut String, label: &str) -> bool {
-    ui.horizontal(|ui| {
-        ui.label(egui::RichText::new(format!("{}: ", label)).color(egui::Color32::WHITE));
-
-        let field_id = egui::Id::new(label);
-        let ip_clone = ip.clone();
-        let is_valid = ip_clone.is_empty() || is_valid_ip(&ip_clone);
-
-        let mut text_edit = egui::TextEdit::singleline(ip)
-            .desired_width(200.0)
-            .id(field_id)
-            .text_color(egui::Color32::WHITE);
-
-        if !ip_clone.is_empty() && !is_valid {
-            text_edit = text_edit.text_color(egui::Color32::RED);
-        }
+/// One input row per server, each with a remove button, and an add button
+/// underneath. Always shows at least one row. Addresses of the other family
+/// are shown as invalid.
+pub fn render_server_list_input(
+    ui: &mut egui::Ui,
+    id: &str,
+    family: IpFamily,
+    servers: &mut Vec<String>,
+) {
+    if servers.is_empty() {
+        servers.push(String::new());
+    }
 
-        ui.add_sized(Vec2::new(200.0, 20.0), text_edit);
-    });
+    let mut remove = None;
+    egui::ScrollArea::vertical()
+        .id_salt(id)
+        .max_height(SERVER_LIST_HEIGHT)
+        .show(ui, |ui| {
+            for (position, server) in servers.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        egui::RichText::new(format!("{} {}: ", family.label(), position + 1))
+                            .color(egui::Color32::WHITE),
+                    );
 
-    ip.is_empty() || is_valid_ip(ip)
-}
+                    let is_valid = server.trim().is_empty() || family.accepts(server);
+                    let text_edit = egui::TextEdit::singleline(server)
+                        .id(egui::Id::new((id, position)))
+                        .text_color(if is_valid {
+                            egui::Color32::WHITE
+                        } else {
+                            egui::Color32::RED
+                        });
+                    ui.add_sized(Vec2::new(170.0, 20.0), text_edit);
+
+                    if ui
+                        .add(egui::Button::new("✖").frame(false))
+                        .on_hover_text("Remove")
+                        .clicked()
+                    {
+                        remove = Some(position);
+                    }
+                });
+                ui.add_space(3.0);
+            }
+        });
 
-pub fn is_valid_ip(ip: &str) -> bool {
-    let parts: Vec<&str> = ip.split('.').collect();
-    if parts.len() != 4 {
-        return false;
+    if let Some(position) = remove {
+        servers.remove(position);
     }
-    for part in parts {
-        if part.parse::<u8>().is_err() {
-            return false;
-        }
+
+    if ui
+        .add(egui::Button::new(
+            egui::RichText::new(format!("➕ Add {} server", family.label()))
+                .color(egui::Color32::LIGHT_BLUE)
+                .size(12.0),
+        ))
+        .clicked()
+    {
+        servers.push(String::new());
     }
-    true
 }
 
 pub fn render_status_section(
     ui: &mut egui::Ui,
     dns_state: &DnsState,
     app_state: &AppState,
+    auto_status: Option<&str>,
     on_test_click: impl FnOnce(),
+    on_interception_click: impl FnOnce(),
+    on_undo_click: Option<impl FnOnce()>,
 ) {
     ui.vertical(|ui| {
         ui.horizontal(|ui| {
@@ -107,25 +147,69 @@ pub fn render_status_section(
                 if test_btn.clicked() {
                     on_test_click();
                 }
+                let interception_btn = ui
+                    .add_sized(
+                        Vec2::new(22.0, 22.0),
+                        egui::Button::new(egui::RichText::new("🕵").size(16.0)).frame(false),
+                    )
+                    .on_hover_text("Check for DNS interception")
+                    .on_hover_cursor(egui::CursorIcon::PointingHand);
+                if interception_btn.clicked() {
+                    on_interception_click();
+                }
+                if let Some(on_undo_click) = on_undo_click {
+                    let undo_btn = ui
+                        .add_sized(
+                            Vec2::new(22.0, 22.0),
+                            egui::Button::new(egui::RichText::new("↩").size(16.0)).frame(false),
+                        )
+                        .on_hover_text("Undo last change")
+                        .on_hover_cursor(egui::CursorIcon::PointingHand);
+                    if undo_btn.clicked() {
+                        on_undo_click();
+                    }
+                }
             });
         });
     });
 
+    if let Some(reason) = auto_status {
+        ui.add(
+            egui::Label::new(
+                egui::RichText::new(format!("⚡ Auto: {}", reason))
+                    .color(egui::Color32::LIGHT_BLUE)
+                    .size(11.0),
+            )
+            .truncate(),
+        )
+        .on_hover_text(reason);
+    }
+
     match dns_state {
         DnsState::Static(servers) => {
             ui.colored_label(STATUS_STATIC, "Static DNS Configuration 🔒");
+            let (ipv6, ipv4): (Vec<&String>, Vec<&String>) =
+                servers.iter().partition(|server| server.contains(':'));
             let fallback = String::from("None");
-            let primary = servers.first().unwrap_or(&fallback);
+            let primary = ipv4.first().copied().unwrap_or(&fallback);
             ui.label(
                 egui::RichText::new(format!("Primary: {}", primary)).color(egui::Color32::WHITE),
-            );
-            if servers.len() > 1 {
-                let secondary = servers.get(1).unwrap_or(&fallback);
+            )
+            .on_hover_text(servers.join("\n"));
+            if ipv4.len() > 1 {
                 ui.label(
-                    egui::RichText::new(format!("Secondary: {}", secondary))
+                    egui::RichText::new(format!("Secondary: {}", ipv4[1]))
                         .color(egui::Color32::WHITE),
                 );
             }
+            if !ipv6.is_empty() {
+                let ipv6: Vec<&str> = ipv6.iter().map(|server| server.as_str()).collect();
+                ui.label(
+                    egui::RichText::new(format!("IPv6: {}", ipv6.join(", ")))
+                        .color(egui::Color32::WHITE)
+                        .size(11.0),
+                );
+            }
         }
         DnsState::Dhcp => {
             ui.colored_label(STATUS_DHCP, "🔄 DHCP DNS Configuration");
@@ -149,8 +233,7 @@ pub fn render_status_section(
 pub fn render_provider_selection(
     ui: &mut egui::Ui,
     selected_provider: &DnsProvider,
-    custom_primary: &str,
-    custom_secondary: &str,
+    custom_provider: &DnsProvider,
     saved_entries: &[crate::domain::SavedDnsEntry],
     mut on_provider_change: impl FnMut(DnsProvider),
     mut on_custom_selected: impl FnMut(),
@@ -162,24 +245,14 @@ pub fn render_provider_selection(
         ("Shekan", DnsProvider::shekan()),
         ("Bogzar", DnsProvider::bogzar()),
         ("Quad9", DnsProvider::quad9()),
-        (
-            "Custom",
-            DnsProvider::custom(custom_primary.to_string(), custom_secondary.to_string()),
-        ),
+        ("Custom", custom_provider.clone()),
     ];
 
     for entry in saved_entries {
-        providers.push((
-            &entry.name,
-            DnsProvider::saved(
-                entry.name.clone(),
-                entry.primary.clone(),
-                entry.secondary.clone(),
-            ),
-        ));
+        providers.push((&entry.name, DnsProvider::saved(entry)));
     }
 
-    providers.push(("+", DnsProvider::custom(String::new(), String::new())));
+    providers.push(("+", DnsProvider::custom(ServerList::default())));
 
     let current_index = providers
         .iter()
@@ -286,6 +359,99 @@ pub fn render_provider_selection(
     }
 }
 
+pub fn render_adapter_selection(
+    ui: &mut egui::Ui,
+    adapters: &[AdapterInfo],
+    target: &AdapterTarget,
+    loading: bool,
+    mut on_target_change: impl FnMut(AdapterTarget),
+    on_refresh: impl FnOnce(),
+) {
+    let mut options = vec![AdapterTarget::Active];
+    options.extend(
+        adapters
+            .iter()
+            .map(|adapter| AdapterTarget::Named(adapter.name.clone())),
+    );
+    options.push(AdapterTarget::All);
+    let all: Vec<&str> = adapters
+        .iter()
+        .filter(|adapter| adapter.in_all())
+        .map(|adapter| adapter.name.as_str())
+        .collect();
+    let all = if all.is_empty() {
+        "No connected adapters besides VPNs, virtual switches and loopback".to_string()
+    } else {
+        format!("Changes {}", all.join(", "))
+    };
+
+    let mut refresh = false;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_salt("adapter_target")
+            .selected_text(
+                egui::RichText::new(format!("🖧 {}", target.display_name()))
+                    .color(egui::Color32::WHITE),
+            )
+            .width(BUTTON_WIDTH - 30.0)
+            .show_ui(ui, |ui| {
+                ui.style_mut().visuals.override_text_color = Some(egui::Color32::WHITE);
+
+                for option in &options {
+                    let adapter = match option {
+                        AdapterTarget::Named(name) => adapters.iter().find(|a| &a.name == name),
+                        _ => None,
+                    };
+                    let label = match adapter {
+                        Some(adapter) if adapter.connected => format!("🟢 {}", adapter.name),
+                        Some(adapter) => format!("⚪ {}", adapter.name),
+                        None => option.display_name(),
+                    };
+
+                    let mut response = ui.selectable_label(option == target, label);
+                    if let Some(adapter) = adapter {
+                        let dns = if adapter.dns.is_empty() {
+                            "None".to_string()
+                        } else {
+                            adapter.dns.join(", ")
+                        };
+                        response = response.on_hover_text(format!(
+                            "#{} {}\nStatus: {}\nDNS: {}",
+                            adapter.index, adapter.kind, adapter.status, dns
+                        ));
+                    } else if *option == AdapterTarget::All {
+                        response = response.on_hover_text(&all);
+                    }
+                    if response.clicked() {
+                        on_target_change(option.clone());
+                    }
+                }
+            });
+
+        if loading {
+            ui.spinner();
+        } else if ui
+            .add(egui::Button::new(egui::RichText::new("🔄").size(14.0)).frame(false))
+            .on_hover_text("Refresh adapters")
+            .on_hover_cursor(egui::CursorIcon::PointingHand)
+            .clicked()
+        {
+            refresh = true;
+        }
+    });
+
+    if *target == AdapterTarget::All {
+        ui.label(
+            egui::RichText::new(all)
+                .color(egui::Color32::LIGHT_GRAY)
+                .size(11.0),
+        );
+    }
+
+    if refresh {
+        on_refresh();
+    }
+}
+
 pub fn render_app_state(ui: &mut egui::Ui, app_state: &AppState) {
     match app_state {
         AppState::Idle => {}
@@ -307,6 +473,35 @@ pub fn render_app_state(ui: &mut egui::Ui, app_state: &AppState) {
     }
 }
 
+/// Keep/Revert buttons shown while freshly applied DNS servers wait for the
+/// user to confirm them.
+pub fn render_keep_changes(ui: &mut egui::Ui, on_keep: impl FnOnce(), on_revert: impl FnOnce()) {
+    ui.horizontal(|ui| {
+        if ui
+            .add_sized(
+                Vec2::new(70.0, 24.0),
+                egui::Button::new(egui::RichText::new("Keep").color(BUTTON_TEXT).size(12.0))
+                    .fill(BUTTON_SUCCESS)
+                    .corner_radius(6),
+            )
+            .clicked()
+        {
+            on_keep();
+        }
+        if ui
+            .add_sized(
+                Vec2::new(70.0, 24.0),
+                egui::Button::new(egui::RichText::new("Revert").color(BUTTON_TEXT).size(12.0))
+                    .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                    .corner_radius(6),
+            )
+            .clicked()
+        {
+            on_revert();
+        }
+    });
+}
+
 pub fn render_action_buttons(
     ui: &mut egui::Ui,
     provider_name: &str,
@@ -372,137 +567,761 @@ pub fn render_action_buttons(
     });
 }
 
-pub fn render_footer(
-    ui: &mut egui::Ui,
-    social_logos: &std::collections::HashMap<String, TextureHandle>,
-    has_delete_button: bool,
-) {
-    let icon_size = 28.0;
-    let icon_spacing = 15.0;
-    let light_gray = egui::Color32::from_rgb(180, 180, 180);
+/// Returns true when the user flipped the toggle.
+pub fn render_dry_run_toggle(ui: &mut egui::Ui, dry_run: &mut bool) -> bool {
+    ui.vertical_centered(|ui| {
+        ui.checkbox(
+            dry_run,
+            egui::RichText::new("Dry run")
+                .color(egui::Color32::WHITE)
+                .size(12.0),
+        )
+        .on_hover_text("Show the commands DNS changes would run without running them")
+        .changed()
+    })
+    .inner
+}
 
-    ui.vertical(|ui| {
-        let top_spacing = if has_delete_button { 10.0 } else { 40.0 };
-        ui.add_space(top_spacing);
+/// The local forwarder switch, with what it is doing underneath while on.
+pub fn render_forwarder_toggle(
+    ui: &mut egui::Ui,
+    enabled: &mut bool,
+    status: Option<(&str, &str)>,
+    on_rules_click: impl FnOnce(),
+    on_blocklists_click: impl FnOnce(),
+    on_query_log_click: impl FnOnce(),
+) -> bool {
+    ui.vertical_centered(|ui| {
+        let changed = ui
+            .checkbox(
+                enabled,
+                egui::RichText::new("Local forwarder")
+                    .color(egui::Color32::WHITE)
+                    .size(12.0),
+            )
+            .on_hover_text(
+                "Point the adapter at a caching resolver on 127.0.0.1 once, so switching \
+                 provider is instant. DNS stops working while DNSight is closed.",
+            )
+            .changed();
+        if let Some((summary, health)) = status {
+            let label = ui.label(
+                egui::RichText::new(summary)
+                    .color(egui::Color32::LIGHT_GRAY)
+                    .size(11.0),
+            );
+            if !health.is_empty() {
+                label.on_hover_text(health);
+            }
+        }
         ui.horizontal(|ui| {
-            ui.add_space(52.5);
-            let logos = vec![
-                ("cup-of-drink", "https://www.coffeete.ir/rezapourdast"),
-                ("email", "mailto:s.rezapourdast@gmail.com"),
-                ("github", "https://github.com/RezaPourdast"),
-            ];
-
-            for (logo_name, url) in logos {
-                if let Some(texture) = social_logos.get(logo_name) {
-                    let (rect, response) = ui
-                        .allocate_exact_size(Vec2::new(icon_size, icon_size), egui::Sense::click());
-
-                    let painter = ui.painter();
-                    painter.image(
-                        texture.id(),
-                        rect,
-                        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                        light_gray,
-                    );
-
-                    if response.clicked() {
-                        let _ = open::that(url);
-                    }
-
-                    if response.hovered() {
-                        painter.rect_filled(
-                            rect,
-                            0.0,
-                            egui::Color32::from_rgba_unmultiplied(255, 255, 255, 30),
-                        );
-                        ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
-                    }
+            // Roughly centres the buttons under the checkbox.
+            ui.add_space((ui.available_width() - 190.0).max(0.0) / 2.0);
+            if ui
+                .small_button(egui::RichText::new("Rules…").size(11.0))
+                .on_hover_text("Send some names to another provider through the forwarder")
+                .clicked()
+            {
+                on_rules_click();
+            }
+            if ui
+                .small_button(egui::RichText::new("Blocklists…").size(11.0))
+                .on_hover_text("Answer ad, tracker and malware domains without forwarding them")
+                .clicked()
+            {
+                on_blocklists_click();
+            }
+            if ui
+                .small_button(egui::RichText::new("Query log…").size(11.0))
+                .on_hover_text("Watch every query the forwarder answers")
+                .clicked()
+            {
+                on_query_log_click();
+            }
+        });
+        changed
+    })
+    .inner
+}
 
-                    ui.add_space(icon_spacing);
-                }
+pub fn render_plan_window_content(ui: &mut egui::Ui, steps: &[String], on_close: impl FnOnce()) {
+    ui.label(
+        egui::RichText::new("Nothing was changed. These steps would have run:")
+            .color(egui::Color32::WHITE),
+    );
+    ui.add_space(5.0);
+    egui::ScrollArea::vertical()
+        .max_height(250.0)
+        .show(ui, |ui| {
+            for (position, step) in steps.iter().enumerate() {
+                ui.label(
+                    egui::RichText::new(format!("{}. {}", position + 1, step))
+                        .monospace()
+                        .color(egui::Color32::LIGHT_GRAY),
+                );
             }
         });
+    ui.add_space(10.0);
+
+    ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+        if ui
+            .add_sized(
+                Vec2::new(80.0, 30.0),
+                egui::Button::new(egui::RichText::new("Close").color(BUTTON_TEXT))
+                    .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                    .corner_radius(6),
+            )
+            .clicked()
+        {
+            on_close();
+        }
     });
 }
 
-pub fn render_ping_window_content(
+/// Settings, progress and the sortable results table of the benchmark
+/// window. `progress` is `(finished, total)` servers while a run is going.
+#[allow(clippy::too_many_arguments)]
+pub fn render_benchmark_window_content(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
-    ping_value: f64,
-    ping_history: &[f64],
+    settings: &mut BenchmarkSettings,
+    auto_select: &mut AutoSelectSettings,
+    doh_method: &mut DohMethod,
+    domains: &mut String,
+    results: &[ServerStats],
+    fastest: Option<&ServerStats>,
+    progress: Option<(usize, usize)>,
+    error: Option<&str>,
+    sort: &mut SortColumn,
+    descending: &mut bool,
+    on_run: impl FnOnce(),
+    on_apply_fastest: impl FnOnce(),
 ) {
-    if let Some(texture) =
+    if let Some(Some(tex)) =
         ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("ping_background_texture")))
     {
-        if let Some(ref tex) = texture {
-            let painter = ui.painter();
-            let viewport_rect = ui.ctx().viewport_rect();
-            let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
-            painter.image(
-                tex.id(),
-                viewport_rect,
-                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                tint,
-            );
-        }
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
     }
 
+    let running = progress.is_some();
     ui.vertical(|ui| {
-        ui.spacing_mut().item_spacing.y = 0.0;
+        ui.spacing_mut().item_spacing.y = 6.0;
         ui.vertical_centered(|ui| {
-            ui.heading(" Ping Monitor");
-            ui.add_space(10.0);
-
-            let ping_text = format!("{} ms", ping_value);
-            let ping_color = if ping_value == 0.0 {
-                egui::Color32::LIGHT_GRAY
-            } else if ping_value < 100.0 {
-                egui::Color32::GREEN
-            } else if ping_value < 200.0 {
-                egui::Color32::YELLOW
-            } else {
-                egui::Color32::RED
-            };
-
-            ui.label(egui::RichText::new(ping_text).size(28.0).color(ping_color));
+            ui.label(
+                egui::RichText::new("DNS Benchmark")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
         });
 
-        ui.add_space(10.0);
+        ui.add_enabled_ui(!running, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Rounds").color(egui::Color32::WHITE));
+                ui.add(egui::DragValue::new(&mut settings.rounds).range(1..=50));
+                ui.label(egui::RichText::new("Timeout").color(egui::Color32::WHITE));
+                ui.add(
+                    egui::DragValue::new(&mut settings.timeout_ms)
+                        .range(100..=10_000)
+                        .suffix(" ms"),
+                );
+                ui.selectable_value(&mut settings.cache_mode, CacheMode::Warm, "Warm cache")
+                    .on_hover_text(
+                        "Query each domain once before timing, then measure cached answers",
+                    );
+                ui.selectable_value(&mut settings.cache_mode, CacheMode::Cold, "Cold cache")
+                    .on_hover_text("Ask for a fresh random subdomain every time");
+                ui.label(egui::RichText::new("DoH").color(egui::Color32::WHITE));
+                ui.selectable_value(doh_method, DohMethod::Get, "GET");
+                ui.selectable_value(doh_method, DohMethod::Post, "POST");
+            });
+            ui.add(
+                egui::TextEdit::multiline(domains)
+                    .desired_rows(3)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("One domain per line"),
+            );
+        });
 
-        if !ping_history.is_empty() {
-            let line_color = if ping_value == 0.0 {
-                egui::Color32::LIGHT_GRAY
-            } else if ping_value < 100.0 {
-                egui::Color32::GREEN
-            } else if ping_value < 200.0 {
-                egui::Color32::YELLOW
-            } else {
-                egui::Color32::RED
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !running,
+                    egui::Button::new(egui::RichText::new("Run").color(BUTTON_TEXT))
+                        .fill(BUTTON_SUCCESS)
+                        .corner_radius(6),
+                )
+                .clicked()
+            {
+                on_run();
+            }
+            let apply_text = match fastest {
+                Some(stats) => format!("Apply fastest ({})", stats.provider.display_name()),
+                None => "Apply fastest".to_string(),
             };
+            if ui
+                .add_enabled(
+                    !running && fastest.is_some(),
+                    egui::Button::new(egui::RichText::new(apply_text).color(BUTTON_TEXT))
+                        .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                        .corner_radius(6),
+                )
+                .clicked()
+            {
+                on_apply_fastest();
+            }
 
-            let chart_height = 150.0;
-            let chart_margin = 40.0;
-            let chart_width = ui.available_width() - (chart_margin * 2.0);
-            let (chart_rect, _) =
-                ui.allocate_exact_size(egui::vec2(chart_width, chart_height), egui::Sense::hover());
-            let chart_rect = chart_rect.translate(egui::vec2(chart_margin, 0.0));
+            if let Some((finished, total)) = progress {
+                ui.spinner();
+                ui.label(
+                    egui::RichText::new(format!("{} / {} servers", finished, total))
+                        .color(egui::Color32::LIGHT_GRAY),
+                );
+            } else if let Some(error) = error {
+                ui.label(egui::RichText::new(error).color(ERROR));
+            }
+        });
 
-            let painter = ui.painter();
+        render_auto_select_settings(ui, auto_select);
+        render_benchmark_table(ui, results, fastest, sort, descending);
+    });
+}
 
-            painter.rect_filled(
-                chart_rect,
-                0.0,
-                egui::Color32::from_rgba_unmultiplied(20, 20, 20, 100),
+fn render_auto_select_settings(ui: &mut egui::Ui, settings: &mut AutoSelectSettings) {
+    ui.horizontal(|ui| {
+        ui.checkbox(
+            &mut settings.enabled,
+            egui::RichText::new("Auto-pick fastest").color(egui::Color32::WHITE),
+        )
+        .on_hover_text("Benchmark every provider periodically and switch to the best one");
+        ui.add_enabled_ui(settings.enabled, |ui| {
+            ui.label(egui::RichText::new("every").color(egui::Color32::WHITE));
+            let mut minutes = settings.interval_secs / 60;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut minutes)
+                        .range(1..=1440)
+                        .suffix(" min"),
+                )
+                .changed()
+            {
+                settings.interval_secs = minutes * 60;
+            }
+            ui.label(egui::RichText::new("stay").color(egui::Color32::WHITE));
+            let mut minutes = settings.dwell_secs / 60;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut minutes)
+                        .range(0..=1440)
+                        .suffix(" min"),
+                )
+                .on_hover_text("Minimum time on a provider before switching away from it")
+                .changed()
+            {
+                settings.dwell_secs = minutes * 60;
+            }
+            ui.label(egui::RichText::new("margin").color(egui::Color32::WHITE));
+            ui.add(
+                egui::DragValue::new(&mut settings.hysteresis_percent)
+                    .range(0..=90)
+                    .suffix("%"),
+            )
+            .on_hover_text(
+                "How much better another provider has to score to replace the current one",
             );
+        });
+    });
+}
 
-            let min_val = ping_history
-                .iter()
-                .copied()
-                .fold(f64::INFINITY, f64::min)
-                .max(0.0);
-            let max_val = ping_history
-                .iter()
-                .copied()
+fn render_benchmark_table(
+    ui: &mut egui::Ui,
+    results: &[ServerStats],
+    fastest: Option<&ServerStats>,
+    sort: &mut SortColumn,
+    descending: &mut bool,
+) {
+    use egui_extras::{Column, TableBuilder};
+
+    let columns = [
+        ("Provider", SortColumn::Provider),
+        ("Server", SortColumn::Server),
+        ("Min", SortColumn::Min),
+        ("Median", SortColumn::Median),
+        ("p95", SortColumn::P95),
+        ("Timeouts", SortColumn::Timeouts),
+        ("Errors", SortColumn::Errors),
+    ];
+
+    TableBuilder::new(ui)
+        .striped(true)
+        .column(Column::auto().at_least(70.0))
+        .column(Column::remainder().at_least(120.0))
+        .columns(Column::auto().at_least(55.0), columns.len() - 2)
+        .header(20.0, |mut header| {
+            for (label, column) in columns {
+                header.col(|ui| {
+                    let arrow = match (*sort == column, *descending) {
+                        (true, false) => " ⏶",
+                        (true, true) => " ⏷",
+                        (false, _) => "",
+                    };
+                    let text = egui::RichText::new(format!("{}{}", label, arrow))
+                        .color(egui::Color32::WHITE)
+                        .strong();
+                    if ui.add(egui::Button::new(text).frame(false)).clicked() {
+                        if *sort == column {
+                            *descending = !*descending;
+                        } else {
+                            *sort = column;
+                            *descending = false;
+                        }
+                    }
+                });
+            }
+        })
+        .body(|mut body| {
+            for stats in results {
+                let color = if fastest.is_some_and(|f| f.server == stats.server) {
+                    SUCCESS
+                } else {
+                    egui::Color32::LIGHT_GRAY
+                };
+                let cell = |text: String| egui::RichText::new(text).color(color);
+                body.row(18.0, |mut row| {
+                    row.col(|ui| {
+                        ui.label(cell(stats.provider.display_name()));
+                    });
+                    row.col(|ui| {
+                        ui.label(cell(stats.server.to_string()));
+                    });
+                    for latency in [stats.min, stats.median, stats.p95] {
+                        row.col(|ui| {
+                            ui.label(cell(format_latency(latency)));
+                        });
+                    }
+                    row.col(|ui| {
+                        ui.label(cell(format!("{:.0}%", stats.timeout_rate() * 100.0)));
+                    });
+                    row.col(|ui| {
+                        let response = ui.label(cell(stats.errors.to_string()));
+                        if let Some(error) = &stats.last_error {
+                            response.on_hover_text(error);
+                        }
+                    });
+                });
+            }
+        });
+}
+
+/// Domain input and the side-by-side answers of the compare window.
+/// Answers that disagree with most providers are highlighted.
+pub fn render_compare_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    domain: &mut String,
+    answers: &[ProviderAnswer],
+    running: bool,
+    error: Option<&str>,
+    on_compare: impl FnOnce(),
+) {
+    if let Some(Some(tex)) =
+        ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("ping_background_texture")))
+    {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 6.0;
+        ui.vertical_centered(|ui| {
+            ui.label(
+                egui::RichText::new("Compare Answers")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            let input = ui.add_enabled(
+                !running,
+                egui::TextEdit::singleline(domain)
+                    .desired_width(300.0)
+                    .hint_text("Domain, e.g. youtube.com"),
+            );
+            let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            let clicked = ui
+                .add_enabled(
+                    !running,
+                    egui::Button::new(egui::RichText::new("Compare").color(BUTTON_TEXT))
+                        .fill(BUTTON_SUCCESS)
+                        .corner_radius(6),
+                )
+                .clicked();
+            if (clicked || submitted) && !running {
+                on_compare();
+            }
+            if running {
+                ui.spinner();
+            } else if let Some(error) = error {
+                ui.label(egui::RichText::new(error).color(ERROR));
+            }
+        });
+
+        render_compare_table(ui, answers);
+    });
+}
+
+fn render_compare_table(ui: &mut egui::Ui, answers: &[ProviderAnswer]) {
+    use egui_extras::{Column, TableBuilder};
+
+    TableBuilder::new(ui)
+        .striped(true)
+        .column(Column::auto().at_least(70.0))
+        .column(Column::remainder().at_least(140.0).clip(true))
+        .column(Column::remainder().at_least(140.0).clip(true))
+        .column(Column::auto().at_least(90.0))
+        .header(20.0, |mut header| {
+            for label in ["Provider", "A", "AAAA", "Result"] {
+                header.col(|ui| {
+                    ui.label(
+                        egui::RichText::new(label)
+                            .color(egui::Color32::WHITE)
+                            .strong(),
+                    );
+                });
+            }
+        })
+        .body(|mut body| {
+            for answer in answers {
+                let color = match (&answer.outcome, answer.divergent) {
+                    (Outcome::Unreachable(_), _) => egui::Color32::GRAY,
+                    (_, true) => WARNING,
+                    (_, false) => egui::Color32::LIGHT_GRAY,
+                };
+                let cell = |text: String| egui::RichText::new(text).color(color);
+                let (v4, v6) = match &answer.outcome {
+                    Outcome::Addresses { v4, v6 } => (compare::join(v4), compare::join(v6)),
+                    _ => ("—".to_string(), "—".to_string()),
+                };
+                body.row(18.0, |mut row| {
+                    row.col(|ui| {
+                        let response = ui.label(cell(answer.provider.display_name()));
+                        if let Some(server) = answer.server {
+                            response.on_hover_text(format!("Answered by {}", server.ip()));
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.label(cell(v4.clone())).on_hover_text(v4);
+                    });
+                    row.col(|ui| {
+                        ui.label(cell(v6.clone())).on_hover_text(v6);
+                    });
+                    row.col(|ui| {
+                        let mut result = answer.outcome.to_string();
+                        if answer.divergent {
+                            result = format!("⚠ {}", result);
+                        }
+                        ui.label(cell(result))
+                            .on_hover_text(answer.outcome.to_string());
+                    });
+                });
+            }
+        });
+}
+
+/// Host list and the host × provider grid of the reachability window.
+/// `progress` is `(finished, total)` cells while a run is going.
+#[allow(clippy::too_many_arguments)]
+pub fn render_reachability_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    settings: &mut ReachabilitySettings,
+    hosts: &mut String,
+    providers: &[String],
+    cells: &[Cell],
+    progress: Option<(usize, usize)>,
+    error: Option<&str>,
+    on_run: impl FnOnce(),
+) {
+    if let Some(Some(tex)) =
+        ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("ping_background_texture")))
+    {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    let running = progress.is_some();
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 6.0;
+        ui.vertical_centered(|ui| {
+            ui.label(
+                egui::RichText::new("Service Reachability")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
+        });
+
+        ui.add_enabled_ui(!running, |ui| {
+            ui.add(
+                egui::TextEdit::multiline(hosts)
+                    .desired_rows(3)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("One host per line"),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !running,
+                    egui::Button::new(egui::RichText::new("Run").color(BUTTON_TEXT))
+                        .fill(BUTTON_SUCCESS)
+                        .corner_radius(6),
+                )
+                .clicked()
+            {
+                on_run();
+            }
+            ui.label(egui::RichText::new("Timeout").color(egui::Color32::WHITE));
+            ui.add_enabled(
+                !running,
+                egui::DragValue::new(&mut settings.timeout_ms)
+                    .range(500..=15_000)
+                    .suffix(" ms"),
+            );
+
+            if let Some((finished, total)) = progress {
+                ui.spinner();
+                ui.label(
+                    egui::RichText::new(format!("{} / {} checks", finished, total))
+                        .color(egui::Color32::LIGHT_GRAY),
+                );
+            } else if let Some(error) = error {
+                ui.label(egui::RichText::new(error).color(ERROR));
+            }
+        });
+
+        render_reachability_grid(ui, &settings.hosts, providers, cells);
+    });
+}
+
+fn render_reachability_grid(
+    ui: &mut egui::Ui,
+    hosts: &[String],
+    providers: &[String],
+    cells: &[Cell],
+) {
+    use egui_extras::{Column, TableBuilder};
+
+    TableBuilder::new(ui)
+        .striped(true)
+        .column(Column::auto().at_least(150.0))
+        .columns(Column::auto().at_least(70.0), providers.len())
+        .header(20.0, |mut header| {
+            header.col(|ui| {
+                ui.label(
+                    egui::RichText::new("Host")
+                        .color(egui::Color32::WHITE)
+                        .strong(),
+                );
+            });
+            for provider in providers {
+                header.col(|ui| {
+                    ui.label(
+                        egui::RichText::new(provider)
+                            .color(egui::Color32::WHITE)
+                            .strong(),
+                    );
+                });
+            }
+        })
+        .body(|mut body| {
+            for host in hosts {
+                body.row(18.0, |mut row| {
+                    row.col(|ui| {
+                        ui.label(egui::RichText::new(host).color(egui::Color32::LIGHT_GRAY));
+                    });
+                    for provider in providers {
+                        let cell = cells
+                            .iter()
+                            .find(|cell| cell.host == *host && cell.provider == *provider);
+                        row.col(|ui| match cell {
+                            Some(cell) => {
+                                let color = match &cell.result {
+                                    Reachability::Reachable { .. } => SUCCESS,
+                                    result if result.is_blocked() => ERROR,
+                                    _ => WARNING,
+                                };
+                                ui.label(egui::RichText::new(cell.result.label()).color(color))
+                                    .on_hover_text(cell.result.details());
+                            }
+                            None => {
+                                ui.label(egui::RichText::new("…").color(egui::Color32::GRAY));
+                            }
+                        });
+                    }
+                });
+            }
+        });
+}
+
+fn format_latency(latency: Option<std::time::Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{} ms", latency.as_millis()),
+        None => "—".to_string(),
+    }
+}
+
+pub fn render_footer(
+    ui: &mut egui::Ui,
+    social_logos: &std::collections::HashMap<String, TextureHandle>,
+    has_delete_button: bool,
+) {
+    let icon_size = 28.0;
+    let icon_spacing = 15.0;
+    let light_gray = egui::Color32::from_rgb(180, 180, 180);
+
+    ui.vertical(|ui| {
+        let top_spacing = if has_delete_button { 10.0 } else { 40.0 };
+        ui.add_space(top_spacing);
+        ui.horizontal(|ui| {
+            ui.add_space(52.5);
+            let logos = vec![
+                ("cup-of-drink", "https://www.coffeete.ir/rezapourdast"),
+                ("email", "mailto:s.rezapourdast@gmail.com"),
+                ("github", "https://github.com/RezaPourdast"),
+            ];
+
+            for (logo_name, url) in logos {
+                if let Some(texture) = social_logos.get(logo_name) {
+                    let (rect, response) = ui
+                        .allocate_exact_size(Vec2::new(icon_size, icon_size), egui::Sense::click());
+
+                    let painter = ui.painter();
+                    painter.image(
+                        texture.id(),
+                        rect,
+                        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                        light_gray,
+                    );
+
+                    if response.clicked() {
+                        let _ = open::that(url);
+                    }
+
+                    if response.hovered() {
+                        painter.rect_filled(
+                            rect,
+                            0.0,
+                            egui::Color32::from_rgba_unmultiplied(255, 255, 255, 30),
+                        );
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                    }
+
+                    ui.add_space(icon_spacing);
+                }
+            }
+        });
+    });
+}
+
+pub fn render_ping_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    ping_value: f64,
+    ping_history: &[f64],
+) {
+    if let Some(Some(tex)) =
+        ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("ping_background_texture")))
+    {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 0.0;
+        ui.vertical_centered(|ui| {
+            ui.heading(" Ping Monitor");
+            ui.add_space(10.0);
+
+            let ping_text = format!("{} ms", ping_value);
+            let ping_color = if ping_value == 0.0 {
+                egui::Color32::LIGHT_GRAY
+            } else if ping_value < 100.0 {
+                egui::Color32::GREEN
+            } else if ping_value < 200.0 {
+                egui::Color32::YELLOW
+            } else {
+                egui::Color32::RED
+            };
+
+            ui.label(egui::RichText::new(ping_text).size(28.0).color(ping_color));
+        });
+
+        ui.add_space(10.0);
+
+        if !ping_history.is_empty() {
+            let line_color = if ping_value == 0.0 {
+                egui::Color32::LIGHT_GRAY
+            } else if ping_value < 100.0 {
+                egui::Color32::GREEN
+            } else if ping_value < 200.0 {
+                egui::Color32::YELLOW
+            } else {
+                egui::Color32::RED
+            };
+
+            let chart_height = 150.0;
+            let chart_margin = 40.0;
+            let chart_width = ui.available_width() - (chart_margin * 2.0);
+            let (chart_rect, _) =
+                ui.allocate_exact_size(egui::vec2(chart_width, chart_height), egui::Sense::hover());
+            let chart_rect = chart_rect.translate(egui::vec2(chart_margin, 0.0));
+
+            let painter = ui.painter();
+
+            painter.rect_filled(
+                chart_rect,
+                0.0,
+                egui::Color32::from_rgba_unmultiplied(20, 20, 20, 100),
+            );
+
+            let min_val = ping_history
+                .iter()
+                .copied()
+                .fold(f64::INFINITY, f64::min)
+                .max(0.0);
+            let max_val = ping_history
+                .iter()
+                .copied()
                 .fold(f64::NEG_INFINITY, f64::max)
                 .max(100.0);
             let range = (max_val - min_val).max(1.0);
@@ -603,25 +1422,23 @@ pub fn render_ping_window_content(
 pub fn render_custom_dns_window_content(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
-    custom_primary: &mut String,
-    custom_secondary: &mut String,
+    custom_servers: &mut Vec<String>,
+    custom_servers_v6: &mut Vec<String>,
     on_save: impl FnOnce(),
     on_clear: impl FnOnce(),
 ) {
-    if let Some(texture) = ctx.data(|d| {
+    if let Some(Some(tex)) = ctx.data(|d| {
         d.get_temp::<Option<TextureHandle>>(egui::Id::new("custom_dns_background_texture"))
     }) {
-        if let Some(ref tex) = texture {
-            let painter = ui.painter();
-            let viewport_rect = ui.ctx().viewport_rect();
-            let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
-            painter.image(
-                tex.id(),
-                viewport_rect,
-                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                tint,
-            );
-        }
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
     }
 
     ui.vertical(|ui| {
@@ -646,13 +1463,23 @@ pub fn render_custom_dns_window_content(
                 ui.set_width(available_width);
                 ui.vertical(|ui| {
                     ui.add_space(12.0);
-                    render_ip_input(ui, custom_primary, "1st DNS ");
+                    render_server_list_input(
+                        ui,
+                        "custom_dns_servers",
+                        IpFamily::V4,
+                        custom_servers,
+                    );
                     ui.add_space(5.0);
-                    render_ip_input(ui, custom_secondary, "2nd DNS");
+                    render_server_list_input(
+                        ui,
+                        "custom_dns_servers_v6",
+                        IpFamily::V6,
+                        custom_servers_v6,
+                    );
 
                     ui.add_space(3.0);
                     ui.label(
-                        egui::RichText::new("Example: 8.8.8.8, 1.1.1.1")
+                        egui::RichText::new("Example: 8.8.8.8, 2001:4860:4860::8888")
                             .color(egui::Color32::from_rgba_unmultiplied(150, 150, 150, 150))
                             .size(11.0),
                     );
@@ -702,30 +1529,31 @@ pub fn render_custom_dns_window_content(
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_add_dns_window_content(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
     name: &mut String,
-    primary: &mut String,
-    secondary: &mut String,
+    servers: &mut Vec<String>,
+    servers_v6: &mut Vec<String>,
+    doh_url: &mut String,
+    tls_name: &mut String,
     error_message: Option<String>,
     on_save: impl FnOnce(),
     on_cancel: impl FnOnce(),
 ) {
-    if let Some(texture) = ctx.data(|d| {
+    if let Some(Some(tex)) = ctx.data(|d| {
         d.get_temp::<Option<TextureHandle>>(egui::Id::new("custom_dns_background_texture"))
     }) {
-        if let Some(ref tex) = texture {
-            let painter = ui.painter();
-            let viewport_rect = ui.ctx().viewport_rect();
-            let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
-            painter.image(
-                tex.id(),
-                viewport_rect,
-                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                tint,
-            );
-        }
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
     }
 
     ui.vertical(|ui| {
@@ -769,22 +1597,42 @@ pub fn render_add_dns_window_content(
                     } else {
                         ui.add_space(5.0);
                     }
-                    render_ip_input(ui, primary, "1st DNS ");
+                    render_server_list_input(ui, "new_dns_servers", IpFamily::V4, servers);
                     ui.add_space(5.0);
-                    render_ip_input(ui, secondary, "2nd DNS");
+                    render_server_list_input(ui, "new_dns_servers_v6", IpFamily::V6, servers_v6);
 
                     ui.add_space(3.0);
                     ui.label(
-                        egui::RichText::new("Example: 8.8.8.8, 1.1.1.1")
+                        egui::RichText::new("Example: 8.8.8.8, 2001:4860:4860::8888")
                             .color(egui::Color32::from_rgba_unmultiplied(150, 150, 150, 150))
                             .size(11.0),
                     );
 
-                    ui.add_space(5.0);
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("DoH URL:").color(egui::Color32::WHITE));
+                        ui.add_sized(
+                            Vec2::new(200.0, 20.0),
+                            egui::TextEdit::singleline(doh_url)
+                                .text_color(egui::Color32::WHITE)
+                                .hint_text("optional, https://…/dns-query"),
+                        );
+                    });
 
-                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
-                        if ui
-                            .add_sized(
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("TLS name:").color(egui::Color32::WHITE));
+                        ui.add_sized(
+                            Vec2::new(200.0, 20.0),
+                            egui::TextEdit::singleline(tls_name)
+                                .text_color(egui::Color32::WHITE)
+                                .hint_text("optional, for DoT on port 853"),
+                        );
+                    });
+
+                    ui.add_space(5.0);
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+                        if ui
+                            .add_sized(
                                 Vec2::new(70.0, 30.0),
                                 egui::Button::new(
                                     egui::RichText::new("Save")
@@ -824,3 +1672,577 @@ pub fn render_add_dns_window_content(
         });
     });
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_rules_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    rules: &[RoutingRule],
+    new_rule: &mut RoutingRule,
+    strategy: &mut UpstreamStrategy,
+    providers: &[String],
+    error_message: Option<String>,
+    on_add: impl FnOnce(),
+    mut on_delete: impl FnMut(usize),
+    on_close: impl FnOnce(),
+) {
+    if let Some(Some(tex)) = ctx.data(|d| {
+        d.get_temp::<Option<TextureHandle>>(egui::Id::new("custom_dns_background_texture"))
+    }) {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    ui.vertical(|ui| {
+        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
+            ui.label(
+                egui::RichText::new("Routing Rules")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
+            ui.label(
+                egui::RichText::new("First match wins; other names use the selected DNS")
+                    .color(egui::Color32::from_rgba_unmultiplied(150, 150, 150, 150))
+                    .size(11.0),
+            );
+        });
+        ui.add_space(SPACING_SMALL);
+
+        let left_margin = 8.0;
+        let right_margin = 16.0;
+        ui.horizontal(|ui| {
+            ui.add_space(left_margin);
+            let available_width = ui.available_width() - left_margin - right_margin;
+            let frame = egui::Frame::group(ui.style())
+                .fill(egui::Color32::from_rgba_unmultiplied(60, 60, 65, 45))
+                .corner_radius(12.0);
+            frame.show(ui, |ui| {
+                ui.set_width(available_width);
+                ui.vertical(|ui| {
+                    ui.add_space(8.0);
+
+                    egui::ScrollArea::vertical()
+                        .max_height(170.0)
+                        .show(ui, |ui| {
+                            if rules.is_empty() {
+                                ui.label(
+                                    egui::RichText::new("No rules yet")
+                                        .color(egui::Color32::LIGHT_GRAY),
+                                );
+                            }
+                            for (index, rule) in rules.iter().enumerate() {
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                                        on_delete(index);
+                                    }
+                                    ui.label(
+                                        egui::RichText::new(format!(
+                                            "{} {} → {} ({})",
+                                            rule.kind.label(),
+                                            rule.pattern,
+                                            rule.upstream,
+                                            rule.strategy.label()
+                                        ))
+                                        .color(egui::Color32::WHITE),
+                                    );
+                                });
+                            }
+                        });
+
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("Other names:").color(egui::Color32::WHITE));
+                        ui.style_mut().visuals.override_text_color = Some(egui::Color32::WHITE);
+                        render_strategy_combo(ui, "default_strategy", strategy);
+                    })
+                    .response
+                    .on_hover_text(
+                        "Failover waits briefly for each server in turn, Race asks them all at \
+                         once and Round-robin starts each query at the next server",
+                    );
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        ui.style_mut().visuals.override_text_color = Some(egui::Color32::WHITE);
+                        egui::ComboBox::from_id_salt("rule_kind")
+                            .selected_text(new_rule.kind.label())
+                            .width(80.0)
+                            .show_ui(ui, |ui| {
+                                for option in MatchKind::ALL {
+                                    ui.selectable_value(&mut new_rule.kind, option, option.label());
+                                }
+                            });
+                        ui.add_sized(
+                            Vec2::new(170.0, 20.0),
+                            egui::TextEdit::singleline(&mut new_rule.pattern)
+                                .text_color(egui::Color32::WHITE)
+                                .hint_text(match new_rule.kind {
+                                    MatchKind::Suffix => "corp.internal",
+                                    MatchKind::Exact => "intranet.example",
+                                    MatchKind::Regex => r"^ads?\d*\.",
+                                    MatchKind::Wildcard => "*.corp.internal",
+                                }),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("Send to:").color(egui::Color32::WHITE));
+                        ui.style_mut().visuals.override_text_color = Some(egui::Color32::WHITE);
+                        egui::ComboBox::from_id_salt("rule_upstream")
+                            .selected_text(new_rule.upstream.as_str())
+                            .width(100.0)
+                            .show_ui(ui, |ui| {
+                                for name in providers {
+                                    ui.selectable_value(&mut new_rule.upstream, name.clone(), name);
+                                }
+                            });
+                        render_strategy_combo(ui, "rule_strategy", &mut new_rule.strategy);
+                    });
+
+                    if let Some(error) = error_message {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .color(egui::Color32::RED)
+                                .size(11.0),
+                        );
+                        ui.add_space(1.0);
+                    } else {
+                        ui.add_space(5.0);
+                    }
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+                        if ui
+                            .add_sized(
+                                Vec2::new(70.0, 30.0),
+                                egui::Button::new(
+                                    egui::RichText::new("Add")
+                                        .color(egui::Color32::WHITE)
+                                        .size(12.0),
+                                )
+                                .fill(BUTTON_SUCCESS)
+                                .corner_radius(6.0),
+                            )
+                            .clicked()
+                        {
+                            on_add();
+                        }
+
+                        ui.add_space(5.0);
+
+                        if ui
+                            .add_sized(
+                                Vec2::new(70.0, 30.0),
+                                egui::Button::new(
+                                    egui::RichText::new("Close")
+                                        .color(egui::Color32::WHITE)
+                                        .size(12.0),
+                                )
+                                .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                                .corner_radius(6.0),
+                            )
+                            .clicked()
+                        {
+                            on_close();
+                        }
+                    });
+                    ui.add_space(5.0);
+                });
+            });
+            ui.add_space(right_margin);
+        });
+    });
+}
+
+fn render_strategy_combo(ui: &mut egui::Ui, id: &str, strategy: &mut UpstreamStrategy) {
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(strategy.label())
+        .width(90.0)
+        .show_ui(ui, |ui| {
+            for option in UpstreamStrategy::ALL {
+                ui.selectable_value(strategy, option, option.label());
+            }
+        });
+}
+
+/// Which of the two kinds of list a path in the blocklists window is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Block,
+    Allow,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_blocklists_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    blocking: &BlockingSettings,
+    response: &mut BlockResponse,
+    path: &mut String,
+    counts: Option<(u64, u64)>,
+    loaded: Option<&str>,
+    error_message: Option<String>,
+    on_add: impl FnOnce(ListKind),
+    mut on_remove: impl FnMut(ListKind, usize),
+    on_close: impl FnOnce(),
+) {
+    if let Some(Some(tex)) = ctx.data(|d| {
+        d.get_temp::<Option<TextureHandle>>(egui::Id::new("custom_dns_background_texture"))
+    }) {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    ui.vertical(|ui| {
+        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
+            ui.label(
+                egui::RichText::new("Blocklists")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
+            let counts = match counts {
+                Some((blocked, allowed)) => {
+                    format!("{} queries blocked, {} allowed", blocked, allowed)
+                }
+                None => "Turn on the local forwarder to use blocklists".to_string(),
+            };
+            ui.label(egui::RichText::new(counts).color(egui::Color32::LIGHT_GRAY));
+        });
+        ui.add_space(SPACING_SMALL);
+
+        let left_margin = 8.0;
+        let right_margin = 16.0;
+        ui.horizontal(|ui| {
+            ui.add_space(left_margin);
+            let available_width = ui.available_width() - left_margin - right_margin;
+            let frame = egui::Frame::group(ui.style())
+                .fill(egui::Color32::from_rgba_unmultiplied(60, 60, 65, 45))
+                .corner_radius(12.0);
+            frame.show(ui, |ui| {
+                ui.set_width(available_width);
+                ui.vertical(|ui| {
+                    ui.add_space(8.0);
+
+                    egui::ScrollArea::vertical()
+                        .max_height(150.0)
+                        .show(ui, |ui| {
+                            let lists = blocking
+                                .blocklists
+                                .iter()
+                                .map(|path| (ListKind::Block, path))
+                                .enumerate()
+                                .chain(
+                                    blocking
+                                        .allowlists
+                                        .iter()
+                                        .map(|path| (ListKind::Allow, path))
+                                        .enumerate(),
+                                );
+                            let mut empty = true;
+                            for (index, (kind, path)) in lists {
+                                empty = false;
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                                        on_remove(kind, index);
+                                    }
+                                    let (tag, color) = match kind {
+                                        ListKind::Block => ("⛔", egui::Color32::LIGHT_RED),
+                                        ListKind::Allow => ("✔", egui::Color32::LIGHT_GREEN),
+                                    };
+                                    ui.label(egui::RichText::new(tag).color(color));
+                                    ui.add(
+                                        egui::Label::new(
+                                            egui::RichText::new(path).color(egui::Color32::WHITE),
+                                        )
+                                        .truncate(),
+                                    )
+                                    .on_hover_text(path);
+                                });
+                            }
+                            if empty {
+                                ui.label(
+                                    egui::RichText::new("No lists yet")
+                                        .color(egui::Color32::LIGHT_GRAY),
+                                );
+                            }
+                        });
+                    if let Some(loaded) = loaded {
+                        ui.label(
+                            egui::RichText::new(loaded)
+                                .color(egui::Color32::LIGHT_GRAY)
+                                .size(11.0),
+                        );
+                    }
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("Answer:").color(egui::Color32::WHITE));
+                        ui.style_mut().visuals.override_text_color = Some(egui::Color32::WHITE);
+                        egui::ComboBox::from_id_salt("block_response")
+                            .selected_text(response.label())
+                            .width(100.0)
+                            .show_ui(ui, |ui| {
+                                for option in BlockResponse::ALL {
+                                    ui.selectable_value(response, option, option.label());
+                                }
+                            });
+                    });
+
+                    ui.add_sized(
+                        Vec2::new(ui.available_width(), 20.0),
+                        egui::TextEdit::singleline(path)
+                            .text_color(egui::Color32::WHITE)
+                            .hint_text("Path to a hosts, AdBlock or domain list"),
+                    );
+
+                    if let Some(error) = error_message {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .color(egui::Color32::RED)
+                                .size(11.0),
+                        );
+                        ui.add_space(1.0);
+                    } else {
+                        ui.add_space(5.0);
+                    }
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+                        let mut add = None;
+                        if ui
+                            .add_sized(
+                                Vec2::new(70.0, 30.0),
+                                egui::Button::new(
+                                    egui::RichText::new("Block")
+                                        .color(egui::Color32::WHITE)
+                                        .size(12.0),
+                                )
+                                .fill(BUTTON_DANGER)
+                                .corner_radius(6.0),
+                            )
+                            .clicked()
+                        {
+                            add = Some(ListKind::Block);
+                        }
+
+                        ui.add_space(5.0);
+
+                        if ui
+                            .add_sized(
+                                Vec2::new(70.0, 30.0),
+                                egui::Button::new(
+                                    egui::RichText::new("Allow")
+                                        .color(egui::Color32::WHITE)
+                                        .size(12.0),
+                                )
+                                .fill(BUTTON_SUCCESS)
+                                .corner_radius(6.0),
+                            )
+                            .clicked()
+                        {
+                            add = Some(ListKind::Allow);
+                        }
+                        if let Some(kind) = add {
+                            on_add(kind);
+                        }
+
+                        ui.add_space(5.0);
+
+                        if ui
+                            .add_sized(
+                                Vec2::new(70.0, 30.0),
+                                egui::Button::new(
+                                    egui::RichText::new("Close")
+                                        .color(egui::Color32::WHITE)
+                                        .size(12.0),
+                                )
+                                .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                                .corner_radius(6.0),
+                            )
+                            .clicked()
+                        {
+                            on_close();
+                        }
+                    });
+                    ui.add_space(5.0);
+                });
+            });
+            ui.add_space(right_margin);
+        });
+    });
+}
+
+/// The live query log: a filter, pause and export, then one row per query
+/// with the newest at the bottom.
+#[allow(clippy::too_many_arguments)]
+pub fn render_query_log_window_content(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    events: &[&QueryEvent],
+    total: usize,
+    filter: &mut String,
+    paused: &mut bool,
+    listening: bool,
+    export: Option<&Result<String, String>>,
+    on_export: impl FnOnce(),
+    on_clear: impl FnOnce(),
+) {
+    use egui_extras::{Column, TableBuilder};
+
+    if let Some(Some(tex)) =
+        ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("ping_background_texture")))
+    {
+        let painter = ui.painter();
+        let viewport_rect = ui.ctx().viewport_rect();
+        let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+        painter.image(
+            tex.id(),
+            viewport_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            tint,
+        );
+    }
+
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 6.0;
+        ui.vertical_centered(|ui| {
+            ui.label(
+                egui::RichText::new("Query Log")
+                    .color(egui::Color32::WHITE)
+                    .size(18.0),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(filter)
+                    .desired_width(220.0)
+                    .hint_text("Filter, e.g. example.com SERVFAIL"),
+            );
+            let pause_text = if *paused { "Resume" } else { "Pause" };
+            if ui
+                .add(
+                    egui::Button::new(egui::RichText::new(pause_text).color(BUTTON_TEXT))
+                        .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                        .corner_radius(6),
+                )
+                .on_hover_text("New queries wait while paused and appear on resume")
+                .clicked()
+            {
+                *paused = !*paused;
+            }
+            if ui
+                .add_enabled(
+                    !events.is_empty(),
+                    egui::Button::new(egui::RichText::new("Export").color(BUTTON_TEXT))
+                        .fill(BUTTON_SUCCESS)
+                        .corner_radius(6),
+                )
+                .on_hover_text("Save the rows shown to a JSONL file")
+                .clicked()
+            {
+                on_export();
+            }
+            if ui
+                .add(
+                    egui::Button::new(egui::RichText::new("Clear").color(BUTTON_TEXT))
+                        .fill(egui::Color32::from_rgba_unmultiplied(100, 100, 100, 100))
+                        .corner_radius(6),
+                )
+                .clicked()
+            {
+                on_clear();
+            }
+            let counts = if events.len() == total {
+                format!("{} queries", total)
+            } else {
+                format!("{} of {} queries", events.len(), total)
+            };
+            ui.label(egui::RichText::new(counts).color(egui::Color32::LIGHT_GRAY));
+        });
+
+        if !listening {
+            ui.label(
+                egui::RichText::new("Turn on the local forwarder to see queries").color(WARNING),
+            );
+        }
+        match export {
+            Some(Ok(path)) => {
+                ui.label(egui::RichText::new(format!("Saved to {}", path)).color(SUCCESS));
+            }
+            Some(Err(error)) => {
+                ui.label(egui::RichText::new(error).color(ERROR));
+            }
+            None => {}
+        }
+
+        let columns = [
+            "Time (UTC)",
+            "Client",
+            "Name",
+            "Type",
+            "Upstream",
+            "Rcode",
+            "Latency",
+            "Served",
+        ];
+        TableBuilder::new(ui)
+            .striped(true)
+            .stick_to_bottom(true)
+            .column(Column::auto().at_least(80.0))
+            .column(Column::auto().at_least(100.0))
+            .column(Column::remainder().at_least(160.0).clip(true))
+            .column(Column::auto().at_least(40.0))
+            .column(Column::auto().at_least(90.0).clip(true))
+            .columns(Column::auto().at_least(60.0), 3)
+            .header(20.0, |mut header| {
+                for label in columns {
+                    header.col(|ui| {
+                        ui.label(
+                            egui::RichText::new(label)
+                                .color(egui::Color32::WHITE)
+                                .strong(),
+                        );
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(18.0, events.len(), |mut row| {
+                    let event = events[row.index()];
+                    let color = match (event.source, event.rcode.as_str()) {
+                        (Source::Blocked, _) => egui::Color32::LIGHT_RED,
+                        (_, "NOERROR" | "NXDOMAIN") => egui::Color32::LIGHT_GRAY,
+                        _ => ERROR,
+                    };
+                    let cells = [
+                        event.utc_time(),
+                        event.client.clone(),
+                        event.name.clone(),
+                        event.qtype.clone(),
+                        event.upstream.clone().unwrap_or_default(),
+                        event.rcode.clone(),
+                        format!("{:.1} ms", event.latency_ms),
+                        event.source.label().to_string(),
+                    ];
+                    for text in cells {
+                        row.col(|ui| {
+                            ui.label(egui::RichText::new(text).color(color));
+                        });
+                    }
+                });
+            });
+    });
+}
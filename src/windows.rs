@@ -9,6 +9,9 @@ pub fn custom_window_frame(
     _title: &str,
     add_contents: impl FnOnce(&mut egui::Ui),
     on_ping_click: impl FnOnce(),
+    on_benchmark_click: impl FnOnce(),
+    on_compare_click: impl FnOnce(),
+    on_reachability_click: impl FnOnce(),
 ) {
     let panel_frame = egui::Frame::new()
         .fill(ctx.style().visuals.window_fill())
@@ -18,20 +21,17 @@ pub fn custom_window_frame(
     CentralPanel::default().frame(panel_frame).show(ctx, |ui| {
         let app_rect = ui.max_rect();
 
-        if let Some(texture) =
+        if let Some(Some(tex)) =
             ctx.data(|d| d.get_temp::<Option<TextureHandle>>(egui::Id::new("background_texture")))
         {
-            if let Some(ref tex) = texture {
-                let painter = ui.painter();
-                let tint =
-                    egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
-                painter.image(
-                    tex.id(),
-                    app_rect,
-                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                    tint,
-                );
-            }
+            let painter = ui.painter();
+            let tint = egui::Color32::from_rgba_unmultiplied(255, 255, 255, (255.0 * 0.3) as u8);
+            painter.image(
+                tex.id(),
+                app_rect,
+                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                tint,
+            );
         }
 
         let title_bar_height = TITLE_BAR_HEIGHT;
@@ -40,7 +40,15 @@ pub fn custom_window_frame(
             rect.max.y = rect.min.y + title_bar_height;
             rect
         };
-        title_bar_ui(ui, title_bar_rect, _title, on_ping_click);
+        title_bar_ui(
+            ui,
+            title_bar_rect,
+            _title,
+            on_ping_click,
+            on_benchmark_click,
+            on_compare_click,
+            on_reachability_click,
+        );
 
         let content_rect = {
             let mut rect = app_rect;
@@ -59,6 +67,9 @@ pub fn title_bar_ui(
     title_bar_rect: eframe::epaint::Rect,
     _title: &str,
     on_ping_click: impl FnOnce(),
+    on_benchmark_click: impl FnOnce(),
+    on_compare_click: impl FnOnce(),
+    on_reachability_click: impl FnOnce(),
 ) {
     let title_bar_response = ui.interact(
         title_bar_rect,
@@ -87,6 +98,39 @@ pub fn title_bar_ui(
                 on_ping_click();
             }
 
+            let benchmark_btn = ui
+                .add(egui::Button::new(
+                    egui::RichText::new("⏱").size(button_height),
+                ))
+                .on_hover_text("Benchmark DNS providers")
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+
+            if benchmark_btn.clicked() {
+                on_benchmark_click();
+            }
+
+            let compare_btn = ui
+                .add(egui::Button::new(
+                    egui::RichText::new("🔍").size(button_height),
+                ))
+                .on_hover_text("Compare answers across providers")
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+
+            if compare_btn.clicked() {
+                on_compare_click();
+            }
+
+            let reachability_btn = ui
+                .add(egui::Button::new(
+                    egui::RichText::new("🌐").size(button_height),
+                ))
+                .on_hover_text("Check which provider reaches which service")
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+
+            if reachability_btn.clicked() {
+                on_reachability_click();
+            }
+
             ui.add_space(4.0);
         },
     );